@@ -0,0 +1,216 @@
+// Copyright (C) 2019 Centrality Investments Limited
+// This file is part of CENNZnet.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//!
+//! Account state queries
+//!
+//! Collects an account's generic asset balances, nonce and index, so clients need not hash
+//! storage keys themselves. Generic asset balances cannot be enumerated per account, so the assets
+//! `Reaping` checks for dead accounts are read: all assets with issuance from the lowest tracked
+//! asset id up to the next asset id. The scan is bounded by `reaping::MAX_SCANNED_ASSETS` asset
+//! ids, beyond which only the staking, spending and tracked assets are read.
+//!
+use crate::{AccountId, AccountIndex, Address, AssetId, Balance, GenericAsset, Index, Indices, Reaping, System};
+use client::decl_runtime_apis;
+use parity_codec::{Codec, Decode, Encode};
+use rstd::prelude::*;
+use runtime_primitives::traits::{StaticLookup, Zero};
+#[cfg(feature = "std")]
+use serde::Serialize;
+
+/// The number of accounts in an `Indices` enum set
+const ENUM_SET_SIZE: AccountIndex = 64;
+
+/// The balances of an account in a generic asset
+#[derive(Encode, Decode, Clone, PartialEq)]
+#[cfg_attr(feature = "std", derive(Debug, Serialize))]
+#[cfg_attr(feature = "std", serde(rename_all = "camelCase"))]
+pub struct AssetBalance<AssetId, Balance> {
+	/// The asset
+	pub asset_id: AssetId,
+	/// The free balance
+	pub free: Balance,
+	/// The reserved balance
+	pub reserved: Balance,
+}
+
+/// The state of an account
+#[derive(Encode, Decode, Clone, PartialEq)]
+#[cfg_attr(feature = "std", derive(Debug, Serialize))]
+#[cfg_attr(feature = "std", serde(rename_all = "camelCase"))]
+pub struct AccountInfo<AccountId, AccountIndex, AssetId, Balance, Index> {
+	/// The account id
+	pub account_id: AccountId,
+	/// The account index, if one is assigned
+	pub index: Option<AccountIndex>,
+	/// The nonce of the next transaction
+	pub nonce: Index,
+	/// The balances of every asset the account holds, and always of the staking and spending assets
+	pub balances: Vec<AssetBalance<AssetId, Balance>>,
+}
+
+/// The balances of the account `who`, or `None` if `who` is an unknown index
+pub fn account_balances(who: Address) -> Option<Vec<AssetBalance<AssetId, Balance>>> {
+	Indices::lookup(who).ok().map(|who| balances(&who))
+}
+
+/// The state of the account `who`, or `None` if `who` is an unknown index
+pub fn account_info(who: Address) -> Option<AccountInfo<AccountId, AccountIndex, AssetId, Balance, Index>> {
+	let account_id = Indices::lookup(who).ok()?;
+	Some(AccountInfo {
+		index: index_of(&account_id),
+		nonce: System::account_nonce(&account_id),
+		balances: balances(&account_id),
+		account_id,
+	})
+}
+
+/// The non-zero balances of `who` among the assets `Reaping` scans, and its staking and spending
+/// asset balances
+fn balances(who: &AccountId) -> Vec<AssetBalance<AssetId, Balance>> {
+	let always = [GenericAsset::staking_asset_id(), GenericAsset::spending_asset_id()];
+	Reaping::held_assets()
+		.unwrap_or_else(Reaping::assets)
+		.into_iter()
+		.map(|asset_id| AssetBalance {
+			asset_id,
+			free: GenericAsset::free_balance(&asset_id, who),
+			reserved: GenericAsset::reserved_balance(&asset_id, who),
+		})
+		.filter(|b| always.contains(&b.asset_id) || !(b.free.is_zero() && b.reserved.is_zero()))
+		.collect()
+}
+
+/// The index assigned to `who`, if any
+fn index_of(who: &AccountId) -> Option<AccountIndex> {
+	(0..=Indices::next_enum_set()).find_map(|set| {
+		Indices::enum_set(set)
+			.iter()
+			.position(|account| account == who)
+			.map(|position| set * ENUM_SET_SIZE + position as AccountIndex)
+	})
+}
+
+decl_runtime_apis! {
+	/// The API to query account state
+	pub trait AccountStateApi<Address, AccountId, AccountIndex, AssetId, Balance, Index> where
+		Address: Codec,
+		AccountId: Codec,
+		AccountIndex: Codec,
+		AssetId: Codec,
+		Balance: Codec,
+		Index: Codec,
+	{
+		/// The balances of the account `who`, or `None` if `who` is an unknown index
+		fn account_balances(who: Address) -> Option<Vec<AssetBalance<AssetId, Balance>>>;
+
+		/// The balances, nonce and index of the account `who`, or `None` if `who` is an unknown index
+		fn account_info(who: Address) -> Option<AccountInfo<AccountId, AccountIndex, AssetId, Balance, Index>>;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::{reaping, Origin, Runtime};
+	use primitives::Blake2Hasher;
+	use runtime_io::with_externalities;
+	use runtime_primitives::BuildStorage;
+	use support::assert_ok;
+
+	const STAKING_ASSET: AssetId = 16000;
+	const SPENDING_ASSET: AssetId = 16001;
+	const OTHER_ASSET: AssetId = 16500;
+
+	fn alice() -> AccountId {
+		AccountId::from_raw([1; 32])
+	}
+
+	fn bob() -> AccountId {
+		AccountId::from_raw([2; 32])
+	}
+
+	// Alice holds every asset and has index 0
+	fn new_test_ext() -> runtime_io::TestExternalities<Blake2Hasher> {
+		let mut t = system::GenesisConfig::<Runtime>::default().build_storage().unwrap().0;
+		t.extend(
+			generic_asset::GenesisConfig::<Runtime> {
+				assets: vec![STAKING_ASSET, SPENDING_ASSET, OTHER_ASSET],
+				initial_balance: 100,
+				endowed_accounts: vec![alice()],
+				next_asset_id: 17000,
+				create_asset_stake: 0,
+				staking_asset_id: STAKING_ASSET,
+				spending_asset_id: SPENDING_ASSET,
+			}
+			.build_storage()
+			.unwrap()
+			.0,
+		);
+		t.extend(indices::GenesisConfig::<Runtime> { ids: vec![alice()] }.build_storage().unwrap().0);
+		t.extend(
+			reaping::GenesisConfig::<Runtime> {
+				tracked_assets: vec![],
+				dust_threshold: 0,
+				dust_receiver: Default::default(),
+			}
+			.build_storage()
+			.unwrap()
+			.0,
+		);
+		t.into()
+	}
+
+	fn balance(asset_id: AssetId, free: Balance) -> AssetBalance<AssetId, Balance> {
+		AssetBalance { asset_id, free, reserved: 0 }
+	}
+
+	#[test]
+	fn account_info_lists_every_held_asset() {
+		with_externalities(&mut new_test_ext(), || {
+			let info = account_info(indices::address::Address::Index(0)).unwrap();
+			assert_eq!(info.account_id, alice());
+			assert_eq!(info.index, Some(0));
+			assert_eq!(info.nonce, 0);
+			assert_eq!(
+				info.balances,
+				vec![balance(STAKING_ASSET, 100), balance(SPENDING_ASSET, 100), balance(OTHER_ASSET, 100)]
+			);
+		});
+	}
+
+	#[test]
+	fn empty_balances_are_omitted_except_staking_and_spending() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_ok!(GenericAsset::transfer(Origin::signed(alice()), OTHER_ASSET, bob(), 40));
+
+			let info = account_info(Indices::unlookup(bob())).unwrap();
+			assert_eq!(info.index, None);
+			assert_eq!(
+				info.balances,
+				vec![balance(STAKING_ASSET, 0), balance(SPENDING_ASSET, 0), balance(OTHER_ASSET, 40)]
+			);
+		});
+	}
+
+	#[test]
+	fn unknown_index_has_no_state() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_eq!(account_info(indices::address::Address::Index(1)), None);
+			assert_eq!(account_balances(indices::address::Address::Index(1)), None);
+		});
+	}
+}
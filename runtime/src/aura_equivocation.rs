@@ -0,0 +1,279 @@
+// Copyright (C) 2019 Centrality Investments Limited
+// This file is part of CENNZnet.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//!
+//! Aura equivocation reports
+//!
+//! An Aura authority equivocates when it seals two different headers for the same slot.
+//! Anyone may report an equivocation with both headers as proof. The seals are verified against
+//! the author of the slot in the current authority set, so reports must be made before the
+//! authority set changes. Each slot is punished at most once.
+//!
+use parity_codec::Encode;
+use rstd::prelude::*;
+use runtime_primitives::generic::{self, DigestItem};
+use runtime_primitives::traits::{Header as HeaderT, Member, Verify};
+use support::{decl_event, decl_module, decl_storage, dispatch::Result, ensure, Parameter, StorageMap};
+use system::ensure_signed;
+
+/// The module's configuration trait.
+pub trait Trait: system::Trait {
+	/// The overarching event type.
+	type Event: From<Event<Self>> + Into<<Self as system::Trait>::Event>;
+	/// The session key of an authority
+	type AuthorityId: Parameter + Member;
+	/// The signature of a block seal
+	type Signature: Parameter + Member + Verify<Signer = Self::AuthorityId>;
+	/// A block header, sealed by its author as the last digest item
+	type Header: Parameter + HeaderT<Hash = Self::Hash, Digest = generic::Digest<DigestItemOf<Self>>>;
+	/// Provides the current authority set
+	type Authorities: AuthoritySet<Self::AuthorityId>;
+	/// Punishes an equivocating authority
+	type OnEquivocation: OnEquivocation<Self::AuthorityId>;
+}
+
+/// Provides the current authority set
+pub trait AuthoritySet<AuthorityId> {
+	/// The current authorities, in slot order
+	fn authorities() -> Vec<AuthorityId>;
+}
+
+/// Punishes an equivocating authority
+pub trait OnEquivocation<AuthorityId> {
+	/// Punish the owner of the session key `authority`
+	fn on_equivocation(authority: &AuthorityId);
+}
+
+impl<AuthorityId> OnEquivocation<AuthorityId> for () {
+	fn on_equivocation(_: &AuthorityId) {}
+}
+
+type DigestItemOf<T> =
+	generic::DigestItem<<T as system::Trait>::Hash, <T as Trait>::AuthorityId, <T as Trait>::Signature>;
+
+decl_storage! {
+	trait Store for Module<T: Trait> as AuraEquivocation {
+		/// Slots for which an equivocation has been punished
+		pub ReportedSlots get(is_reported): map u64 => bool;
+	}
+}
+
+decl_module! {
+	pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+		fn deposit_event<T>() = default;
+
+		/// Report the author of the slot `first` and `second` were both sealed for
+		pub fn report_equivocation(origin, first: T::Header, second: T::Header) -> Result {
+			let reporter = ensure_signed(origin)?;
+			let (slot, first_hash, first_signature) = Self::unseal(first).ok_or("first header is not sealed")?;
+			let (second_slot, second_hash, second_signature) =
+				Self::unseal(second).ok_or("second header is not sealed")?;
+			ensure!(slot == second_slot, "headers are sealed for different slots");
+			ensure!(first_hash != second_hash, "headers are identical");
+			ensure!(!Self::is_reported(slot), "equivocation has already been reported for slot");
+
+			let authorities = T::Authorities::authorities();
+			ensure!(!authorities.is_empty(), "there are no authorities");
+			let author = &authorities[(slot % authorities.len() as u64) as usize];
+			ensure!(
+				Self::is_sealed_by(slot, first_hash, &first_signature, author)
+					&& Self::is_sealed_by(slot, second_hash, &second_signature, author),
+				"headers are not sealed by the slot author"
+			);
+
+			<ReportedSlots<T>>::insert(slot, true);
+			T::OnEquivocation::on_equivocation(author);
+			Self::deposit_event(RawEvent::Equivocation(author.clone(), slot, reporter));
+			Ok(())
+		}
+	}
+}
+
+decl_event!(
+	pub enum Event<T> where
+		AccountId = <T as system::Trait>::AccountId,
+		AuthorityId = <T as Trait>::AuthorityId
+	{
+		/// An authority was punished for equivocating: (authority, slot, reporter)
+		Equivocation(AuthorityId, u64, AccountId),
+	}
+);
+
+impl<T: Trait> Module<T> {
+	/// Remove the seal of `header`, returning its slot, the hash of the unsealed header and the
+	/// seal signature
+	fn unseal(mut header: T::Header) -> Option<(u64, T::Hash, T::Signature)> {
+		match header.digest_mut().logs.pop()? {
+			DigestItem::Seal(slot, signature) => Some((slot, header.hash(), signature)),
+			_ => None,
+		}
+	}
+
+	/// Whether `signature` seals the unsealed header `pre_hash` for `slot` by `author`
+	fn is_sealed_by(slot: u64, pre_hash: T::Hash, signature: &T::Signature, author: &T::AuthorityId) -> bool {
+		signature.verify(&(slot, pre_hash).encode()[..], author)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use primitives::{ed25519, Blake2Hasher, Pair, H256};
+	use runtime_io::with_externalities;
+	use runtime_primitives::{
+		testing::{Digest, DigestItem as TestDigestItem, Header as TestHeader},
+		traits::{BlakeTwo256, IdentityLookup},
+		BuildStorage,
+	};
+	use std::cell::RefCell;
+	use support::{assert_noop, assert_ok, impl_outer_origin};
+
+	impl_outer_origin! {
+		pub enum Origin for Test {}
+	}
+
+	type SealedHeader = generic::Header<u64, BlakeTwo256, generic::DigestItem<H256, ed25519::Public, ed25519::Signature>>;
+
+	thread_local! {
+		static PUNISHED: RefCell<Vec<ed25519::Public>> = RefCell::new(vec![]);
+	}
+
+	fn authority(index: u8) -> ed25519::Pair {
+		ed25519::Pair::from_seed(&[index; 32])
+	}
+
+	// Authorities 0, 1 and 2
+	pub struct Authorities;
+	impl AuthoritySet<ed25519::Public> for Authorities {
+		fn authorities() -> Vec<ed25519::Public> {
+			(0..3).map(|i| authority(i).public()).collect()
+		}
+	}
+
+	pub struct Punish;
+	impl OnEquivocation<ed25519::Public> for Punish {
+		fn on_equivocation(authority: &ed25519::Public) {
+			PUNISHED.with(|p| p.borrow_mut().push(authority.clone()));
+		}
+	}
+
+	#[derive(Clone, Eq, PartialEq)]
+	pub struct Test;
+	impl system::Trait for Test {
+		type Origin = Origin;
+		type Index = u64;
+		type BlockNumber = u64;
+		type Hash = H256;
+		type Hashing = BlakeTwo256;
+		type Digest = Digest;
+		type AccountId = u64;
+		type Lookup = IdentityLookup<Self::AccountId>;
+		type Header = TestHeader;
+		type Event = ();
+		type Log = TestDigestItem;
+	}
+	impl Trait for Test {
+		type Event = ();
+		type AuthorityId = ed25519::Public;
+		type Signature = ed25519::Signature;
+		type Header = SealedHeader;
+		type Authorities = Authorities;
+		type OnEquivocation = Punish;
+	}
+	type AuraEquivocation = Module<Test>;
+
+	fn new_test_ext() -> runtime_io::TestExternalities<Blake2Hasher> {
+		system::GenesisConfig::<Test>::default().build_storage().unwrap().0.into()
+	}
+
+	// A header at `number` sealed for `slot` by `author`, made distinct by `state_root`
+	fn sealed_header(number: u64, state_root: u8, slot: u64, author: &ed25519::Pair) -> SealedHeader {
+		let mut header = SealedHeader::new(
+			number,
+			Default::default(),
+			H256::repeat_byte(state_root),
+			Default::default(),
+			Default::default(),
+		);
+		let signature = author.sign(&(slot, header.hash()).encode()[..]);
+		header.digest_mut().logs.push(DigestItem::Seal(slot, signature));
+		header
+	}
+
+	#[test]
+	fn double_sealed_slot_punishes_author() {
+		with_externalities(&mut new_test_ext(), || {
+			// Slot 7 belongs to authority 1
+			let first = sealed_header(5, 1, 7, &authority(1));
+			let second = sealed_header(5, 2, 7, &authority(1));
+
+			assert_ok!(AuraEquivocation::report_equivocation(Origin::signed(10), first.clone(), second.clone()));
+			assert!(AuraEquivocation::is_reported(7));
+			PUNISHED.with(|p| assert_eq!(*p.borrow(), vec![authority(1).public()]));
+
+			assert_noop!(
+				AuraEquivocation::report_equivocation(Origin::signed(10), first, second),
+				"equivocation has already been reported for slot"
+			);
+		});
+	}
+
+	#[test]
+	fn forged_seal_is_rejected() {
+		with_externalities(&mut new_test_ext(), || {
+			// Authority 2 forges a second header for authority 1's slot
+			let first = sealed_header(5, 1, 7, &authority(1));
+			let forged = sealed_header(5, 2, 7, &authority(2));
+			assert_noop!(
+				AuraEquivocation::report_equivocation(Origin::signed(10), first, forged),
+				"headers are not sealed by the slot author"
+			);
+
+			// A seal copied onto another header does not verify
+			let mut copied = sealed_header(5, 2, 7, &authority(1));
+			let seal = sealed_header(5, 1, 7, &authority(1)).digest_mut().logs.pop().unwrap();
+			copied.digest_mut().logs.pop();
+			copied.digest_mut().logs.push(seal);
+			assert_noop!(
+				AuraEquivocation::report_equivocation(Origin::signed(10), sealed_header(5, 3, 7, &authority(1)), copied),
+				"headers are not sealed by the slot author"
+			);
+		});
+	}
+
+	#[test]
+	fn different_slots_or_identical_headers_are_rejected() {
+		with_externalities(&mut new_test_ext(), || {
+			let first = sealed_header(5, 1, 7, &authority(1));
+			assert_noop!(
+				AuraEquivocation::report_equivocation(Origin::signed(10), first.clone(), sealed_header(6, 2, 10, &authority(1))),
+				"headers are sealed for different slots"
+			);
+			assert_noop!(
+				AuraEquivocation::report_equivocation(Origin::signed(10), first.clone(), first.clone()),
+				"headers are identical"
+			);
+
+			let mut unsealed = first.clone();
+			unsealed.digest_mut().logs.pop();
+			assert_noop!(
+				AuraEquivocation::report_equivocation(Origin::signed(10), first, unsealed),
+				"second header is not sealed"
+			);
+			PUNISHED.with(|p| assert!(p.borrow().is_empty()));
+		});
+	}
+}
@@ -0,0 +1,197 @@
+// Copyright (C) 2019 Centrality Investments Limited
+// This file is part of CENNZnet.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//!
+//! Contract code allowlist
+//!
+//! Contract code must be approved by root before it can be uploaded or instantiated.
+//! Optionally, only accounts holding the deployer role may upload or instantiate code.
+//! The rules are enforced by the runtime's call filter in front of `Contract` calls, including
+//! those proxied, batched, proposed by a multisig account or scheduled. Contracts may not
+//! dispatch `Contract` calls themselves, and the code they instantiate with `ext_create` is
+//! held to the same rules, with the contract as the deployer.
+//!
+use rstd::prelude::*;
+use support::{decl_event, decl_module, decl_storage, dispatch::Result, ensure, StorageMap, StorageValue};
+use system::ensure_root;
+
+/// The module's configuration trait.
+pub trait Trait: system::Trait {
+	/// The overarching event type.
+	type Event: From<Event<Self>> + Into<<Self as system::Trait>::Event>;
+}
+
+decl_storage! {
+	trait Store for Module<T: Trait> as CodeAllowlist {
+		/// Code hashes approved for upload and instantiation
+		pub ApprovedCode get(is_approved_code): map T::Hash => bool;
+		/// Accounts holding the deployer role
+		pub Deployers get(is_deployer): map T::AccountId => bool;
+		/// Whether deployment is restricted to accounts holding the deployer role
+		pub DeployerRoleRequired get(deployer_role_required): bool;
+	}
+}
+
+decl_module! {
+	pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+		fn deposit_event<T>() = default;
+
+		/// Approve contract code with `code_hash`. Must be called by root.
+		pub fn approve_code(origin, code_hash: T::Hash) -> Result {
+			ensure_root(origin)?;
+			<ApprovedCode<T>>::insert(code_hash, true);
+			Self::deposit_event(RawEvent::CodeApproved(code_hash));
+			Ok(())
+		}
+
+		/// Revoke the approval of contract code with `code_hash`. Must be called by root.
+		/// Contracts already instantiated from it are unaffected.
+		pub fn revoke_code(origin, code_hash: T::Hash) -> Result {
+			ensure_root(origin)?;
+			ensure!(Self::is_approved_code(code_hash), "code is not approved");
+			<ApprovedCode<T>>::remove(code_hash);
+			Self::deposit_event(RawEvent::CodeRevoked(code_hash));
+			Ok(())
+		}
+
+		/// Grant or revoke the deployer role of `who`. Must be called by root.
+		pub fn set_deployer(origin, who: T::AccountId, is_deployer: bool) -> Result {
+			ensure_root(origin)?;
+			if is_deployer {
+				<Deployers<T>>::insert(&who, true);
+			} else {
+				<Deployers<T>>::remove(&who);
+			}
+			Self::deposit_event(RawEvent::DeployerSet(who, is_deployer));
+			Ok(())
+		}
+
+		/// Set whether deployment requires the deployer role. Must be called by root.
+		pub fn set_deployer_role_required(origin, required: bool) -> Result {
+			ensure_root(origin)?;
+			<DeployerRoleRequired<T>>::put(required);
+			Self::deposit_event(RawEvent::DeployerRoleRequiredSet(required));
+			Ok(())
+		}
+	}
+}
+
+decl_event!(
+	pub enum Event<T> where
+		AccountId = <T as system::Trait>::AccountId,
+		Hash = <T as system::Trait>::Hash
+	{
+		/// Contract code was approved: (code hash)
+		CodeApproved(Hash),
+		/// Contract code approval was revoked: (code hash)
+		CodeRevoked(Hash),
+		/// The deployer role of an account was granted or revoked: (account, is deployer)
+		DeployerSet(AccountId, bool),
+		/// Whether deployment requires the deployer role was set: (required)
+		DeployerRoleRequiredSet(bool),
+	}
+);
+
+impl<T: Trait> Module<T> {
+	/// Ensure `who` may upload or instantiate code with `code_hash`
+	pub fn ensure_can_deploy(who: &T::AccountId, code_hash: &T::Hash) -> Result {
+		ensure!(Self::is_approved_code(code_hash), "contract code is not approved");
+		ensure!(
+			!Self::deployer_role_required() || Self::is_deployer(who),
+			"account does not hold the deployer role"
+		);
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use primitives::{Blake2Hasher, H256};
+	use runtime_io::with_externalities;
+	use runtime_primitives::{
+		testing::{Digest, DigestItem, Header},
+		traits::{BlakeTwo256, IdentityLookup},
+		BuildStorage,
+	};
+	use support::{assert_noop, assert_ok, impl_outer_origin};
+
+	impl_outer_origin! {
+		pub enum Origin for Test {}
+	}
+
+	#[derive(Clone, Eq, PartialEq)]
+	pub struct Test;
+	impl system::Trait for Test {
+		type Origin = Origin;
+		type Index = u64;
+		type BlockNumber = u64;
+		type Hash = H256;
+		type Hashing = BlakeTwo256;
+		type Digest = Digest;
+		type AccountId = u64;
+		type Lookup = IdentityLookup<Self::AccountId>;
+		type Header = Header;
+		type Event = ();
+		type Log = DigestItem;
+	}
+	impl Trait for Test {
+		type Event = ();
+	}
+	type CodeAllowlist = Module<Test>;
+
+	fn new_test_ext() -> runtime_io::TestExternalities<Blake2Hasher> {
+		system::GenesisConfig::<Test>::default().build_storage().unwrap().0.into()
+	}
+
+	#[test]
+	fn only_approved_code_can_be_deployed() {
+		with_externalities(&mut new_test_ext(), || {
+			let code_hash = H256::repeat_byte(1);
+			assert_noop!(CodeAllowlist::ensure_can_deploy(&1, &code_hash), "contract code is not approved");
+			assert_noop!(
+				CodeAllowlist::approve_code(Origin::signed(1), code_hash),
+				"bad origin: expected to be a root origin"
+			);
+
+			assert_ok!(CodeAllowlist::approve_code(Origin::ROOT, code_hash));
+			assert_ok!(CodeAllowlist::ensure_can_deploy(&1, &code_hash));
+
+			assert_ok!(CodeAllowlist::revoke_code(Origin::ROOT, code_hash));
+			assert_noop!(CodeAllowlist::ensure_can_deploy(&1, &code_hash), "contract code is not approved");
+		});
+	}
+
+	#[test]
+	fn deployer_role_can_be_required() {
+		with_externalities(&mut new_test_ext(), || {
+			let code_hash = H256::repeat_byte(1);
+			assert_ok!(CodeAllowlist::approve_code(Origin::ROOT, code_hash));
+			assert_ok!(CodeAllowlist::set_deployer_role_required(Origin::ROOT, true));
+			assert_noop!(
+				CodeAllowlist::ensure_can_deploy(&1, &code_hash),
+				"account does not hold the deployer role"
+			);
+
+			assert_ok!(CodeAllowlist::set_deployer(Origin::ROOT, 1, true));
+			assert_ok!(CodeAllowlist::ensure_can_deploy(&1, &code_hash));
+			assert_noop!(
+				CodeAllowlist::ensure_can_deploy(&2, &code_hash),
+				"account does not hold the deployer role"
+			);
+		});
+	}
+}
@@ -0,0 +1,380 @@
+// Copyright (C) 2019 Centrality Investments Limited
+// This file is part of CENNZnet.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//!
+//! Dry-run of contract calls and instantiation
+//!
+//! Runtime API calls made by the node are executed against a block's state and their changes
+//! are discarded, so dispatching `Contract` calls from a runtime API reports their outcome
+//! without committing anything. Gas used is taken from the `ContractGas` event of the run.
+//! Calls are executed directly by `srml-contract` rather than dispatched, so the data a
+//! contract returns, or the buffer it left when failing, is reported too.
+//!
+//! A dry-run is subject to the same call filter as a submitted extrinsic, so code which may not
+//! be deployed is reported as failing rather than simulated.
+//!
+use crate::dispatch::DispatchAs;
+use crate::{contract_gas, AccountId, Balance, Call, Contract, ContractGas, Event, Hash, Runtime, System};
+use client::decl_runtime_apis;
+use parity_codec::{Codec, Decode, Encode};
+use rstd::prelude::*;
+
+/// The outcome of a dry-run contract call or instantiation
+#[derive(Encode, Decode, Clone, PartialEq)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct ContractExecResult<AccountId, Event> {
+	/// The error message if execution failed
+	pub error: Option<Vec<u8>>,
+	/// The data returned by the contract
+	pub output: Vec<u8>,
+	/// The scratch buffer left by a failed execution, e.g. a reason the contract wrote
+	pub debug_buffer: Vec<u8>,
+	/// The address of the contract, if one was instantiated
+	pub address: Option<AccountId>,
+	/// The gas consumed by execution
+	pub gas_used: u64,
+	/// The events emitted during execution
+	pub events: Vec<Event>,
+}
+
+/// Call contract `dest` without committing the result
+pub fn call(
+	origin: AccountId,
+	dest: AccountId,
+	value: Balance,
+	gas_limit: u64,
+	input_data: Vec<u8>,
+) -> ContractExecResult<AccountId, Event> {
+	let contract_call = contract::Call::call(dest.clone().into(), value, gas_limit, input_data.clone());
+	let call = Call::Contract(contract_call.clone());
+	if let Err(e) = Runtime::filter_call(&origin, &call) {
+		return filtered(e);
+	}
+
+	collect(|| {
+		ContractGas::note_gas_payer(&origin, &contract_call);
+		let result = Contract::bare_call(origin.clone(), dest, value, gas_limit, input_data);
+		ContractGas::clear_gas_payer();
+		match result {
+			Ok(value) => (Ok(()), value.data, Vec::new()),
+			Err(e) => (Err(e.reason), Vec::new(), e.buffer),
+		}
+	})
+}
+
+/// Instantiate the contract code with `code_hash` without committing the result
+pub fn instantiate(
+	origin: AccountId,
+	endowment: Balance,
+	gas_limit: u64,
+	code_hash: Hash,
+	data: Vec<u8>,
+) -> ContractExecResult<AccountId, Event> {
+	dry_run(origin, Call::Contract(contract::Call::create(endowment, gas_limit, code_hash, data)))
+}
+
+/// Dispatch `call` as `origin` and collect its result and the events it deposited
+fn dry_run(origin: AccountId, call: Call) -> ContractExecResult<AccountId, Event> {
+	if let Err(e) = Runtime::filter_call(&origin, &call) {
+		return filtered(e);
+	}
+
+	collect(|| (Runtime::dispatch_as(origin, call), Vec::new(), Vec::new()))
+}
+
+/// The result of a call rejected by the call filter with error `e`
+fn filtered(e: &'static str) -> ContractExecResult<AccountId, Event> {
+	ContractExecResult {
+		error: Some(e.as_bytes().to_vec()),
+		output: Vec::new(),
+		debug_buffer: Vec::new(),
+		address: None,
+		gas_used: 0,
+		events: Vec::new(),
+	}
+}
+
+/// Run `execute`, returning its result, output and debug buffer, and collect the events it deposited
+fn collect<F>(execute: F) -> ContractExecResult<AccountId, Event>
+where
+	F: FnOnce() -> (support::dispatch::Result, Vec<u8>, Vec<u8>),
+{
+	let events_before = System::events().len();
+	let (result, output, debug_buffer) = execute();
+	let events: Vec<Event> = System::events()
+		.into_iter()
+		.skip(events_before)
+		.map(|record| record.event)
+		.collect();
+
+	let gas_used = events
+		.iter()
+		.find_map(|event| match event {
+			Event::contract_gas(contract_gas::RawEvent::GasPaid(_, _, gas)) => Some(*gas),
+			_ => None,
+		})
+		.unwrap_or(0);
+	let address = events.iter().find_map(|event| match event {
+		Event::contract(contract::RawEvent::Instantiated(_, address)) => Some(address.clone()),
+		_ => None,
+	});
+
+	ContractExecResult {
+		error: result.err().map(|e| e.as_bytes().to_vec()),
+		output,
+		debug_buffer,
+		address,
+		gas_used,
+		events,
+	}
+}
+
+decl_runtime_apis! {
+	/// The API to dry-run contract calls and instantiation
+	pub trait ContractsApi<AccountId, Balance, Hash, Event> where
+		AccountId: Codec,
+		Balance: Codec,
+		Hash: Codec,
+		Event: Codec,
+	{
+		/// Call contract `dest` with `input_data` as `origin` without committing the result
+		fn call(
+			origin: AccountId,
+			dest: AccountId,
+			value: Balance,
+			gas_limit: u64,
+			input_data: Vec<u8>,
+		) -> ContractExecResult<AccountId, Event>;
+
+		/// Instantiate the code with `code_hash` as `origin` without committing the result
+		fn instantiate(
+			origin: AccountId,
+			endowment: Balance,
+			gas_limit: u64,
+			code_hash: Hash,
+			data: Vec<u8>,
+		) -> ContractExecResult<AccountId, Event>;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::{CodeAllowlist, GenericAsset, Origin, Schedule};
+	use primitives::Blake2Hasher;
+	use runtime_io::with_externalities;
+	use runtime_primitives::{
+		traits::{BlakeTwo256, Hash as HashT},
+		BuildStorage,
+	};
+	use support::assert_ok;
+
+	const STAKING_ASSET: u32 = 16000;
+	const SPENDING_ASSET: u32 = 16001;
+	const INITIAL_BALANCE: Balance = 10_000_000_000;
+
+	fn alice() -> AccountId {
+		AccountId::from_raw([1; 32])
+	}
+
+	fn bob() -> AccountId {
+		AccountId::from_raw([2; 32])
+	}
+
+	fn new_test_ext() -> runtime_io::TestExternalities<Blake2Hasher> {
+		new_test_ext_with_gas_asset(SPENDING_ASSET)
+	}
+
+	// Alice holds both assets, gas costs 1 each of `gas_asset_id`
+	fn new_test_ext_with_gas_asset(gas_asset_id: u32) -> runtime_io::TestExternalities<Blake2Hasher> {
+		let mut t = system::GenesisConfig::<Runtime>::default().build_storage().unwrap().0;
+		t.extend(
+			generic_asset::GenesisConfig::<Runtime> {
+				assets: vec![STAKING_ASSET, SPENDING_ASSET],
+				initial_balance: INITIAL_BALANCE,
+				endowed_accounts: vec![alice()],
+				next_asset_id: 17000,
+				create_asset_stake: 0,
+				staking_asset_id: STAKING_ASSET,
+				spending_asset_id: SPENDING_ASSET,
+			}
+			.build_storage()
+			.unwrap()
+			.0,
+		);
+		t.extend(
+			contract::GenesisConfig::<Runtime> {
+				signed_claim_handicap: 2,
+				rent_byte_price: 1,
+				rent_deposit_offset: 1000,
+				storage_size_offset: 8,
+				surcharge_reward: 150,
+				tombstone_deposit: 16,
+				contract_fee: 1,
+				call_base_fee: 1,
+				create_base_fee: 1,
+				creation_fee: 0,
+				transaction_base_fee: 1,
+				transaction_byte_fee: 0,
+				transfer_fee: 1,
+				gas_price: 1,
+				max_depth: 1024,
+				block_gas_limit: 10_000_000,
+				current_schedule: Schedule::default(),
+			}
+			.build_storage()
+			.unwrap()
+			.0,
+		);
+		t.extend(contract_gas::GenesisConfig::<Runtime> { gas_asset_id }.build_storage().unwrap().0);
+		t.into()
+	}
+
+	const CHILD_CODE: &str = r#"
+(module
+	(import "env" "memory" (memory 1 1))
+	(func (export "deploy"))
+	(func (export "call"))
+)
+"#;
+
+	// Instantiates the code with `code_hash` when called, trapping if instantiation fails
+	fn creator_code(code_hash: &Hash) -> Vec<u8> {
+		let code_hash: String = code_hash.as_bytes().iter().map(|byte| format!("\\{:02x}", byte)).collect();
+		wabt::wat2wasm(format!(
+			r#"
+(module
+	(import "env" "ext_create" (func $ext_create (param i32 i32 i64 i32 i32 i32 i32) (result i32)))
+	(import "env" "memory" (memory 1 1))
+	(func (export "deploy"))
+	(func (export "call")
+		(if (call $ext_create
+				(i32.const 16) (i32.const 32) ;; code hash
+				(i64.const 50000) ;; gas
+				(i32.const 0) (i32.const 16) ;; zero endowment
+				(i32.const 0) (i32.const 0)) ;; no input
+			(then unreachable)
+		)
+	)
+	(data (i32.const 16) "{}")
+)
+"#,
+			code_hash
+		))
+		.unwrap()
+	}
+
+	fn instantiated(result: &ContractExecResult<AccountId, Event>) -> bool {
+		result.events.iter().any(|event| match event {
+			Event::contract(contract::RawEvent::Instantiated(..)) => true,
+			_ => false,
+		})
+	}
+
+	#[test]
+	fn call_reports_output_gas_and_events() {
+		with_externalities(&mut new_test_ext(), || {
+			// An account without code returns no output
+			let result = call(alice(), bob(), 0, 100_000, vec![1, 2, 3]);
+			assert_eq!(result.error, None);
+			assert_eq!(result.output, Vec::<u8>::new());
+			assert_eq!(result.debug_buffer, Vec::<u8>::new());
+			assert!(result.gas_used > 0);
+			assert!(result.events.iter().any(|event| match event {
+				Event::contract_gas(contract_gas::RawEvent::GasPaid(Some(payer), _, _)) => *payer == alice(),
+				_ => false,
+			}));
+		});
+	}
+
+	#[test]
+	fn gas_left_is_refunded() {
+		with_externalities(&mut new_test_ext(), || {
+			let result = call(alice(), bob(), 0, 100_000, vec![]);
+			let gas_used = Balance::from(result.gas_used);
+			let refunded = result.events.iter().find_map(|event| match event {
+				Event::contract_gas(contract_gas::RawEvent::GasRefunded(payer, amount)) if *payer == alice() => {
+					Some(*amount)
+				}
+				_ => None,
+			});
+			assert!(result
+				.events
+				.contains(&Event::contract_gas(contract_gas::RawEvent::GasBought(alice(), 100_000))));
+			assert_eq!(refunded, Some(100_000 - gas_used));
+
+			// Only the gas used is paid for
+			assert_eq!(GenericAsset::free_balance(&SPENDING_ASSET, &alice()), INITIAL_BALANCE - gas_used);
+		});
+	}
+
+	#[test]
+	fn gas_is_bought_with_the_gas_asset() {
+		with_externalities(&mut new_test_ext_with_gas_asset(STAKING_ASSET), || {
+			let result = call(alice(), bob(), 0, 100_000, vec![]);
+			assert_eq!(result.error, None);
+			let gas_used = Balance::from(result.gas_used);
+			assert_eq!(GenericAsset::free_balance(&STAKING_ASSET, &alice()), INITIAL_BALANCE - gas_used);
+			assert_eq!(GenericAsset::free_balance(&SPENDING_ASSET, &alice()), INITIAL_BALANCE);
+		});
+	}
+
+	#[test]
+	fn failed_call_reports_its_error() {
+		with_externalities(&mut new_test_ext(), || {
+			// Bob cannot pay for the gas
+			let result = call(bob(), alice(), 0, 100_000, vec![]);
+			assert!(result.error.is_some());
+			assert_eq!(result.output, Vec::<u8>::new());
+			assert_eq!(result.gas_used, 0);
+		});
+	}
+
+	#[test]
+	fn filtered_instantiation_is_not_run() {
+		with_externalities(&mut new_test_ext(), || {
+			let result = instantiate(alice(), 0, 100_000, Default::default(), vec![]);
+			assert_eq!(result.error, Some(b"contract code is not approved".to_vec()));
+			assert_eq!((result.output, result.debug_buffer, result.events), (vec![], vec![], vec![]));
+		});
+	}
+
+	#[test]
+	fn contracts_cannot_instantiate_unapproved_code() {
+		with_externalities(&mut new_test_ext(), || {
+			let child = wabt::wat2wasm(CHILD_CODE).unwrap();
+			let child_hash = BlakeTwo256::hash(&child);
+			let creator = creator_code(&child_hash);
+			let creator_hash = BlakeTwo256::hash(&creator);
+			assert_ok!(CodeAllowlist::approve_code(Origin::ROOT, child_hash));
+			assert_ok!(CodeAllowlist::approve_code(Origin::ROOT, creator_hash));
+			assert_ok!(Runtime::dispatch_as(alice(), Call::Contract(contract::Call::put_code(500_000, child))));
+			assert_ok!(Runtime::dispatch_as(alice(), Call::Contract(contract::Call::put_code(500_000, creator))));
+			let address = instantiate(alice(), 100_000, 500_000, creator_hash, vec![]).address.unwrap();
+
+			// The child code stays uploaded once its approval is revoked
+			assert_ok!(CodeAllowlist::revoke_code(Origin::ROOT, child_hash));
+			let result = call(alice(), address.clone(), 0, 500_000, vec![]);
+			assert!(result.error.is_some());
+			assert!(!instantiated(&result));
+
+			assert_ok!(CodeAllowlist::approve_code(Origin::ROOT, child_hash));
+			let result = call(alice(), address, 0, 500_000, vec![]);
+			assert_eq!(result.error, None);
+			assert!(instantiated(&result));
+		});
+	}
+}
@@ -0,0 +1,148 @@
+// Copyright (C) 2019 Centrality Investments Limited
+// This file is part of CENNZnet.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//!
+//! Contract gas payment
+//!
+//! `srml-contract` buys gas up front, refunds what is left after execution and hands the
+//! imbalance of the gas actually spent to its `GasPayment` handler. This module is that handler:
+//! it reports the spent amount to the `fees` module's `OnFeeCharged` hook and emits events with
+//! the amounts bought, paid and refunded and the gas used.
+//!
+//! The account paying for gas and the gas limit are not passed to `GasPayment`, so they are
+//! noted via `note_gas_payer` before each `Contract` call is dispatched: by the runtime's
+//! extrinsic fee charger for extrinsics, and by the runtime's `DispatchAs` for calls dispatched
+//! by proxies, batches, multisig accounts and scheduled tasks.
+//!
+//! Gas is bought with the `GasAssetId` asset. `GasCurrency` is that asset's currency, to be used
+//! as `srml-contract`'s currency, so contract balances are held in the gas asset too.
+//!
+use fees::OnFeeCharged;
+use generic_asset::{AssetCurrency, AssetIdProvider};
+use rstd::{marker::PhantomData, prelude::*};
+use runtime_primitives::traits::{As, Saturating, Zero};
+use support::{
+	decl_event, decl_module, decl_storage,
+	traits::{Currency, Imbalance, OnUnbalanced},
+	StorageValue,
+};
+
+/// The module's configuration trait.
+pub trait Trait: contract::Trait + fees::Trait + generic_asset::Trait {
+	/// The overarching event type.
+	type Event: From<Event<Self>> + Into<<Self as system::Trait>::Event>;
+}
+
+/// Provides the asset ID of the gas asset
+pub struct GasAssetIdProvider<T>(PhantomData<T>);
+
+impl<T: Trait> AssetIdProvider for GasAssetIdProvider<T> {
+	type AssetId = <T as generic_asset::Trait>::AssetId;
+
+	fn asset_id() -> Self::AssetId {
+		<Module<T>>::gas_asset_id()
+	}
+}
+
+/// The currency of the gas asset
+pub type GasCurrency<T> = AssetCurrency<T, GasAssetIdProvider<T>>;
+
+type BalanceOf<T> = <<T as contract::Trait>::Currency as Currency<<T as system::Trait>::AccountId>>::Balance;
+type NegativeImbalanceOf<T> =
+	<<T as contract::Trait>::Currency as Currency<<T as system::Trait>::AccountId>>::NegativeImbalance;
+
+decl_storage! {
+	trait Store for Module<T: Trait> as ContractGas {
+		/// The account paying for gas of the contract call being dispatched
+		GasPayer get(gas_payer): Option<T::AccountId>;
+		/// The gas limit of the contract call being dispatched
+		GasLimit get(gas_limit): Option<<T as contract::Trait>::Gas>;
+		/// The asset gas is bought with
+		pub GasAssetId get(gas_asset_id) config(): <T as generic_asset::Trait>::AssetId;
+	}
+}
+
+decl_module! {
+	pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+		fn deposit_event<T>() = default;
+
+		fn on_finalize(_n: T::BlockNumber) {
+			Self::clear_gas_payer();
+		}
+	}
+}
+
+decl_event!(
+	pub enum Event<T> where
+		AccountId = <T as system::Trait>::AccountId,
+		Balance = BalanceOf<T>,
+		Gas = <T as contract::Trait>::Gas
+	{
+		/// Gas was bought up front for a contract call: (payer, amount)
+		GasBought(AccountId, Balance),
+		/// Gas was paid for a contract call: (payer, amount paid, gas used)
+		GasPaid(Option<AccountId>, Balance, Gas),
+		/// Gas left after a contract call was refunded: (payer, amount)
+		GasRefunded(AccountId, Balance),
+	}
+);
+
+impl<T: Trait> Module<T> {
+	/// Note `who` as the payer for the gas of `call`, about to be dispatched
+	pub fn note_gas_payer(who: &T::AccountId, call: &contract::Call<T>) {
+		<GasPayer<T>>::put(who.clone());
+		match call {
+			contract::Call::call(_, _, gas_limit, _)
+			| contract::Call::create(_, gas_limit, _, _)
+			| contract::Call::put_code(gas_limit, _) => <GasLimit<T>>::put(*gas_limit),
+			_ => <GasLimit<T>>::kill(),
+		}
+	}
+
+	/// Forget the gas payer once its contract call was dispatched
+	pub fn clear_gas_payer() {
+		<GasPayer<T>>::kill();
+		<GasLimit<T>>::kill();
+	}
+}
+
+impl<T: Trait> OnUnbalanced<NegativeImbalanceOf<T>> for Module<T>
+where
+	T::OnFeeCharged: OnFeeCharged<BalanceOf<T>>,
+{
+	fn on_unbalanced(imbalance: NegativeImbalanceOf<T>) {
+		let amount = imbalance.peek();
+		let payer = <GasPayer<T>>::take();
+		let gas_price = <contract::Module<T>>::gas_price();
+		if let (Some(payer), Some(gas_limit)) = (&payer, <GasLimit<T>>::take()) {
+			// Gas is bought at the current price and what is left refunded at the same price
+			let bought = gas_price.saturating_mul(As::sa(As::<u64>::as_(gas_limit)));
+			Self::deposit_event(RawEvent::GasBought(payer.clone(), bought));
+			Self::deposit_event(RawEvent::GasRefunded(payer.clone(), bought.saturating_sub(amount)));
+		}
+		if amount.is_zero() {
+			return;
+		}
+
+		let gas_used = if gas_price.is_zero() {
+			Zero::zero()
+		} else {
+			<<T as contract::Trait>::Gas as As<u64>>::sa(As::<u64>::as_(amount / gas_price))
+		};
+
+		T::OnFeeCharged::on_fee_charged(&amount);
+		Self::deposit_event(RawEvent::GasPaid(payer, amount, gas_used));
+	}
+}
@@ -0,0 +1,158 @@
+// Copyright (C) 2019 Centrality Investments Limited
+// This file is part of CENNZnet.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//!
+//! Dispatch of calls on behalf of an account
+//!
+//! Modules dispatching a call with a signed origin of their choosing (a proxy's principal, a
+//! batch's caller, a multisig account or a scheduled task's owner) do so through `DispatchAs`,
+//! so the runtime can keep the state a call relies on in step with its origin. Contract calls
+//! note the account buying their gas, as `srml-contract` does not pass it to `GasPayment`.
+//!
+use crate::{AccountId, Call, ContractGas, Runtime};
+use runtime_primitives::traits::Dispatchable;
+use support::dispatch::Result;
+
+/// Dispatches a call with the signed origin of an account
+pub trait DispatchAs<AccountId, Call> {
+	/// Dispatch `call` with `who` as its signed origin
+	fn dispatch_as(who: AccountId, call: Call) -> Result;
+}
+
+/// Dispatches without any bookkeeping
+impl<AccountId, Call: Dispatchable> DispatchAs<AccountId, Call> for ()
+where
+	Call::Origin: From<system::RawOrigin<AccountId>>,
+{
+	fn dispatch_as(who: AccountId, call: Call) -> Result {
+		call.dispatch(system::RawOrigin::Signed(who).into())
+	}
+}
+
+impl DispatchAs<AccountId, Call> for Runtime {
+	fn dispatch_as(who: AccountId, call: Call) -> Result {
+		let is_contract_call = match call {
+			Call::Contract(ref call) => {
+				ContractGas::note_gas_payer(&who, call);
+				true
+			}
+			_ => false,
+		};
+		let result = call.dispatch(system::RawOrigin::Signed(who).into());
+		if is_contract_call {
+			ContractGas::clear_gas_payer();
+		}
+		result
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::{contract_gas, Event, Indices, Schedule, System};
+	use primitives::Blake2Hasher;
+	use runtime_io::with_externalities;
+	use runtime_primitives::{traits::StaticLookup, BuildStorage};
+
+	const SPENDING_ASSET: u32 = 16001;
+
+	fn alice() -> AccountId {
+		AccountId::from_raw([1; 32])
+	}
+
+	fn bob() -> AccountId {
+		AccountId::from_raw([2; 32])
+	}
+
+	// Alice and Bob hold the spending asset, gas costs 1 each
+	fn new_test_ext() -> runtime_io::TestExternalities<Blake2Hasher> {
+		let mut t = system::GenesisConfig::<Runtime>::default().build_storage().unwrap().0;
+		t.extend(
+			generic_asset::GenesisConfig::<Runtime> {
+				assets: vec![16000, SPENDING_ASSET],
+				initial_balance: 10u128.pow(10),
+				endowed_accounts: vec![alice(), bob()],
+				next_asset_id: 17000,
+				create_asset_stake: 0,
+				staking_asset_id: 16000,
+				spending_asset_id: SPENDING_ASSET,
+			}
+			.build_storage()
+			.unwrap()
+			.0,
+		);
+		t.extend(
+			contract::GenesisConfig::<Runtime> {
+				signed_claim_handicap: 2,
+				rent_byte_price: 1,
+				rent_deposit_offset: 1000,
+				storage_size_offset: 8,
+				surcharge_reward: 150,
+				tombstone_deposit: 16,
+				contract_fee: 1,
+				call_base_fee: 1,
+				create_base_fee: 1,
+				creation_fee: 0,
+				transaction_base_fee: 1,
+				transaction_byte_fee: 0,
+				transfer_fee: 1,
+				gas_price: 1,
+				max_depth: 1024,
+				block_gas_limit: 10_000_000,
+				current_schedule: Schedule::default(),
+			}
+			.build_storage()
+			.unwrap()
+			.0,
+		);
+		t.extend(contract_gas::GenesisConfig::<Runtime> { gas_asset_id: SPENDING_ASSET }.build_storage().unwrap().0);
+		t.into()
+	}
+
+	fn contract_call() -> Call {
+		Call::Contract(contract::Call::call(<Indices as StaticLookup>::unlookup(bob()), 0, 100_000, vec![]))
+	}
+
+	fn gas_payers() -> Vec<Option<AccountId>> {
+		System::events()
+			.into_iter()
+			.filter_map(|record| match record.event {
+				Event::contract_gas(contract_gas::RawEvent::GasPaid(payer, _, _)) => Some(payer),
+				_ => None,
+			})
+			.collect()
+	}
+
+	#[test]
+	fn contract_call_is_charged_to_its_origin() {
+		with_externalities(&mut new_test_ext(), || {
+			assert!(Runtime::dispatch_as(alice(), contract_call()).is_ok());
+			assert_eq!(gas_payers(), vec![Some(alice())]);
+			assert_eq!(ContractGas::gas_payer(), None);
+		});
+	}
+
+	#[test]
+	fn stale_gas_payer_is_replaced() {
+		with_externalities(&mut new_test_ext(), || {
+			// A payer noted for an extrinsic which never bought gas
+			ContractGas::note_gas_payer(&bob(), &contract::Call::put_code(100_000, vec![]));
+			assert!(Runtime::dispatch_as(alice(), contract_call()).is_ok());
+			assert!(Runtime::dispatch_as(bob(), contract_call()).is_ok());
+			assert_eq!(gas_payers(), vec![Some(alice()), Some(bob())]);
+		});
+	}
+}
@@ -16,9 +16,11 @@
 //!
 //! Runtime extrinsic fee logic
 //!
-use crate::{AccountId, Call, CheckedExtrinsic, Fee, Fees, Runtime};
+use crate::{
+	proxy, scheduler, utility, AccountId, Call, CheckedExtrinsic, ContractGas, Fee, Fees, Runtime, Sponsorship,
+};
 use fees::{AssetOf, CheckCallFee};
-use runtime_primitives::traits::{Applyable, As, Zero};
+use runtime_primitives::traits::{Applyable, As, Saturating, Zero};
 use support::{
 	additional_traits::{ChargeExtrinsicFee, ChargeFee},
 	dispatch::Result,
@@ -30,7 +32,11 @@ pub struct ExtrinsicFeeCharger;
 impl ChargeExtrinsicFee<AccountId, CheckedExtrinsic> for ExtrinsicFeeCharger {
 	/// Calculate and charge a fee to `transactor` for the given `extrinsic`
 	/// The fee is calculated as: 'base fee +e (byte fee * encoded length)'
+	/// It is paid by the transactor's sponsor instead, when one will cover it and can pay
+	/// Extrinsics rejected by the runtime call filter are not charged and fail
 	fn charge_extrinsic_fee(transactor: &AccountId, encoded_len: usize, extrinsic: &CheckedExtrinsic) -> Result {
+		Runtime::filter_call(transactor, extrinsic.call())?;
+
 		let bytes_fee = Fees::fee_registry(Fee::fees(fees::Fee::Bytes))
 			.checked_mul(As::sa(encoded_len))
 			.ok_or_else(|| "extrinsic fee overflow (bytes)")?;
@@ -43,7 +49,23 @@ impl ChargeExtrinsicFee<AccountId, CheckedExtrinsic> for ExtrinsicFeeCharger {
 			.checked_add(call_fee)
 			.ok_or_else(|| "extrinsic fee overflow (base + bytes + call)")?;
 
-		Fees::charge_fee(transactor, total_fee)
+		if let Call::Contract(call) = extrinsic.call() {
+			ContractGas::note_gas_payer(transactor, call);
+		}
+
+		// Scheduled call fees paid up front are refunded to the task owner, so are never sponsored
+		let sponsor = match extrinsic.call() {
+			Call::Scheduler(_) => None,
+			call => Sponsorship::sponsor_for(transactor, call, total_fee),
+		};
+		match sponsor {
+			// A sponsor unable to pay leaves the fee to the transactor
+			Some(ref sponsor) if Fees::charge_fee(sponsor, total_fee).is_ok() => {
+				Sponsorship::note_fee_sponsored(transactor, total_fee);
+				Ok(())
+			}
+			_ => Fees::charge_fee(transactor, total_fee),
+		}
 	}
 }
 
@@ -60,6 +82,17 @@ impl CheckCallFee<AssetOf<Self>, Call> for Runtime {
 				}
 				_ => Zero::zero(),
 			},
+			// The proxy pays for the call it dispatches on behalf of the principal
+			Call::Proxy(proxy::Call::<Self>::proxy(_, call)) => Self::check_call_fee(call),
+			// A batch costs the sum of its calls
+			Call::Utility(utility::Call::<Self>::batch_until_failure(calls)) => calls
+				.iter()
+				.fold(Zero::zero(), |total: AssetOf<Self>, call| total.saturating_add(Self::check_call_fee(call))),
+			// A scheduled call is paid for up front, once per dispatch
+			Call::Scheduler(scheduler::Call::<Self>::schedule(_, periodic, call)) => {
+				let dispatches = periodic.map(|(_, count)| count).unwrap_or(1);
+				Self::check_call_fee(call).saturating_mul(As::sa(dispatches as u64))
+			}
 			_ => Zero::zero(),
 		}
 	}
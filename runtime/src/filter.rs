@@ -0,0 +1,199 @@
+// Copyright (C) 2019 Centrality Investments Limited
+// This file is part of CENNZnet.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//!
+//! Runtime call filter
+//!
+//! Checked for every signed extrinsic before its fee is charged. A call which is rejected here
+//! makes the extrinsic invalid, so it never enters the transaction pool or a block.
+//! Calls wrapped by `Proxy`, `Utility` and `Multisig` are checked with the origin they will be
+//! dispatched with.
+//!
+//! Modules dispatching calls later than their submission are given the filter as a
+//! `CallFilter` so they can check the call again when it is dispatched.
+//!
+//! Contracts dispatch calls with `ext_dispatch_call`, which cannot be rejected. Instead
+//! `ContractDispatchFee` prices the calls contracts may not dispatch beyond any gas limit, so a
+//! contract attempting one traps. These are `Contract` calls, which would bypass the code
+//! allowlist, and calls wrapping other calls.
+//!
+//! Contracts instantiate code with `ext_create` rather than a call, so the code allowlist is
+//! also enforced by `AllowlistAddressDeterminator` when the new contract's address is determined.
+//!
+use crate::{multisig, proxy, scheduler, utility, AccountId, Balance, CodeAllowlist, Call, Hash, Runtime};
+use contract::{ComputeDispatchFee, ContractAddressFor, DefaultDispatchFeeComputor, SimpleAddressDeterminator};
+use runtime_primitives::traits::{BlakeTwo256, Bounded, Hash};
+use support::dispatch::Result;
+
+/// Checks whether an account may dispatch a call
+pub trait CallFilter<AccountId, Call> {
+	/// Check whether `who` may dispatch `call`
+	fn filter(who: &AccountId, call: &Call) -> Result;
+}
+
+/// Allows every call
+impl<AccountId, Call> CallFilter<AccountId, Call> for () {
+	fn filter(_: &AccountId, _: &Call) -> Result {
+		Ok(())
+	}
+}
+
+impl CallFilter<AccountId, Call> for Runtime {
+	fn filter(who: &AccountId, call: &Call) -> Result {
+		Self::filter_call(who, call)
+	}
+}
+
+impl Runtime {
+	/// Check whether `who` may submit `call`
+	pub fn filter_call(who: &AccountId, call: &Call) -> Result {
+		match call {
+			Call::Contract(method) => match method {
+				contract::Call::<Self>::put_code(_, code) => {
+					CodeAllowlist::ensure_can_deploy(who, &BlakeTwo256::hash(code))
+				}
+				contract::Call::<Self>::create(_, _, code_hash, _) => CodeAllowlist::ensure_can_deploy(who, code_hash),
+				_ => Ok(()),
+			},
+			Call::Proxy(proxy::Call::<Self>::proxy(principal, call)) => Self::filter_call(principal, call),
+			Call::Utility(utility::Call::<Self>::batch_until_failure(calls)) => {
+				calls.iter().map(|call| Self::filter_call(who, call)).collect()
+			}
+			Call::Multisig(multisig::Call::<Self>::propose(multisig, call)) => Self::filter_call(multisig, call),
+			Call::Scheduler(scheduler::Call::<Self>::schedule(_, _, call)) => Self::filter_call(who, call),
+			_ => Ok(()),
+		}
+	}
+}
+
+/// Computes the fee of a call dispatched by a contract, refusing the calls contracts may not make
+pub struct ContractDispatchFee;
+
+impl ComputeDispatchFee<Call, Balance> for ContractDispatchFee {
+	fn compute_dispatch_fee(call: &Call) -> Balance {
+		match call {
+			Call::Contract(_) | Call::Proxy(_) | Call::Utility(_) | Call::Multisig(_) | Call::Scheduler(_) => {
+				// More gas than any contract call is given, at any gas price
+				Bounded::max_value()
+			}
+			_ => DefaultDispatchFeeComputor::<Runtime>::compute_dispatch_fee(call),
+		}
+	}
+}
+
+/// Determines the address of a new contract, refusing code its creator may not deploy
+///
+/// `srml-contract` cannot be told to reject an instantiation here, so the address of one the
+/// creator may not make is the creator's own. A contract instantiating code is itself a live
+/// contract, so its instantiation fails as the address is taken. Accounts instantiating code
+/// with `Contract::create` have already been checked by the call filter.
+pub struct AllowlistAddressDeterminator;
+
+impl ContractAddressFor<Hash, AccountId> for AllowlistAddressDeterminator {
+	fn contract_address_for(code_hash: &Hash, data: &[u8], origin: &AccountId) -> AccountId {
+		match CodeAllowlist::ensure_can_deploy(origin, code_hash) {
+			Ok(()) => SimpleAddressDeterminator::<Runtime>::contract_address_for(code_hash, data, origin),
+			Err(_) => origin.clone(),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::proxy::{ProxyFilter, ProxyType};
+	use crate::orderbook::{self, Side};
+	use crate::utility::BatchFilter;
+	use crate::{BatchCallFilter, ProxyCallFilter};
+	use primitives::Blake2Hasher;
+	use runtime_io::with_externalities;
+	use runtime_primitives::BuildStorage;
+	use staking::{RewardDestination, ValidatorPrefs};
+
+	fn new_test_ext() -> runtime_io::TestExternalities<Blake2Hasher> {
+		system::GenesisConfig::<Runtime>::default().build_storage().unwrap().0.into()
+	}
+
+	#[test]
+	fn unapproved_code_is_filtered() {
+		with_externalities(&mut new_test_ext(), || {
+			let who = AccountId::from_raw([1; 32]);
+			let put_code = Call::Contract(contract::Call::put_code(100_000, vec![0, 1, 2]));
+			assert_eq!(Runtime::filter_call(&who, &put_code), Err("contract code is not approved"));
+
+			// Wrapped calls are checked with the origin they are dispatched with
+			let batch = Call::Utility(utility::Call::batch_until_failure(vec![put_code.clone()]));
+			assert_eq!(Runtime::filter_call(&who, &batch), Err("contract code is not approved"));
+			let scheduled = Call::Scheduler(scheduler::Call::schedule(10, None, Box::new(put_code)));
+			assert_eq!(Runtime::filter_call(&who, &scheduled), Err("contract code is not approved"));
+		});
+	}
+
+	#[test]
+	fn contracts_cannot_dispatch_contract_or_wrapping_calls() {
+		with_externalities(&mut new_test_ext(), || {
+			let put_code = Call::Contract(contract::Call::put_code(100_000, vec![0, 1, 2]));
+			assert_eq!(ContractDispatchFee::compute_dispatch_fee(&put_code), Balance::max_value());
+			let create = Call::Contract(contract::Call::create(0, 100_000, Default::default(), vec![]));
+			assert_eq!(ContractDispatchFee::compute_dispatch_fee(&create), Balance::max_value());
+			let batch = Call::Utility(utility::Call::batch_until_failure(vec![create]));
+			assert_eq!(ContractDispatchFee::compute_dispatch_fee(&batch), Balance::max_value());
+
+			let transfer = Call::GenericAsset(generic_asset::Call::transfer(16001, AccountId::from_raw([2; 32]), 1));
+			assert_eq!(
+				ContractDispatchFee::compute_dispatch_fee(&transfer),
+				DefaultDispatchFeeComputor::<Runtime>::compute_dispatch_fee(&transfer)
+			);
+		});
+	}
+	#[test]
+	fn staking_proxies_cannot_redirect_rewards_or_stake() {
+		let principal = AccountId::from_raw([1; 32]);
+		let proxy = AccountId::from_raw([2; 32]);
+		let allowed = vec![
+			staking::Call::nominate(vec![AccountId::from_raw([3; 32]).into()]),
+			staking::Call::validate(ValidatorPrefs::default()),
+			staking::Call::chill(),
+			staking::Call::bond_extra(100),
+			staking::Call::unbond(100),
+			staking::Call::withdraw_unbonded(),
+		];
+		for call in allowed {
+			assert!(ProxyCallFilter::allows(ProxyType::Staking, &principal, &Call::Staking(call)));
+		}
+
+		let set_payee = Call::Staking(staking::Call::set_payee(RewardDestination::Controller));
+		assert!(!ProxyCallFilter::allows(ProxyType::Staking, &principal, &set_payee));
+		let set_controller = Call::Staking(staking::Call::set_controller(proxy.into()));
+		assert!(!ProxyCallFilter::allows(ProxyType::Staking, &principal, &set_controller));
+		// A proxy of any type may still make them
+		assert!(ProxyCallFilter::allows(ProxyType::Any, &principal, &set_payee));
+	}
+	#[test]
+	fn only_calls_failing_without_side_effects_are_batched() {
+		let transfer = Call::GenericAsset(generic_asset::Call::transfer(16001, AccountId::from_raw([2; 32]), 1));
+		assert!(BatchCallFilter::allows(&transfer));
+		assert!(BatchCallFilter::allows(&Call::Staking(staking::Call::unbond(100))));
+		assert!(BatchCallFilter::allows(&Call::OrderBook(orderbook::Call::cancel_order(0))));
+
+		let set_payee = Call::Staking(staking::Call::set_payee(RewardDestination::Controller));
+		assert!(!BatchCallFilter::allows(&set_payee));
+		let place_order = Call::OrderBook(orderbook::Call::place_order(16000, 16001, Side::Buy, 1, 1));
+		assert!(!BatchCallFilter::allows(&place_order));
+		let nested = Call::Utility(utility::Call::batch_until_failure(vec![transfer]));
+		assert!(!BatchCallFilter::allows(&nested));
+	}
+}
@@ -33,15 +33,39 @@ use version::NativeVersion;
 use generic_asset::{SpendingAssetCurrency, StakingAssetCurrency};
 use support::traits::Currency;
 use support::construct_runtime;
+use support::event::DecodeDifferent;
 pub use contract::Schedule;
 pub use staking::StakerStatus;
 
 pub use cennzx_spot::{ExchangeAddressGenerator, FeeRate};
+pub use multisig::MultisigAddressGenerator;
 
+pub use cennzx_spot;
 pub use fees;
 pub use generic_asset;
-
+pub use indices;
+pub use staking;
+pub use system;
+pub use timestamp;
+
+pub mod aura_equivocation;
+pub mod account_state;
+mod code_allowlist;
+pub mod contract_dry_run;
+mod contract_gas;
+mod dispatch;
 mod fee;
+mod filter;
+mod multisig;
+pub mod oracle;
+pub mod orderbook;
+mod proxy;
+mod reaping;
+mod router;
+pub mod scheduler;
+pub mod sponsorship;
+mod utility;
+mod vesting;
 
 /// Used for the module template in `./template.rs`
 mod template;
@@ -83,7 +107,7 @@ pub const VERSION: RuntimeVersion = RuntimeVersion {
 	spec_name: create_runtime_str!("cennznet-runtime-template"),
 	impl_name: create_runtime_str!("cennznet-runtime-template"),
 	authoring_version: 3,
-	spec_version: 3,
+	spec_version: 45,
 	impl_version: 0,
 	apis: RUNTIME_API_VERSIONS,
 };
@@ -144,7 +168,61 @@ impl system::Trait for Runtime {
 }
 
 impl aura::Trait for Runtime {
-	type HandleReport = ();
+	/// Authors which skip their slots are slashed as offline.
+	type HandleReport = aura::StakingSlasher<Runtime>;
+}
+
+/// The current Aura authority set
+pub struct AuraAuthorities;
+
+impl aura_equivocation::AuthoritySet<AuthorityId> for AuraAuthorities {
+	fn authorities() -> Vec<AuthorityId> {
+		Consensus::authorities()
+	}
+}
+
+/// Slashes equivocating authorities through `Staking`
+pub struct StakingEquivocationSlasher;
+
+impl StakingEquivocationSlasher {
+	/// The session validator (a staking controller) owning the session key `authority`.
+	///
+	/// Each session rotation sets the authority at index `i` to the session key of the validator
+	/// at index `i`, or to the default key if the validator has none. Authorities beyond the
+	/// validator count are left over from larger validator sets and belong to no validator, and a
+	/// key held at several indices cannot be attributed, so neither is slashed.
+	fn validator_of(authority: &AuthorityId) -> Option<AccountId> {
+		let validators = Session::validators();
+		let mut positions = Consensus::authorities()
+			.into_iter()
+			.take(validators.len())
+			.enumerate()
+			.filter(|(_, key)| key == authority)
+			.map(|(i, _)| i);
+		match (positions.next(), positions.next()) {
+			(Some(i), None) => validators.get(i).cloned(),
+			_ => None,
+		}
+	}
+}
+
+impl aura_equivocation::OnEquivocation<AuthorityId> for StakingEquivocationSlasher {
+	fn on_equivocation(authority: &AuthorityId) {
+		if let Some(controller) = Self::validator_of(authority) {
+			// Slash immediately, regardless of the grace given to offline validators
+			let offences = Staking::offline_slash_grace() as usize + 1;
+			Staking::on_offline_validator(controller, offences);
+		}
+	}
+}
+
+impl aura_equivocation::Trait for Runtime {
+	type Event = Event;
+	type AuthorityId = AuthorityId;
+	type Signature = AuthoritySignature;
+	type Header = opaque::Header;
+	type Authorities = AuraAuthorities;
+	type OnEquivocation = StakingEquivocationSlasher;
 }
 
 impl consensus::Trait for Runtime {
@@ -164,7 +242,7 @@ impl indices::Trait for Runtime {
 	/// Use the standard means of resolving an index hint from an id.
 	type ResolveHint = indices::SimpleResolveHint<Self::AccountId, Self::AccountIndex>;
 	/// Determine whether an account is dead.
-	type IsDeadAccount = ();
+	type IsDeadAccount = Reaping;
 	/// The uniquitous event type.
 	type Event = Event;
 }
@@ -201,14 +279,42 @@ impl grandpa::Trait for Runtime {
 }
 
 impl contract::Trait for Runtime {
-	type Currency = SpendingAssetCurrency<Self>;
+	type Currency = contract_gas::GasCurrency<Self>;
 	type Call = Call;
 	type Event = Event;
 	type Gas = u64;
-	type DetermineContractAddress = contract::SimpleAddressDeterminator<Runtime>;
-	type ComputeDispatchFee = contract::DefaultDispatchFeeComputor<Runtime>;
+	type DetermineContractAddress = filter::AllowlistAddressDeterminator;
+	type ComputeDispatchFee = filter::ContractDispatchFee;
 	type TrieIdGenerator = contract::TrieIdFromParentCounter<Runtime>;
-	type GasPayment = ();
+	type GasPayment = ContractGas;
+}
+
+impl contract_gas::Trait for Runtime {
+	type Event = Event;
+}
+
+impl code_allowlist::Trait for Runtime {
+	type Event = Event;
+}
+
+/// An account is bonded while it is a staking stash or controller
+pub struct StakingBond;
+
+impl reaping::HasBond<AccountId> for StakingBond {
+	fn has_bond(who: &AccountId) -> bool {
+		Staking::bonded(who).is_some() || Staking::ledger(who).is_some()
+	}
+}
+
+impl reaping::Trait for Runtime {
+	type Event = Event;
+	type Bond = StakingBond;
+	type OnNewAccount = Indices;
+}
+
+impl oracle::Trait for Runtime {
+	type Event = Event;
+	type Signature = Signature;
 }
 
 impl sudo::Trait for Runtime {
@@ -217,9 +323,12 @@ impl sudo::Trait for Runtime {
 	type Proposal = Call;
 }
 
+/// The identifier of a generic asset.
+pub type AssetId = u32;
+
 impl generic_asset::Trait for Runtime {
 	type Balance = u128;
-	type AssetId = u32;
+	type AssetId = AssetId;
 	type Event = Event;
 }
 
@@ -239,6 +348,124 @@ impl cennzx_spot::Trait for Runtime {
 	type U128ToBalance = Balance;
 }
 
+impl router::Trait for Runtime {
+	type Event = Event;
+}
+
+impl orderbook::Trait for Runtime {
+	type Event = Event;
+}
+
+impl vesting::Trait for Runtime {
+	type Event = Event;
+}
+
+impl multisig::Trait for Runtime {
+	type Event = Event;
+	type Call = Call;
+	type MultisigAddressGenerator = MultisigAddressGenerator<Self>;
+	type CallFilter = Runtime;
+	type Dispatcher = Runtime;
+}
+
+/// Restricts the calls each `proxy::ProxyType` may dispatch
+pub struct ProxyCallFilter;
+
+impl proxy::ProxyFilter<AccountId, Call> for ProxyCallFilter {
+	fn allows(proxy_type: proxy::ProxyType, principal: &AccountId, call: &Call) -> bool {
+		match proxy_type {
+			proxy::ProxyType::Any => true,
+			proxy::ProxyType::Staking => match call {
+				// Changing the reward destination or the controller would let the proxy take the
+				// principal's rewards or its stake, so only the calls managing the stake itself
+				// are allowed
+				Call::Staking(staking::Call::<Runtime>::nominate(..))
+				| Call::Staking(staking::Call::<Runtime>::validate(..))
+				| Call::Staking(staking::Call::<Runtime>::chill())
+				| Call::Staking(staking::Call::<Runtime>::bond_extra(..))
+				| Call::Staking(staking::Call::<Runtime>::unbond(..))
+				| Call::Staking(staking::Call::<Runtime>::withdraw_unbonded()) => true,
+				_ => false,
+			},
+			proxy::ProxyType::Trading => match call {
+				// Swap proceeds must stay with the principal, or the proxy could pay them to itself
+				Call::CennzxSpot(cennzx_spot::Call::<Runtime>::asset_swap_input(recipient, ..))
+				| Call::CennzxSpot(cennzx_spot::Call::<Runtime>::asset_swap_output(recipient, ..))
+				| Call::Router(router::Call::<Runtime>::swap_with_path(recipient, ..)) => {
+					recipient.as_ref().map_or(true, |recipient| recipient == principal)
+				}
+				Call::CennzxSpot(cennzx_spot::Call::<Runtime>::add_liquidity(..))
+				| Call::CennzxSpot(cennzx_spot::Call::<Runtime>::remove_liquidity(..))
+				| Call::OrderBook(_) => true,
+				_ => false,
+			},
+		}
+	}
+}
+
+impl proxy::Trait for Runtime {
+	type Event = Event;
+	type Call = Call;
+	type ProxyFilter = ProxyCallFilter;
+	type Dispatcher = Runtime;
+}
+
+impl sponsorship::Trait for Runtime {
+	type Event = Event;
+}
+
+/// Allows batching the calls which fail without side effects
+pub struct BatchCallFilter;
+
+impl utility::BatchFilter<Call> for BatchCallFilter {
+	fn allows(call: &Call) -> bool {
+		match call {
+			// Check the balance before moving it
+			Call::GenericAsset(generic_asset::Call::<Runtime>::transfer(..))
+			// Check the stash or controller ledger before updating it. `bond` is left out, as it
+			// creates the ledger after locking the stake.
+			| Call::Staking(staking::Call::<Runtime>::bond_extra(..))
+			| Call::Staking(staking::Call::<Runtime>::unbond(..))
+			| Call::Staking(staking::Call::<Runtime>::withdraw_unbonded())
+			| Call::Staking(staking::Call::<Runtime>::validate(..))
+			| Call::Staking(staking::Call::<Runtime>::nominate(..))
+			| Call::Staking(staking::Call::<Runtime>::chill())
+			// Price the swap and check the limit and the balance before the transfers. Adding and
+			// removing liquidity are left out, as they transfer and mint in separate steps.
+			| Call::CennzxSpot(cennzx_spot::Call::<Runtime>::asset_swap_input(..))
+			| Call::CennzxSpot(cennzx_spot::Call::<Runtime>::asset_swap_output(..))
+			// Quotes every hop and checks the balance before the first swap
+			| Call::Router(router::Call::<Runtime>::swap_with_path(..))
+			// Only cancelling: placing an order may match and settle several orders in turn
+			| Call::OrderBook(orderbook::Call::<Runtime>::cancel_order(..))
+			| Call::Vesting(vesting::Call::<Runtime>::vest(..))
+			| Call::Vesting(vesting::Call::<Runtime>::vest_other(..))
+			| Call::Proxy(proxy::Call::<Runtime>::add_proxy(..))
+			| Call::Proxy(proxy::Call::<Runtime>::remove_proxy(..))
+			| Call::Sponsorship(sponsorship::Call::<Runtime>::sponsor(..))
+			| Call::Sponsorship(sponsorship::Call::<Runtime>::accept(..))
+			| Call::Sponsorship(sponsorship::Call::<Runtime>::revoke(..))
+			| Call::Sponsorship(sponsorship::Call::<Runtime>::set_sponsored_calls(..)) => true,
+			_ => false,
+		}
+	}
+}
+
+impl utility::Trait for Runtime {
+	type Event = Event;
+	type Call = Call;
+	type BatchFilter = BatchCallFilter;
+	type Dispatcher = Runtime;
+}
+
+impl scheduler::Trait for Runtime {
+	type Event = Event;
+	type Call = Call;
+	type CallFilter = Runtime;
+	type Dispatcher = Runtime;
+	type CallFee = Runtime;
+}
+
 /// Used for the module template in `./template.rs`
 impl template::Trait for Runtime {
 	type Event = Event;
@@ -260,18 +487,105 @@ construct_runtime!(
 		Staking: staking,
 		Grandpa: grandpa::{Module, Call, Storage, Config<T>, Log(), Event<T>},
 		Contract: contract::{Module, Call, Storage, Config<T>, Event<T>},
+		ContractGas: contract_gas::{Module, Storage, Config<T>, Event<T>},
+		CodeAllowlist: code_allowlist::{Module, Call, Storage, Event<T>},
+		Reaping: reaping::{Module, Call, Storage, Config<T>, Event<T>},
 		Sudo: sudo,
 		Fees: fees::{Module, Call, Fee, Storage, Config<T>, Event<T>},
 		CennzxSpot: cennzx_spot::{Module, Call, Storage, Config<T>, Event<T>},
+		Router: router::{Module, Call, Event<T>},
+		OrderBook: orderbook::{Module, Call, Storage, Config<T>, Event<T>},
+		Vesting: vesting::{Module, Call, Storage, Config<T>, Event<T>},
+		Multisig: multisig::{Module, Call, Storage, Event<T>},
+		Proxy: proxy::{Module, Call, Storage, Event<T>},
+		Sponsorship: sponsorship::{Module, Call, Storage, Event<T>},
+		Utility: utility::{Module, Call, Event},
+		Scheduler: scheduler::{Module, Call, Storage, Config<T>, Event<T>},
+		Oracle: oracle::{Module, Call, Storage, Config<T>, Event<T>},
+		AuraEquivocation: aura_equivocation::{Module, Call, Storage, Event<T>},
 		// Used for the module template in `./template.rs`
 		TemplateModule: template::{Module, Call, Storage, Event<T>},
 	}
 );
 
+impl Call {
+	/// The name of the module the call is dispatched to, as named in the runtime metadata
+	pub fn module_name(&self) -> &'static str {
+		match self {
+			Call::System(_) => "System",
+			Call::Timestamp(_) => "Timestamp",
+			Call::Consensus(_) => "Consensus",
+			Call::Indices(_) => "Indices",
+			Call::GenericAsset(_) => "GenericAsset",
+			Call::Session(_) => "Session",
+			Call::Staking(_) => "Staking",
+			Call::Grandpa(_) => "Grandpa",
+			Call::Contract(_) => "Contract",
+			Call::CodeAllowlist(_) => "CodeAllowlist",
+			Call::Reaping(_) => "Reaping",
+			Call::Sudo(_) => "Sudo",
+			Call::Fees(_) => "Fees",
+			Call::CennzxSpot(_) => "CennzxSpot",
+			Call::Router(_) => "Router",
+			Call::OrderBook(_) => "OrderBook",
+			Call::Vesting(_) => "Vesting",
+			Call::Multisig(_) => "Multisig",
+			Call::Proxy(_) => "Proxy",
+			Call::Sponsorship(_) => "Sponsorship",
+			Call::Utility(_) => "Utility",
+			Call::Scheduler(_) => "Scheduler",
+			Call::Oracle(_) => "Oracle",
+			Call::AuraEquivocation(_) => "AuraEquivocation",
+			Call::TemplateModule(_) => "TemplateModule",
+		}
+	}
+}
+
+#[cfg(feature = "std")]
+impl Event {
+	/// The runtime name of the module which deposited the event, and the name and field type
+	/// names of the event variant, as given by the module's event metadata
+	pub fn metadata(&self) -> (&'static str, Option<(&'static str, &'static [&'static str])>) {
+		let (module, variants) = match self {
+			Event::system(_) => ("System", <system::Event>::metadata()),
+			Event::indices(_) => ("Indices", <indices::Event<Runtime>>::metadata()),
+			Event::generic_asset(_) => ("GenericAsset", <generic_asset::Event<Runtime>>::metadata()),
+			Event::session(_) => ("Session", <session::Event<Runtime>>::metadata()),
+			Event::staking(_) => ("Staking", <staking::Event<Runtime>>::metadata()),
+			Event::grandpa(_) => ("Grandpa", <grandpa::Event<Runtime>>::metadata()),
+			Event::contract(_) => ("Contract", <contract::Event<Runtime>>::metadata()),
+			Event::contract_gas(_) => ("ContractGas", <contract_gas::Event<Runtime>>::metadata()),
+			Event::code_allowlist(_) => ("CodeAllowlist", <code_allowlist::Event<Runtime>>::metadata()),
+			Event::reaping(_) => ("Reaping", <reaping::Event<Runtime>>::metadata()),
+			Event::sudo(_) => ("Sudo", <sudo::Event<Runtime>>::metadata()),
+			Event::fees(_) => ("Fees", <fees::Event<Runtime>>::metadata()),
+			Event::cennzx_spot(_) => ("CennzxSpot", <cennzx_spot::Event<Runtime>>::metadata()),
+			Event::router(_) => ("Router", <router::Event<Runtime>>::metadata()),
+			Event::orderbook(_) => ("OrderBook", <orderbook::Event<Runtime>>::metadata()),
+			Event::vesting(_) => ("Vesting", <vesting::Event<Runtime>>::metadata()),
+			Event::multisig(_) => ("Multisig", <multisig::Event<Runtime>>::metadata()),
+			Event::proxy(_) => ("Proxy", <proxy::Event<Runtime>>::metadata()),
+			Event::sponsorship(_) => ("Sponsorship", <sponsorship::Event<Runtime>>::metadata()),
+			Event::utility(_) => ("Utility", <utility::Event>::metadata()),
+			Event::scheduler(_) => ("Scheduler", <scheduler::Event<Runtime>>::metadata()),
+			Event::oracle(_) => ("Oracle", <oracle::Event<Runtime>>::metadata()),
+			Event::aura_equivocation(_) => ("AuraEquivocation", <aura_equivocation::Event<Runtime>>::metadata()),
+			Event::template(_) => ("TemplateModule", <template::Event<Runtime>>::metadata()),
+		};
+		// The outer event is encoded as its module index followed by the module's event
+		let variant = self.using_encoded(|e| e.get(1).and_then(|i| variants.get(*i as usize)));
+		let variant = variant.and_then(|event| match (&event.name, &event.arguments) {
+			(DecodeDifferent::Encode(name), DecodeDifferent::Encode(arguments)) => Some((*name, *arguments)),
+			_ => None,
+		});
+		(module, variant)
+	}
+}
+
 /// The type used as a helper for interpreting the sender of transactions.
 type Context = system::ChainContext<Runtime>;
 /// The address format for describing accounts.
-type Address = <Indices as StaticLookup>::Source;
+pub type Address = <Indices as StaticLookup>::Source;
 /// Block header type as expected by this runtime.
 pub type Header = generic::Header<BlockNumber, BlakeTwo256, Log>;
 /// Block type as expected by this runtime.
@@ -337,6 +651,13 @@ impl_runtime_apis! {
 
 	impl client_api::TaggedTransactionQueue<Block> for Runtime {
 		fn validate_transaction(tx: <Block as BlockT>::Extrinsic) -> TransactionValidity {
+			// The executive rejects all unsigned transactions, so those carrying their own proof
+			// of authorization are validated by their module
+			if tx.signature.is_none() {
+				if let Call::Oracle(call) = &tx.function {
+					return Oracle::validate_unsigned(call);
+				}
+			}
 			Executive::validate_transaction(tx)
 		}
 	}
@@ -392,4 +713,56 @@ impl_runtime_apis! {
 			Consensus::authorities()
 		}
 	}
+
+	impl orderbook::OrderBookApi<Block, AssetId, Balance> for Runtime {
+		fn book_depth(base: AssetId, quote: AssetId, levels: u32) -> (Vec<(Balance, Balance)>, Vec<(Balance, Balance)>) {
+			OrderBook::book_depth(base, quote, levels)
+		}
+	}
+
+	impl scheduler::SchedulerApi<Block, scheduler::TaskOf<Runtime>> for Runtime {
+		fn pending_tasks() -> Vec<(scheduler::TaskId, scheduler::TaskOf<Runtime>)> {
+			Scheduler::tasks()
+		}
+	}
+
+	impl oracle::OracleApi<Block, AssetId, Balance, BlockNumber> for Runtime {
+		fn price(asset_id: AssetId) -> Option<(Balance, oracle::Round, BlockNumber)> {
+			Oracle::price_with_age(asset_id)
+		}
+	}
+
+	impl account_state::AccountStateApi<Block, Address, AccountId, AccountIndex, AssetId, Balance, Index> for Runtime {
+		fn account_balances(who: Address) -> Option<Vec<account_state::AssetBalance<AssetId, Balance>>> {
+			account_state::account_balances(who)
+		}
+
+		fn account_info(
+			who: Address,
+		) -> Option<account_state::AccountInfo<AccountId, AccountIndex, AssetId, Balance, Index>> {
+			account_state::account_info(who)
+		}
+	}
+
+	impl contract_dry_run::ContractsApi<Block, AccountId, Balance, Hash, Event> for Runtime {
+		fn call(
+			origin: AccountId,
+			dest: AccountId,
+			value: Balance,
+			gas_limit: u64,
+			input_data: Vec<u8>,
+		) -> contract_dry_run::ContractExecResult<AccountId, Event> {
+			contract_dry_run::call(origin, dest, value, gas_limit, input_data)
+		}
+
+		fn instantiate(
+			origin: AccountId,
+			endowment: Balance,
+			gas_limit: u64,
+			code_hash: Hash,
+			data: Vec<u8>,
+		) -> contract_dry_run::ContractExecResult<AccountId, Event> {
+			contract_dry_run::instantiate(origin, endowment, gas_limit, code_hash, data)
+		}
+	}
 }
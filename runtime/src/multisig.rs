@@ -0,0 +1,354 @@
+// Copyright (C) 2019 Centrality Investments Limited
+// This file is part of CENNZnet.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//!
+//! Multisig accounts
+//!
+//! A multisig account ID is derived deterministically from its sorted signatories and threshold,
+//! so it can be funded before it is registered. Once registered, any signatory may propose a
+//! call, the other signatories approve it by hash, and the call is dispatched with the multisig
+//! account as its signed origin when `threshold` approvals are reached. The call filter is
+//! checked again at that point, as the call may have become forbidden since it was proposed.
+//!
+use crate::dispatch::DispatchAs;
+use crate::filter::CallFilter;
+use parity_codec::{Decode, Encode};
+use primitives::crypto::UncheckedFrom;
+use rstd::{marker::PhantomData, prelude::*};
+use runtime_primitives::traits::{Dispatchable, Hash};
+use support::{decl_event, decl_module, decl_storage, dispatch::Result, ensure, Parameter, StorageMap};
+use system::ensure_signed;
+
+/// Maximum number of signatories of a multisig account
+pub const MAX_SIGNATORIES: usize = 32;
+
+/// The module's configuration trait.
+pub trait Trait: system::Trait {
+	/// The overarching event type.
+	type Event: From<Event<Self>> + Into<<Self as system::Trait>::Event>;
+	/// The call type which may be proposed for dispatch
+	type Call: Parameter + Dispatchable<Origin = <Self as system::Trait>::Origin>;
+	/// Determines the account ID of a multisig from its signatories and threshold
+	type MultisigAddressGenerator: MultisigAddressFor<Self::AccountId>;
+	/// Checks the multisig account may dispatch a call once it is approved
+	type CallFilter: CallFilter<Self::AccountId, <Self as Trait>::Call>;
+	/// Dispatches approved calls on behalf of the multisig account
+	type Dispatcher: DispatchAs<Self::AccountId, <Self as Trait>::Call>;
+}
+
+/// A function that generates an `AccountId` for a multisig account
+pub trait MultisigAddressFor<AccountId: Sized> {
+	/// The account ID of the multisig with the given sorted `signatories` and `threshold`
+	fn multisig_address_for(signatories: &[AccountId], threshold: u16) -> AccountId;
+}
+
+/// A multisig address generator which hashes the signatories and threshold into an account ID
+pub struct MultisigAddressGenerator<T: Trait>(PhantomData<T>);
+
+impl<T: Trait> MultisigAddressFor<T::AccountId> for MultisigAddressGenerator<T>
+where
+	T::AccountId: UncheckedFrom<T::Hash>,
+{
+	fn multisig_address_for(signatories: &[T::AccountId], threshold: u16) -> T::AccountId {
+		let entropy = (b"cennznet:multisig", signatories, threshold).encode();
+		UncheckedFrom::unchecked_from(T::Hashing::hash(&entropy))
+	}
+}
+
+/// The signatories and approval threshold of a multisig account
+#[derive(Encode, Decode, Clone, PartialEq)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct MultisigConfig<AccountId> {
+	/// Sorted, deduplicated signatories
+	pub signatories: Vec<AccountId>,
+	/// Number of approvals required to dispatch a call
+	pub threshold: u16,
+}
+
+/// A call awaiting approvals
+#[derive(Encode, Decode, Clone, PartialEq)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct Proposal<AccountId, Call> {
+	/// The signatory who proposed the call
+	pub proposer: AccountId,
+	/// The call to dispatch
+	pub call: Call,
+	/// Signatories which have approved, including the proposer
+	pub approvals: Vec<AccountId>,
+}
+
+type ProposalOf<T> = Proposal<<T as system::Trait>::AccountId, <T as Trait>::Call>;
+
+decl_storage! {
+	trait Store for Module<T: Trait> as Multisig {
+		/// Registered multisig accounts
+		pub Multisigs get(multisig): map T::AccountId => Option<MultisigConfig<T::AccountId>>;
+		/// Open proposals by (multisig account, call hash)
+		pub Proposals get(proposal): map (T::AccountId, T::Hash) => Option<ProposalOf<T>>;
+	}
+}
+
+decl_module! {
+	pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+		fn deposit_event<T>() = default;
+
+		/// Register the multisig account of `signatories` requiring `threshold` approvals
+		pub fn register(origin, signatories: Vec<T::AccountId>, threshold: u16) -> Result {
+			ensure_signed(origin)?;
+			let mut signatories = signatories;
+			signatories.sort();
+			signatories.dedup();
+			ensure!(signatories.len() <= MAX_SIGNATORIES, "too many signatories");
+			ensure!(threshold > 0, "threshold should be non-zero");
+			ensure!(threshold as usize <= signatories.len(), "threshold exceeds the number of signatories");
+
+			let multisig = T::MultisigAddressGenerator::multisig_address_for(&signatories, threshold);
+			ensure!(!<Multisigs<T>>::exists(&multisig), "multisig account is already registered");
+			<Multisigs<T>>::insert(&multisig, MultisigConfig { signatories, threshold });
+
+			Self::deposit_event(RawEvent::MultisigRegistered(multisig, threshold));
+			Ok(())
+		}
+
+		/// Propose `call` for dispatch from `multisig`, counting as the caller's approval
+		pub fn propose(origin, multisig: T::AccountId, call: Box<<T as Trait>::Call>) -> Result {
+			let who = ensure_signed(origin)?;
+			let config = Self::multisig(&multisig).ok_or("multisig account is not registered")?;
+			ensure!(config.signatories.contains(&who), "caller is not a signatory");
+
+			let call_hash = T::Hashing::hash_of(&call);
+			let key = (multisig.clone(), call_hash);
+			ensure!(!<Proposals<T>>::exists(&key), "call has already been proposed");
+
+			Self::deposit_event(RawEvent::Proposed(multisig.clone(), call_hash, who.clone()));
+			let proposal = Proposal { proposer: who.clone(), call: *call, approvals: vec![who] };
+			Self::approve_or_dispatch(multisig, call_hash, proposal, config.threshold);
+			Ok(())
+		}
+
+		/// Approve the proposal of `multisig` with `call_hash`
+		pub fn approve(origin, multisig: T::AccountId, call_hash: T::Hash) -> Result {
+			let who = ensure_signed(origin)?;
+			let config = Self::multisig(&multisig).ok_or("multisig account is not registered")?;
+			ensure!(config.signatories.contains(&who), "caller is not a signatory");
+
+			let mut proposal = Self::proposal((multisig.clone(), call_hash)).ok_or("proposal does not exist")?;
+			ensure!(!proposal.approvals.contains(&who), "caller has already approved");
+			proposal.approvals.push(who.clone());
+
+			Self::deposit_event(RawEvent::Approved(multisig.clone(), call_hash, who));
+			Self::approve_or_dispatch(multisig, call_hash, proposal, config.threshold);
+			Ok(())
+		}
+
+		/// Cancel a proposal. Only its proposer may cancel it.
+		pub fn cancel(origin, multisig: T::AccountId, call_hash: T::Hash) -> Result {
+			let who = ensure_signed(origin)?;
+			let key = (multisig.clone(), call_hash);
+			let proposal = Self::proposal(&key).ok_or("proposal does not exist")?;
+			ensure!(proposal.proposer == who, "only the proposer can cancel");
+
+			<Proposals<T>>::remove(&key);
+			Self::deposit_event(RawEvent::Cancelled(multisig, call_hash));
+			Ok(())
+		}
+	}
+}
+
+decl_event!(
+	pub enum Event<T> where
+		AccountId = <T as system::Trait>::AccountId,
+		Hash = <T as system::Trait>::Hash
+	{
+		/// A multisig account was registered: (multisig, threshold)
+		MultisigRegistered(AccountId, u16),
+		/// A call was proposed: (multisig, call hash, proposer)
+		Proposed(AccountId, Hash, AccountId),
+		/// A proposal was approved: (multisig, call hash, approver)
+		Approved(AccountId, Hash, AccountId),
+		/// A proposal reached its threshold and was dispatched: (multisig, call hash, success)
+		Executed(AccountId, Hash, bool),
+		/// A proposal was cancelled: (multisig, call hash)
+		Cancelled(AccountId, Hash),
+	}
+);
+
+impl<T: Trait> Module<T> {
+	/// Dispatch `proposal` if it has `threshold` approvals, otherwise store it
+	fn approve_or_dispatch(multisig: T::AccountId, call_hash: T::Hash, proposal: ProposalOf<T>, threshold: u16) {
+		let key = (multisig.clone(), call_hash);
+		if proposal.approvals.len() < threshold as usize {
+			<Proposals<T>>::insert(&key, proposal);
+			return;
+		}
+
+		<Proposals<T>>::remove(&key);
+		let ok = T::CallFilter::filter(&multisig, &proposal.call).is_ok()
+			&& T::Dispatcher::dispatch_as(multisig.clone(), proposal.call).is_ok();
+		Self::deposit_event(RawEvent::Executed(multisig, call_hash, ok));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use primitives::{Blake2Hasher, H256};
+	use runtime_io::with_externalities;
+	use runtime_primitives::{
+		testing::{Digest, DigestItem, Header},
+		traits::{BlakeTwo256, IdentityLookup},
+		BuildStorage,
+	};
+	use support::{assert_noop, assert_ok, impl_outer_origin};
+
+	impl_outer_origin! {
+		pub enum Origin for Test {}
+	}
+
+	#[derive(Clone, Eq, PartialEq)]
+	pub struct Test;
+	impl system::Trait for Test {
+		type Origin = Origin;
+		type Index = u64;
+		type BlockNumber = u64;
+		type Hash = H256;
+		type Hashing = BlakeTwo256;
+		type Digest = Digest;
+		type AccountId = u64;
+		type Lookup = IdentityLookup<Self::AccountId>;
+		type Header = Header;
+		type Event = ();
+		type Log = DigestItem;
+	}
+	// The multisig of signatories 1, 2 and 3 with threshold 2 is account 1062
+	pub struct TestAddress;
+	impl MultisigAddressFor<u64> for TestAddress {
+		fn multisig_address_for(signatories: &[u64], threshold: u16) -> u64 {
+			1000 + signatories.iter().sum::<u64>() * 10 + u64::from(threshold)
+		}
+	}
+	// Forbids registering multisigs with threshold 3
+	pub struct TestFilter;
+	impl CallFilter<u64, Call<Test>> for TestFilter {
+		fn filter(_: &u64, call: &Call<Test>) -> Result {
+			match call {
+				Call::register(_, 3) => Err("call is filtered"),
+				_ => Ok(()),
+			}
+		}
+	}
+	impl Trait for Test {
+		type Event = ();
+		type Call = Call<Test>;
+		type MultisigAddressGenerator = TestAddress;
+		type CallFilter = TestFilter;
+		type Dispatcher = ();
+	}
+	type Multisig = Module<Test>;
+
+	const MULTISIG: u64 = 1062;
+
+	fn new_test_ext() -> runtime_io::TestExternalities<Blake2Hasher> {
+		let mut ext: runtime_io::TestExternalities<Blake2Hasher> =
+			system::GenesisConfig::<Test>::default().build_storage().unwrap().0.into();
+		with_externalities(&mut ext, || {
+			assert_ok!(Multisig::register(Origin::signed(1), vec![3, 1, 2, 1], 2));
+		});
+		ext
+	}
+
+	fn register_call(signatories: Vec<u64>, threshold: u16) -> Box<Call<Test>> {
+		Box::new(Call::register(signatories, threshold))
+	}
+
+	#[test]
+	fn register_validates_threshold() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_eq!(
+				Multisig::multisig(MULTISIG),
+				Some(MultisigConfig { signatories: vec![1, 2, 3], threshold: 2 })
+			);
+			assert_noop!(
+				Multisig::register(Origin::signed(1), vec![1, 2], 0),
+				"threshold should be non-zero"
+			);
+			assert_noop!(
+				Multisig::register(Origin::signed(1), vec![1, 2, 2], 3),
+				"threshold exceeds the number of signatories"
+			);
+			assert_noop!(
+				Multisig::register(Origin::signed(4), vec![1, 2, 3], 2),
+				"multisig account is already registered"
+			);
+		});
+	}
+
+	#[test]
+	fn call_is_dispatched_at_threshold() {
+		with_externalities(&mut new_test_ext(), || {
+			let call = register_call(vec![4, 5], 1);
+			let call_hash = BlakeTwo256::hash_of(&call);
+			assert_ok!(Multisig::propose(Origin::signed(1), MULTISIG, call.clone()));
+			assert_eq!(Multisig::proposal((MULTISIG, call_hash)).map(|p| p.approvals), Some(vec![1]));
+			assert_eq!(Multisig::multisig(1091), None);
+
+			assert_noop!(Multisig::approve(Origin::signed(1), MULTISIG, call_hash), "caller has already approved");
+			assert_ok!(Multisig::approve(Origin::signed(3), MULTISIG, call_hash));
+			assert_eq!(Multisig::proposal((MULTISIG, call_hash)), None);
+			assert!(Multisig::multisig(1091).is_some());
+		});
+	}
+
+	#[test]
+	fn only_signatories_take_part() {
+		with_externalities(&mut new_test_ext(), || {
+			let call = register_call(vec![4, 5], 1);
+			let call_hash = BlakeTwo256::hash_of(&call);
+			assert_noop!(
+				Multisig::propose(Origin::signed(4), MULTISIG, call.clone()),
+				"caller is not a signatory"
+			);
+			assert_noop!(
+				Multisig::propose(Origin::signed(1), 999, call.clone()),
+				"multisig account is not registered"
+			);
+			assert_ok!(Multisig::propose(Origin::signed(1), MULTISIG, call.clone()));
+			assert_noop!(
+				Multisig::propose(Origin::signed(2), MULTISIG, call),
+				"call has already been proposed"
+			);
+			assert_noop!(Multisig::approve(Origin::signed(4), MULTISIG, call_hash), "caller is not a signatory");
+			assert_noop!(Multisig::cancel(Origin::signed(2), MULTISIG, call_hash), "only the proposer can cancel");
+
+			assert_ok!(Multisig::cancel(Origin::signed(1), MULTISIG, call_hash));
+			assert_noop!(Multisig::approve(Origin::signed(2), MULTISIG, call_hash), "proposal does not exist");
+		});
+	}
+
+	#[test]
+	fn filtered_call_is_not_dispatched() {
+		with_externalities(&mut new_test_ext(), || {
+			let call = register_call(vec![4, 5, 6], 3);
+			let call_hash = BlakeTwo256::hash_of(&call);
+			assert_ok!(Multisig::propose(Origin::signed(1), MULTISIG, call));
+			assert_ok!(Multisig::approve(Origin::signed(2), MULTISIG, call_hash));
+
+			// The proposal is used up, but the call was rejected by the filter
+			assert_eq!(Multisig::proposal((MULTISIG, call_hash)), None);
+			assert_eq!(Multisig::multisig(1153), None);
+		});
+	}
+}
@@ -0,0 +1,491 @@
+// Copyright (C) 2019 Centrality Investments Limited
+// This file is part of CENNZnet.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//!
+//! Asset price oracle
+//!
+//! Authorized feeders submit prices per asset for the current round, which lasts
+//! `round_duration` blocks. Submissions for any other round are rejected as stale.
+//! When a round ends, the median of each asset's submissions becomes its price, provided at
+//! least `min_submissions` feeders submitted.
+//!
+//! Feeders may also submit prices without a fee as unsigned transactions carrying their
+//! signature of the submission, as the node's price feed does. These are checked by
+//! `validate_unsigned` before entering the transaction pool.
+//!
+//! Other modules read prices through the `PriceProvider` trait. A price set more than
+//! `max_price_age` blocks ago is stale and not provided, unless `max_price_age` is zero.
+//!
+use client::decl_runtime_apis;
+use parity_codec::{Codec, Decode, Encode};
+use rstd::prelude::*;
+use runtime_primitives::traits::{As, Member, Saturating, SimpleArithmetic, Verify, Zero};
+use runtime_primitives::transaction_validity::TransactionValidity;
+use support::{
+	decl_event, decl_module, decl_storage, dispatch::Result, ensure, Parameter, StorageMap, StorageValue,
+};
+use system::{ensure_root, ensure_signed};
+
+/// Maximum number of feeders
+pub const MAX_FEEDERS: usize = 32;
+
+/// The module's configuration trait.
+pub trait Trait: generic_asset::Trait {
+	/// The overarching event type.
+	type Event: From<Event<Self>> + Into<<Self as system::Trait>::Event>;
+	/// The signature of a feeder over an unsigned price submission
+	type Signature: Parameter + Member + Verify<Signer = Self::AccountId>;
+}
+
+/// Provides the latest price of an asset
+pub trait PriceProvider<AssetId, Balance> {
+	/// The latest price of `asset_id`, if any
+	fn price(asset_id: &AssetId) -> Option<Balance>;
+}
+
+type AssetIdOf<T> = <T as generic_asset::Trait>::AssetId;
+type BalanceOf<T> = <T as generic_asset::Trait>::Balance;
+
+/// A round number
+pub type Round = u64;
+
+/// A price submitted in an unsigned transaction, signed by `feeder`
+#[derive(Encode, Decode, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct PriceSubmission<AccountId, AssetId, Balance> {
+	/// The feeder submitting the price
+	pub feeder: AccountId,
+	/// The asset priced
+	pub asset_id: AssetId,
+	/// The round of the submission
+	pub round: Round,
+	/// The price
+	pub price: Balance,
+}
+
+/// The aggregated price of an asset
+#[derive(Encode, Decode, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct AssetPrice<Balance, BlockNumber> {
+	/// The median of the round's submissions
+	pub price: Balance,
+	/// The round which set the price
+	pub round: Round,
+	/// The block the round ended in
+	pub block_number: BlockNumber,
+}
+
+type AssetPriceOf<T> = AssetPrice<BalanceOf<T>, <T as system::Trait>::BlockNumber>;
+
+type PriceSubmissionOf<T> = PriceSubmission<<T as system::Trait>::AccountId, AssetIdOf<T>, BalanceOf<T>>;
+
+/// Unsigned submissions with an invalid feeder signature are rejected with this code
+const INVALID_SIGNATURE: i8 = -1;
+/// Unsigned submissions failing the checks of a signed submission are rejected with this code
+const INVALID_SUBMISSION: i8 = -2;
+/// Calls other than unsigned submissions are not valid unsigned transactions
+const NOT_UNSIGNED_SUBMISSION: i8 = -3;
+
+decl_storage! {
+	trait Store for Module<T: Trait> as Oracle {
+		/// Accounts authorized to submit prices
+		pub Feeders get(feeders) config(): Vec<T::AccountId>;
+		/// Number of blocks in a round
+		pub RoundDuration get(round_duration) config(): T::BlockNumber;
+		/// Minimum number of submissions for a round to set a price
+		pub MinSubmissions get(min_submissions) config(): u32;
+		/// Number of blocks after which a price is stale, or zero if prices do not go stale
+		pub MaxPriceAge get(max_price_age) config(): T::BlockNumber;
+		/// Submissions of the current round per asset as (feeder, price)
+		pub Submissions get(submissions): map AssetIdOf<T> => Vec<(T::AccountId, BalanceOf<T>)>;
+		/// Assets with submissions in the current round
+		pub SubmittedAssets get(submitted_assets): Vec<AssetIdOf<T>>;
+		/// The latest aggregated price of an asset
+		pub Prices get(prices): map AssetIdOf<T> => Option<AssetPriceOf<T>>;
+	}
+}
+
+decl_module! {
+	pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+		fn deposit_event<T>() = default;
+
+		/// Submit the price of `asset_id` for `round`, which must be the current round
+		pub fn submit_price(origin, asset_id: AssetIdOf<T>, #[compact] round: Round, #[compact] price: BalanceOf<T>) -> Result {
+			let feeder = ensure_signed(origin)?;
+			Self::do_submit_price(feeder, asset_id, round, price)
+		}
+
+		/// Submit the price in `submission` without a fee, authorized by the feeder's `signature`
+		/// of the encoded submission. Must be an unsigned transaction.
+		pub fn submit_price_unsigned(origin, submission: PriceSubmissionOf<T>, signature: T::Signature) -> Result {
+			match origin.into() {
+				Ok(system::RawOrigin::None) => (),
+				_ => return Err("bad origin: expected an unsigned transaction"),
+			}
+			ensure!(signature.verify(&submission.encode()[..], &submission.feeder), "invalid feeder signature");
+			let PriceSubmission { feeder, asset_id, round, price } = submission;
+			Self::do_submit_price(feeder, asset_id, round, price)
+		}
+
+		/// Authorize `who` to submit prices. Must be called by root.
+		pub fn add_feeder(origin, who: T::AccountId) -> Result {
+			ensure_root(origin)?;
+			let mut feeders = Self::feeders();
+			ensure!(!feeders.contains(&who), "account is already a feeder");
+			ensure!(feeders.len() < MAX_FEEDERS, "too many feeders");
+			feeders.push(who);
+			<Feeders<T>>::put(feeders);
+			Ok(())
+		}
+
+		/// Revoke the authorization of `who` to submit prices. Must be called by root.
+		pub fn remove_feeder(origin, who: T::AccountId) -> Result {
+			ensure_root(origin)?;
+			let mut feeders = Self::feeders();
+			ensure!(feeders.contains(&who), "account is not a feeder");
+			feeders.retain(|f| *f != who);
+			<Feeders<T>>::put(feeders);
+			Ok(())
+		}
+
+		fn on_finalize(n: T::BlockNumber) {
+			let duration = Self::round_duration();
+			if duration.is_zero() || !((n + As::sa(1)) % duration).is_zero() {
+				return;
+			}
+
+			let round = Self::current_round();
+			let min_submissions = Self::min_submissions() as usize;
+			for asset_id in <SubmittedAssets<T>>::take() {
+				let submissions = <Submissions<T>>::take(asset_id);
+				if submissions.len() < min_submissions.max(1) {
+					continue;
+				}
+				let price = median(submissions.into_iter().map(|(_, price)| price).collect());
+				<Prices<T>>::insert(asset_id, AssetPrice { price, round, block_number: n });
+				Self::deposit_event(RawEvent::PriceUpdated(asset_id, round, price));
+			}
+		}
+	}
+}
+
+decl_event!(
+	pub enum Event<T> where
+		AccountId = <T as system::Trait>::AccountId,
+		AssetId = AssetIdOf<T>,
+		Balance = BalanceOf<T>
+	{
+		/// A feeder submitted a price: (feeder, asset, round, price)
+		PriceSubmitted(AccountId, AssetId, Round, Balance),
+		/// A round set the price of an asset: (asset, round, price)
+		PriceUpdated(AssetId, Round, Balance),
+	}
+);
+
+impl<T: Trait> Module<T> {
+	/// Record the price of `asset_id` submitted by `feeder` for `round`
+	fn do_submit_price(feeder: T::AccountId, asset_id: AssetIdOf<T>, round: Round, price: BalanceOf<T>) -> Result {
+		Self::check_submission(&feeder, &asset_id, round, &price)?;
+
+		let mut submissions = Self::submissions(asset_id);
+		if submissions.is_empty() {
+			<SubmittedAssets<T>>::mutate(|assets| assets.push(asset_id));
+		}
+		submissions.push((feeder.clone(), price));
+		<Submissions<T>>::insert(asset_id, submissions);
+
+		Self::deposit_event(RawEvent::PriceSubmitted(feeder, asset_id, round, price));
+		Ok(())
+	}
+
+	/// Check `feeder` may submit `price` of `asset_id` for `round`
+	fn check_submission(feeder: &T::AccountId, asset_id: &AssetIdOf<T>, round: Round, price: &BalanceOf<T>) -> Result {
+		ensure!(Self::feeders().contains(feeder), "caller is not a feeder");
+		ensure!(round == Self::current_round(), "submission is for a stale round");
+		ensure!(!price.is_zero(), "price should be non-zero");
+		ensure!(
+			!Self::submissions(asset_id).iter().any(|(who, _)| who == feeder),
+			"feeder has already submitted this round"
+		);
+		Ok(())
+	}
+
+	/// The validity of `call` as an unsigned transaction. Only `submit_price_unsigned` with a
+	/// valid feeder signature and submission is valid, once per feeder, asset and round, and
+	/// only until the round ends.
+	pub fn validate_unsigned(call: &Call<T>) -> TransactionValidity {
+		let (submission, signature) = match call {
+			Call::submit_price_unsigned(submission, signature) => (submission, signature),
+			_ => return TransactionValidity::Invalid(NOT_UNSIGNED_SUBMISSION),
+		};
+		if !signature.verify(&submission.encode()[..], &submission.feeder) {
+			return TransactionValidity::Invalid(INVALID_SIGNATURE);
+		}
+		let PriceSubmission { feeder, asset_id, round, price } = submission;
+		if Self::check_submission(feeder, asset_id, *round, price).is_err() {
+			return TransactionValidity::Invalid(INVALID_SUBMISSION);
+		}
+
+		let block_number = <system::Module<T>>::block_number().as_();
+		let longevity = match Self::round_duration().as_() {
+			0 => u64::max_value(),
+			duration => duration - block_number % duration,
+		};
+		TransactionValidity::Valid {
+			priority: 0,
+			requires: vec![],
+			provides: vec![(feeder, asset_id, round).encode()],
+			longevity,
+		}
+	}
+
+	/// The latest price of `asset_id`, the round it was set in and its age in blocks, if any.
+	/// Stale prices are included.
+	pub fn price_with_age(asset_id: AssetIdOf<T>) -> Option<(BalanceOf<T>, Round, T::BlockNumber)> {
+		Self::prices(asset_id).map(|AssetPrice { price, round, block_number }| {
+			(price, round, <system::Module<T>>::block_number().saturating_sub(block_number))
+		})
+	}
+
+	/// The round of the current block
+	pub fn current_round() -> Round {
+		let duration = Self::round_duration().as_();
+		if duration == 0 {
+			return 0;
+		}
+		<system::Module<T>>::block_number().as_() / duration
+	}
+}
+
+impl<T: Trait> PriceProvider<AssetIdOf<T>, BalanceOf<T>> for Module<T> {
+	fn price(asset_id: &AssetIdOf<T>) -> Option<BalanceOf<T>> {
+		let max_age = Self::max_price_age();
+		Self::price_with_age(*asset_id)
+			.filter(|(_, _, age)| max_age.is_zero() || *age <= max_age)
+			.map(|(price, _, _)| price)
+	}
+}
+
+/// The median of non-empty `values`. The mean of the middle values for an even count.
+fn median<Balance: SimpleArithmetic + As<u64> + Copy>(mut values: Vec<Balance>) -> Balance {
+	values.sort();
+	let mid = values.len() / 2;
+	if values.len() % 2 == 1 {
+		return values[mid];
+	}
+	let (a, b) = (values[mid - 1], values[mid]);
+	let two = Balance::sa(2);
+	a / two + b / two + (a % two + b % two) / two
+}
+
+decl_runtime_apis! {
+	/// The API to query oracle prices
+	pub trait OracleApi<AssetId, Balance, BlockNumber> where
+		AssetId: Codec,
+		Balance: Codec,
+		BlockNumber: Codec,
+	{
+		/// The latest price of `asset_id`, the round it was set in and its age in blocks, if any.
+		/// Stale prices are included.
+		fn price(asset_id: AssetId) -> Option<(Balance, Round, BlockNumber)>;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use primitives::{Blake2Hasher, H256};
+	use runtime_io::with_externalities;
+	use runtime_primitives::{
+		testing::{Digest, DigestItem, Header},
+		traits::{BlakeTwo256, IdentityLookup, Lazy, OnFinalize},
+		BuildStorage,
+	};
+	use support::{assert_noop, assert_ok, impl_outer_origin};
+
+	impl_outer_origin! {
+		pub enum Origin for Test {}
+	}
+
+	/// A signature of the encoded message by the account
+	#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+	pub struct TestSignature(u64, Vec<u8>);
+	impl Verify for TestSignature {
+		type Signer = u64;
+		fn verify<L: Lazy<[u8]>>(&self, mut msg: L, signer: &u64) -> bool {
+			self.0 == *signer && &self.1[..] == msg.get()
+		}
+	}
+
+	fn signed(feeder: u64, round: Round, price: u64) -> (PriceSubmissionOf<Test>, TestSignature) {
+		let submission = PriceSubmission { feeder, asset_id: ASSET, round, price };
+		let signature = TestSignature(feeder, submission.encode());
+		(submission, signature)
+	}
+
+	#[derive(Clone, Eq, PartialEq)]
+	pub struct Test;
+	impl system::Trait for Test {
+		type Origin = Origin;
+		type Index = u64;
+		type BlockNumber = u64;
+		type Hash = H256;
+		type Hashing = BlakeTwo256;
+		type Digest = Digest;
+		type AccountId = u64;
+		type Lookup = IdentityLookup<Self::AccountId>;
+		type Header = Header;
+		type Event = ();
+		type Log = DigestItem;
+	}
+	impl generic_asset::Trait for Test {
+		type Balance = u64;
+		type AssetId = u32;
+		type Event = ();
+	}
+	impl Trait for Test {
+		type Event = ();
+		type Signature = TestSignature;
+	}
+	type Oracle = Module<Test>;
+	type System = system::Module<Test>;
+
+	const ASSET: u32 = 16000;
+
+	// Feeders 1, 2 and 3, rounds of 10 blocks needing 2 submissions, prices stale after 15 blocks
+	fn new_test_ext() -> runtime_io::TestExternalities<Blake2Hasher> {
+		let mut t = system::GenesisConfig::<Test>::default().build_storage().unwrap().0;
+		t.extend(
+			GenesisConfig::<Test> {
+				feeders: vec![1, 2, 3],
+				round_duration: 10,
+				min_submissions: 2,
+				max_price_age: 15,
+			}
+			.build_storage()
+			.unwrap()
+			.0,
+		);
+		t.into()
+	}
+
+	#[test]
+	fn median_of_odd_and_even_counts() {
+		assert_eq!(median(vec![5u64, 1, 3]), 3);
+		assert_eq!(median(vec![4u64, 1, 3, 10]), 3);
+		assert_eq!(median(vec![u64::max_value(), u64::max_value()]), u64::max_value());
+	}
+
+	#[test]
+	fn round_sets_median_price() {
+		with_externalities(&mut new_test_ext(), || {
+			System::set_block_number(12);
+			assert_ok!(Oracle::submit_price(Origin::signed(1), ASSET, 1, 100));
+			assert_ok!(Oracle::submit_price(Origin::signed(2), ASSET, 1, 120));
+			assert_ok!(Oracle::submit_price(Origin::signed(3), ASSET, 1, 400));
+
+			<Oracle as OnFinalize<u64>>::on_finalize(18);
+			assert_eq!(Oracle::price(&ASSET), None);
+			<Oracle as OnFinalize<u64>>::on_finalize(19);
+			assert_eq!(Oracle::prices(ASSET), Some(AssetPrice { price: 120, round: 1, block_number: 19 }));
+		});
+	}
+
+	#[test]
+	fn stale_prices_are_not_provided() {
+		with_externalities(&mut new_test_ext(), || {
+			System::set_block_number(12);
+			assert_ok!(Oracle::submit_price(Origin::signed(1), ASSET, 1, 100));
+			assert_ok!(Oracle::submit_price(Origin::signed(2), ASSET, 1, 120));
+			<Oracle as OnFinalize<u64>>::on_finalize(19);
+
+			System::set_block_number(34);
+			assert_eq!(Oracle::price(&ASSET), Some(110));
+			assert_eq!(Oracle::price_with_age(ASSET), Some((110, 1, 15)));
+
+			System::set_block_number(35);
+			assert_eq!(Oracle::price(&ASSET), None);
+			assert_eq!(Oracle::price_with_age(ASSET), Some((110, 1, 16)));
+
+			// Without a maximum age prices do not go stale
+			<MaxPriceAge<Test>>::put(0);
+			assert_eq!(Oracle::price(&ASSET), Some(110));
+		});
+	}
+
+	#[test]
+	fn too_few_submissions_keep_previous_price() {
+		with_externalities(&mut new_test_ext(), || {
+			System::set_block_number(5);
+			assert_ok!(Oracle::submit_price(Origin::signed(1), ASSET, 0, 100));
+			<Oracle as OnFinalize<u64>>::on_finalize(9);
+			assert_eq!(Oracle::price(&ASSET), None);
+			assert!(Oracle::submissions(ASSET).is_empty());
+		});
+	}
+
+	#[test]
+	fn rejects_stale_rounds_and_unknown_feeders() {
+		with_externalities(&mut new_test_ext(), || {
+			System::set_block_number(25);
+			assert_noop!(Oracle::submit_price(Origin::signed(1), ASSET, 1, 100), "submission is for a stale round");
+			assert_noop!(Oracle::submit_price(Origin::signed(4), ASSET, 2, 100), "caller is not a feeder");
+			assert_ok!(Oracle::submit_price(Origin::signed(1), ASSET, 2, 100));
+			assert_noop!(
+				Oracle::submit_price(Origin::signed(1), ASSET, 2, 100),
+				"feeder has already submitted this round"
+			);
+		});
+	}
+	#[test]
+	fn unsigned_submissions_need_a_feeder_signature() {
+		with_externalities(&mut new_test_ext(), || {
+			System::set_block_number(12);
+			let (submission, signature) = signed(1, 1, 100);
+			let call = Call::submit_price_unsigned(submission.clone(), signature.clone());
+			match Oracle::validate_unsigned(&call) {
+				TransactionValidity::Valid { provides, longevity, .. } => {
+					assert_eq!(provides, vec![(1u64, ASSET, 1u64).encode()]);
+					assert_eq!(longevity, 8);
+				}
+				_ => panic!("expected a valid submission"),
+			}
+
+			// Signed by another account, or by a non-feeder
+			let forged = Call::submit_price_unsigned(submission.clone(), TestSignature(2, submission.encode()));
+			assert_eq!(Oracle::validate_unsigned(&forged), TransactionValidity::Invalid(INVALID_SIGNATURE));
+			let (outsider, outsider_signature) = signed(4, 1, 100);
+			let outsider = Call::submit_price_unsigned(outsider, outsider_signature);
+			assert_eq!(Oracle::validate_unsigned(&outsider), TransactionValidity::Invalid(INVALID_SUBMISSION));
+			assert_eq!(
+				Oracle::validate_unsigned(&Call::remove_feeder(1)),
+				TransactionValidity::Invalid(NOT_UNSIGNED_SUBMISSION)
+			);
+
+			assert_noop!(
+				Oracle::submit_price_unsigned(Origin::signed(1), submission.clone(), signature.clone()),
+				"bad origin: expected an unsigned transaction"
+			);
+			assert_noop!(
+				Oracle::submit_price_unsigned(Origin::NONE, submission.clone(), TestSignature(2, submission.encode())),
+				"invalid feeder signature"
+			);
+			assert_ok!(Oracle::submit_price_unsigned(Origin::NONE, submission, signature));
+			assert_eq!(Oracle::submissions(ASSET), vec![(1, 100)]);
+			assert_eq!(Oracle::validate_unsigned(&call), TransactionValidity::Invalid(INVALID_SUBMISSION));
+		});
+	}
+}
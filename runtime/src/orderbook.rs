@@ -0,0 +1,600 @@
+// Copyright (C) 2019 Centrality Investments Limited
+// This file is part of CENNZnet.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//!
+//! Limit order book over generic assets
+//!
+//! A market is a `(base, quote)` asset pair. Orders are priced in units of `quote` per unit of
+//! `base` and their funds are reserved on placement: a buy reserves `price * amount` of `quote`,
+//! a sell reserves `amount` of `base`.
+//!
+//! Orders are not matched on placement. Markets touched during a block are matched in
+//! `on_finalize` with price-time priority, trading at the price of the older (maker) order.
+//!
+//! To keep books from being filled with dust, each open order also holds `OrderDeposit` of the
+//! spending asset in reserve until it is filled or cancelled, and an account has at most
+//! `MAX_ORDERS_PER_ACCOUNT` open orders in a market.
+//!
+use client::decl_runtime_apis;
+use parity_codec::{Codec, Decode, Encode};
+use rstd::prelude::*;
+use runtime_primitives::traits::{CheckedMul, Saturating, Zero};
+use support::{decl_event, decl_module, decl_storage, dispatch::Result, ensure, StorageMap, StorageValue};
+use system::ensure_signed;
+
+/// Maximum number of open orders on either side of a market
+pub const MAX_ORDERS_PER_SIDE: usize = 1_000;
+/// Maximum number of open orders of a single account in a market
+pub const MAX_ORDERS_PER_ACCOUNT: u32 = 32;
+/// Maximum number of fills executed across all markets in a single block
+pub const MAX_FILLS_PER_BLOCK: u32 = 500;
+
+/// The module's configuration trait.
+pub trait Trait: generic_asset::Trait {
+	/// The overarching event type.
+	type Event: From<Event<Self>> + Into<<Self as system::Trait>::Event>;
+}
+
+/// Unique identifier of an order
+pub type OrderId = u64;
+
+type AssetIdOf<T> = <T as generic_asset::Trait>::AssetId;
+type BalanceOf<T> = <T as generic_asset::Trait>::Balance;
+
+/// The side of the book an order rests on
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub enum Side {
+	/// Buy `base` paying `quote`
+	Buy,
+	/// Sell `base` receiving `quote`
+	Sell,
+}
+
+/// An open limit order
+#[derive(Encode, Decode, Clone, PartialEq)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct Order<AccountId, AssetId, Balance> {
+	/// The account which placed the order
+	pub owner: AccountId,
+	/// The asset being bought or sold
+	pub base: AssetId,
+	/// The asset `price` is denominated in
+	pub quote: AssetId,
+	/// Buy or sell
+	pub side: Side,
+	/// Units of `quote` per unit of `base`
+	pub price: Balance,
+	/// The unfilled amount of `base`
+	pub remaining: Balance,
+}
+
+type OrderOf<T> = Order<<T as system::Trait>::AccountId, AssetIdOf<T>, BalanceOf<T>>;
+
+decl_storage! {
+	trait Store for Module<T: Trait> as OrderBook {
+		/// The next order ID to be assigned
+		pub NextOrderId get(next_order_id): OrderId;
+		/// Open orders by ID
+		pub Orders get(orders): map OrderId => Option<OrderOf<T>>;
+		/// Bids of a market as (price, order ID), best price first then oldest first
+		pub Bids get(bids): map (AssetIdOf<T>, AssetIdOf<T>) => Vec<(BalanceOf<T>, OrderId)>;
+		/// Asks of a market as (price, order ID), best price first then oldest first
+		pub Asks get(asks): map (AssetIdOf<T>, AssetIdOf<T>) => Vec<(BalanceOf<T>, OrderId)>;
+		/// Markets with new orders this block, matched in `on_finalize`
+		PendingMarkets get(pending_markets): Vec<(AssetIdOf<T>, AssetIdOf<T>)>;
+		/// Number of open orders of an account in a market
+		pub OrderCount get(order_count): map (T::AccountId, (AssetIdOf<T>, AssetIdOf<T>)) => u32;
+		/// The spending asset reserved from the owner of an open order
+		pub Deposits get(deposit_of): map OrderId => BalanceOf<T>;
+		/// The deposit reserved for each new order
+		pub OrderDeposit get(order_deposit) config(): BalanceOf<T>;
+	}
+}
+
+decl_module! {
+	pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+		fn deposit_event<T>() = default;
+
+		/// Place a limit order to buy or sell `amount` of `base` at `price` units of `quote` each.
+		/// The funds required to fill the order and the order deposit are reserved until it is
+		/// filled or cancelled.
+		pub fn place_order(
+			origin,
+			base: AssetIdOf<T>,
+			quote: AssetIdOf<T>,
+			side: Side,
+			#[compact] price: BalanceOf<T>,
+			#[compact] amount: BalanceOf<T>
+		) -> Result {
+			let owner = ensure_signed(origin)?;
+			ensure!(base != quote, "base and quote assets must differ");
+			ensure!(!price.is_zero(), "price should be non-zero");
+			ensure!(!amount.is_zero(), "amount should be non-zero");
+
+			let market = (base, quote);
+			let mut book = match side {
+				Side::Buy => Self::bids(market),
+				Side::Sell => Self::asks(market),
+			};
+			ensure!(book.len() < MAX_ORDERS_PER_SIDE, "order book side is full");
+			ensure!(
+				Self::order_count((owner.clone(), market)) < MAX_ORDERS_PER_ACCOUNT,
+				"too many open orders for the account"
+			);
+
+			let (reserve_asset, reserve_amount) = match side {
+				Side::Buy => (quote, price.checked_mul(&amount).ok_or("order value overflow")?),
+				Side::Sell => (base, amount),
+			};
+			let deposit = Self::order_deposit();
+			let spending_asset_id = <generic_asset::Module<T>>::spending_asset_id();
+			<generic_asset::Module<T>>::reserve(&spending_asset_id, &owner, deposit)?;
+			if let Err(e) = <generic_asset::Module<T>>::reserve(&reserve_asset, &owner, reserve_amount) {
+				<generic_asset::Module<T>>::unreserve(&spending_asset_id, &owner, deposit);
+				return Err(e);
+			}
+
+			let order_id = Self::next_order_id();
+			<NextOrderId<T>>::put(order_id + 1);
+			<Deposits<T>>::insert(order_id, deposit);
+			<OrderCount<T>>::mutate((owner.clone(), market), |count| *count += 1);
+
+			// Keep the book sorted by price priority, new orders go behind equal prices
+			let position = book
+				.iter()
+				.position(|(p, _)| match side {
+					Side::Buy => *p < price,
+					Side::Sell => *p > price,
+				})
+				.unwrap_or(book.len());
+			book.insert(position, (price, order_id));
+			match side {
+				Side::Buy => <Bids<T>>::insert(market, book),
+				Side::Sell => <Asks<T>>::insert(market, book),
+			}
+
+			<Orders<T>>::insert(
+				order_id,
+				Order { owner: owner.clone(), base, quote, side, price, remaining: amount },
+			);
+			<PendingMarkets<T>>::mutate(|markets| {
+				if !markets.contains(&market) {
+					markets.push(market)
+				}
+			});
+
+			Self::deposit_event(RawEvent::OrderPlaced(order_id, owner, base, quote, side, price, amount));
+			Ok(())
+		}
+
+		/// Cancel an open order, releasing the reserved funds of its unfilled amount
+		pub fn cancel_order(origin, #[compact] order_id: OrderId) -> Result {
+			let who = ensure_signed(origin)?;
+			let order = Self::orders(order_id).ok_or("order does not exist")?;
+			ensure!(order.owner == who, "only the owner can cancel an order");
+
+			let (asset_id, amount) = Self::reserved_for(&order).ok_or("order value overflow")?;
+			<generic_asset::Module<T>>::unreserve(&asset_id, &who, amount);
+			Self::remove_order(order_id, &order);
+
+			Self::deposit_event(RawEvent::OrderCancelled(order_id, who));
+			Ok(())
+		}
+
+		fn on_finalize(_n: T::BlockNumber) {
+			let mut fills = 0;
+			for (base, quote) in <PendingMarkets<T>>::take() {
+				if fills >= MAX_FILLS_PER_BLOCK {
+					// Markets left unmatched are picked up again next block
+					<PendingMarkets<T>>::mutate(|markets| markets.push((base, quote)));
+					continue;
+				}
+				fills += Self::match_market(base, quote, MAX_FILLS_PER_BLOCK - fills);
+			}
+		}
+	}
+}
+
+decl_event!(
+	pub enum Event<T> where
+		AccountId = <T as system::Trait>::AccountId,
+		AssetId = AssetIdOf<T>,
+		Balance = BalanceOf<T>
+	{
+		/// An order was placed: (order ID, owner, base, quote, side, price, amount)
+		OrderPlaced(OrderId, AccountId, AssetId, AssetId, Side, Balance, Balance),
+		/// An order was cancelled: (order ID, owner)
+		OrderCancelled(OrderId, AccountId),
+		/// Two orders were matched: (bid ID, ask ID, buyer, seller, base, quote, price, amount)
+		OrderFilled(OrderId, OrderId, AccountId, AccountId, AssetId, AssetId, Balance, Balance),
+	}
+);
+
+impl<T: Trait> Module<T> {
+	/// Match crossing orders of the `(base, quote)` market, executing at most `max_fills` fills.
+	/// Returns the number of fills executed.
+	fn match_market(base: AssetIdOf<T>, quote: AssetIdOf<T>, max_fills: u32) -> u32 {
+		let market = (base, quote);
+		let mut bids = Self::bids(market);
+		let mut asks = Self::asks(market);
+		let mut fills = 0;
+
+		while fills < max_fills && !bids.is_empty() && !asks.is_empty() {
+			let (bid_price, bid_id) = bids[0];
+			let (ask_price, ask_id) = asks[0];
+			if bid_price < ask_price {
+				break;
+			}
+			let (mut bid, mut ask) = match (Self::orders(bid_id), Self::orders(ask_id)) {
+				(Some(bid), Some(ask)) => (bid, ask),
+				_ => break,
+			};
+
+			// The older order was resting on the book and sets the trade price
+			let price = if bid_id < ask_id { bid_price } else { ask_price };
+			let amount = rstd::cmp::min(bid.remaining, ask.remaining);
+			let (value, bid_value) = match (price.checked_mul(&amount), bid_price.checked_mul(&amount)) {
+				(Some(value), Some(bid_value)) => (value, bid_value),
+				_ => break,
+			};
+
+			<generic_asset::Module<T>>::repatriate_reserved(&quote, &bid.owner, &ask.owner, value);
+			<generic_asset::Module<T>>::repatriate_reserved(&base, &ask.owner, &bid.owner, amount);
+			// Release the buyer's price improvement
+			if bid_value > value {
+				<generic_asset::Module<T>>::unreserve(&quote, &bid.owner, bid_value - value);
+			}
+
+			bid.remaining -= amount;
+			ask.remaining -= amount;
+			fills += 1;
+			Self::deposit_event(RawEvent::OrderFilled(
+				bid_id,
+				ask_id,
+				bid.owner.clone(),
+				ask.owner.clone(),
+				base,
+				quote,
+				price,
+				amount,
+			));
+
+			if bid.remaining.is_zero() {
+				<Orders<T>>::remove(bid_id);
+				Self::release_deposit(bid_id, &bid);
+				bids.remove(0);
+			} else {
+				<Orders<T>>::insert(bid_id, bid);
+			}
+			if ask.remaining.is_zero() {
+				<Orders<T>>::remove(ask_id);
+				Self::release_deposit(ask_id, &ask);
+				asks.remove(0);
+			} else {
+				<Orders<T>>::insert(ask_id, ask);
+			}
+		}
+
+		if fills == max_fills && !bids.is_empty() && !asks.is_empty() {
+			<PendingMarkets<T>>::mutate(|markets| markets.push(market));
+		}
+		<Bids<T>>::insert(market, bids);
+		<Asks<T>>::insert(market, asks);
+		fills
+	}
+
+	/// The asset and amount held in reserve for the unfilled part of `order`
+	fn reserved_for(order: &OrderOf<T>) -> Option<(AssetIdOf<T>, BalanceOf<T>)> {
+		match order.side {
+			Side::Buy => order.price.checked_mul(&order.remaining).map(|value| (order.quote, value)),
+			Side::Sell => Some((order.base, order.remaining)),
+		}
+	}
+
+	/// Remove `order` from storage and from its side of the book
+	fn remove_order(order_id: OrderId, order: &OrderOf<T>) {
+		let market = (order.base, order.quote);
+		let retain = |book: &mut Vec<(BalanceOf<T>, OrderId)>| book.retain(|(_, id)| *id != order_id);
+		match order.side {
+			Side::Buy => <Bids<T>>::mutate(market, retain),
+			Side::Sell => <Asks<T>>::mutate(market, retain),
+		}
+		<Orders<T>>::remove(order_id);
+		Self::release_deposit(order_id, order);
+	}
+
+	/// Return the deposit of a closed order to its owner
+	fn release_deposit(order_id: OrderId, order: &OrderOf<T>) {
+		let spending_asset_id = <generic_asset::Module<T>>::spending_asset_id();
+		<generic_asset::Module<T>>::unreserve(&spending_asset_id, &order.owner, <Deposits<T>>::take(order_id));
+		<OrderCount<T>>::mutate((order.owner.clone(), (order.base, order.quote)), |count| {
+			*count = count.saturating_sub(1)
+		});
+	}
+
+	/// Aggregate the book of a market into at most `levels` price levels per side.
+	/// Returns (bids, asks) as lists of (price, total amount), best price first.
+	pub fn book_depth(
+		base: AssetIdOf<T>,
+		quote: AssetIdOf<T>,
+		levels: u32,
+	) -> (Vec<(BalanceOf<T>, BalanceOf<T>)>, Vec<(BalanceOf<T>, BalanceOf<T>)>) {
+		let market = (base, quote);
+		(
+			Self::aggregate(Self::bids(market), levels),
+			Self::aggregate(Self::asks(market), levels),
+		)
+	}
+
+	fn aggregate(book: Vec<(BalanceOf<T>, OrderId)>, levels: u32) -> Vec<(BalanceOf<T>, BalanceOf<T>)> {
+		let mut depth: Vec<(BalanceOf<T>, BalanceOf<T>)> = Vec::new();
+		for (price, order_id) in book {
+			let remaining = Self::orders(order_id).map(|o| o.remaining).unwrap_or_else(Zero::zero);
+			match depth.last_mut() {
+				Some((level_price, total)) if *level_price == price => *total = total.saturating_add(remaining),
+				_ => {
+					if depth.len() >= levels as usize {
+						break;
+					}
+					depth.push((price, remaining))
+				}
+			}
+		}
+		depth
+	}
+}
+
+decl_runtime_apis! {
+	/// The API to query the order book of a market
+	pub trait OrderBookApi<AssetId, Balance> where
+		AssetId: Codec,
+		Balance: Codec,
+	{
+		/// Up to `levels` aggregated price levels of (bids, asks) as (price, amount), best first
+		fn book_depth(base: AssetId, quote: AssetId, levels: u32) -> (Vec<(Balance, Balance)>, Vec<(Balance, Balance)>);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use primitives::{Blake2Hasher, H256};
+	use runtime_io::with_externalities;
+	use runtime_primitives::{
+		testing::{Digest, DigestItem, Header},
+		traits::{BlakeTwo256, IdentityLookup, OnFinalize},
+		BuildStorage,
+	};
+	use support::{assert_noop, assert_ok, impl_outer_event, impl_outer_origin};
+
+	impl_outer_origin! {
+		pub enum Origin for Test {}
+	}
+
+	mod orderbook {
+		pub use super::super::Event;
+	}
+
+	impl_outer_event! {
+		pub enum TestEvent for Test {
+			generic_asset<T>, orderbook<T>,
+		}
+	}
+
+	#[derive(Clone, Eq, PartialEq)]
+	pub struct Test;
+	impl system::Trait for Test {
+		type Origin = Origin;
+		type Index = u64;
+		type BlockNumber = u64;
+		type Hash = H256;
+		type Hashing = BlakeTwo256;
+		type Digest = Digest;
+		type AccountId = u64;
+		type Lookup = IdentityLookup<Self::AccountId>;
+		type Header = Header;
+		type Event = TestEvent;
+		type Log = DigestItem;
+	}
+	impl generic_asset::Trait for Test {
+		type Balance = u64;
+		type AssetId = u32;
+		type Event = TestEvent;
+	}
+	impl Trait for Test {
+		type Event = TestEvent;
+	}
+	type OrderBook = Module<Test>;
+	type GenericAsset = generic_asset::Module<Test>;
+	type System = system::Module<Test>;
+
+	const BASE: u32 = 16000;
+	const QUOTE: u32 = 16001;
+	const MARKET: (u32, u32) = (BASE, QUOTE);
+
+	fn new_test_ext() -> runtime_io::TestExternalities<Blake2Hasher> {
+		new_test_ext_with_deposit(0)
+	}
+
+	// Accounts 1 to 20 hold 1,000,000 of each asset
+	fn new_test_ext_with_deposit(order_deposit: u64) -> runtime_io::TestExternalities<Blake2Hasher> {
+		let mut t = system::GenesisConfig::<Test>::default().build_storage().unwrap().0;
+		t.extend(
+			generic_asset::GenesisConfig::<Test> {
+				assets: vec![BASE, QUOTE],
+				initial_balance: 1_000_000,
+				endowed_accounts: (1..=20).collect(),
+				next_asset_id: 17000,
+				create_asset_stake: 0,
+				staking_asset_id: BASE,
+				spending_asset_id: QUOTE,
+			}
+			.build_storage()
+			.unwrap()
+			.0,
+		);
+		t.extend(GenesisConfig::<Test> { order_deposit }.build_storage().unwrap().0);
+		t.into()
+	}
+
+	fn fills() -> Vec<RawEvent<u64, u32, u64>> {
+		System::events()
+			.into_iter()
+			.filter_map(|record| match record.event {
+				TestEvent::orderbook(event @ RawEvent::OrderFilled(..)) => Some(event),
+				_ => None,
+			})
+			.collect()
+	}
+
+	#[test]
+	fn placing_an_order_reserves_funds() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_ok!(OrderBook::place_order(Origin::signed(1), BASE, QUOTE, Side::Buy, 10, 5));
+			assert_ok!(OrderBook::place_order(Origin::signed(2), BASE, QUOTE, Side::Sell, 20, 7));
+			assert_eq!(GenericAsset::reserved_balance(&QUOTE, &1), 50);
+			assert_eq!(GenericAsset::reserved_balance(&BASE, &2), 7);
+
+			assert_ok!(OrderBook::cancel_order(Origin::signed(1), 0));
+			assert_eq!(GenericAsset::reserved_balance(&QUOTE, &1), 0);
+			assert_noop!(OrderBook::cancel_order(Origin::signed(1), 1), "only the owner can cancel an order");
+		});
+	}
+
+	#[test]
+	fn partial_fill_trades_at_maker_price() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_ok!(OrderBook::place_order(Origin::signed(1), BASE, QUOTE, Side::Sell, 10, 100));
+			assert_ok!(OrderBook::place_order(Origin::signed(2), BASE, QUOTE, Side::Buy, 12, 40));
+			OrderBook::on_finalize(1);
+
+			// The ask is older, so 40 trade at its price and the buyer's improvement is released
+			assert_eq!(fills(), vec![RawEvent::OrderFilled(1, 0, 2, 1, BASE, QUOTE, 10, 40)]);
+			assert_eq!(GenericAsset::free_balance(&QUOTE, &2), 1_000_000 - 400);
+			assert_eq!(GenericAsset::reserved_balance(&QUOTE, &2), 0);
+			assert_eq!(GenericAsset::free_balance(&BASE, &2), 1_000_000 + 40);
+			assert_eq!(GenericAsset::free_balance(&QUOTE, &1), 1_000_000 + 400);
+			assert_eq!(GenericAsset::reserved_balance(&BASE, &1), 60);
+
+			// The bid is filled, the rest of the ask stays on the book
+			assert_eq!(OrderBook::orders(1), None);
+			assert_eq!(OrderBook::orders(0).map(|o| o.remaining), Some(60));
+			assert_eq!(OrderBook::bids(MARKET), vec![]);
+			assert_eq!(OrderBook::asks(MARKET), vec![(10, 0)]);
+			assert_eq!(OrderBook::pending_markets(), vec![]);
+		});
+	}
+
+	#[test]
+	fn orders_not_crossing_are_not_filled() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_ok!(OrderBook::place_order(Origin::signed(1), BASE, QUOTE, Side::Sell, 11, 10));
+			assert_ok!(OrderBook::place_order(Origin::signed(2), BASE, QUOTE, Side::Buy, 10, 10));
+			OrderBook::on_finalize(1);
+
+			assert_eq!(fills(), vec![]);
+			assert_eq!(OrderBook::book_depth(BASE, QUOTE, 10), (vec![(10, 10)], vec![(11, 10)]));
+		});
+	}
+
+	#[test]
+	fn fills_beyond_block_limit_carry_over() {
+		with_externalities(&mut new_test_ext(), || {
+			// Spread the asks over enough accounts to stay within the per-account limit
+			for i in 0..=MAX_FILLS_PER_BLOCK {
+				let seller = 3 + u64::from(i / MAX_ORDERS_PER_ACCOUNT);
+				assert_ok!(OrderBook::place_order(Origin::signed(seller), BASE, QUOTE, Side::Sell, 1, 1));
+			}
+			let bid_id = OrderBook::next_order_id();
+			let amount = u64::from(MAX_FILLS_PER_BLOCK) + 1;
+			assert_ok!(OrderBook::place_order(Origin::signed(2), BASE, QUOTE, Side::Buy, 1, amount));
+
+			OrderBook::on_finalize(1);
+			assert_eq!(fills().len(), MAX_FILLS_PER_BLOCK as usize);
+			assert_eq!(OrderBook::orders(bid_id).map(|o| o.remaining), Some(1));
+			assert_eq!(OrderBook::pending_markets(), vec![MARKET]);
+
+			OrderBook::on_finalize(2);
+			assert_eq!(fills().len(), MAX_FILLS_PER_BLOCK as usize + 1);
+			assert_eq!(OrderBook::orders(bid_id), None);
+			assert_eq!(OrderBook::pending_markets(), vec![]);
+			assert_eq!(GenericAsset::free_balance(&BASE, &2), 1_000_000 + amount);
+		});
+	}
+
+	#[test]
+	fn order_deposit_is_held_until_filled_or_cancelled() {
+		with_externalities(&mut new_test_ext_with_deposit(100), || {
+			assert_ok!(OrderBook::place_order(Origin::signed(1), BASE, QUOTE, Side::Sell, 10, 5));
+			assert_ok!(OrderBook::place_order(Origin::signed(1), BASE, QUOTE, Side::Sell, 11, 5));
+			assert_ok!(OrderBook::place_order(Origin::signed(2), BASE, QUOTE, Side::Buy, 10, 5));
+			assert_eq!(GenericAsset::reserved_balance(&QUOTE, &1), 200);
+			assert_eq!(GenericAsset::reserved_balance(&QUOTE, &2), 100 + 50);
+			assert_eq!(OrderBook::order_count((1, MARKET)), 2);
+
+			OrderBook::on_finalize(1);
+			assert_eq!(GenericAsset::reserved_balance(&QUOTE, &1), 100);
+			assert_eq!(GenericAsset::reserved_balance(&QUOTE, &2), 0);
+			assert_eq!(OrderBook::order_count((2, MARKET)), 0);
+
+			assert_ok!(OrderBook::cancel_order(Origin::signed(1), 1));
+			assert_eq!(GenericAsset::reserved_balance(&QUOTE, &1), 0);
+			assert_eq!(OrderBook::order_count((1, MARKET)), 0);
+		});
+	}
+
+	#[test]
+	fn placing_an_order_requires_deposit() {
+		with_externalities(&mut new_test_ext_with_deposit(100), || {
+			assert!(OrderBook::place_order(Origin::signed(21), BASE, QUOTE, Side::Sell, 10, 5).is_err());
+			assert_eq!(OrderBook::asks(MARKET), vec![]);
+			assert_eq!(OrderBook::order_count((21, MARKET)), 0);
+		});
+	}
+
+	#[test]
+	fn open_orders_are_capped_per_account_and_market() {
+		with_externalities(&mut new_test_ext(), || {
+			for _ in 0..MAX_ORDERS_PER_ACCOUNT {
+				assert_ok!(OrderBook::place_order(Origin::signed(1), BASE, QUOTE, Side::Sell, 10, 1));
+			}
+			assert_noop!(
+				OrderBook::place_order(Origin::signed(1), BASE, QUOTE, Side::Buy, 5, 1),
+				"too many open orders for the account"
+			);
+			assert_ok!(OrderBook::place_order(Origin::signed(1), QUOTE, BASE, Side::Buy, 5, 1));
+			assert_ok!(OrderBook::place_order(Origin::signed(2), BASE, QUOTE, Side::Sell, 10, 1));
+
+			// Cancelling an order makes room for another
+			assert_ok!(OrderBook::cancel_order(Origin::signed(1), 0));
+			assert_ok!(OrderBook::place_order(Origin::signed(1), BASE, QUOTE, Side::Buy, 5, 1));
+		});
+	}
+
+	#[test]
+	fn book_depth_aggregates_price_levels() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_ok!(OrderBook::place_order(Origin::signed(1), BASE, QUOTE, Side::Buy, 10, 5));
+			assert_ok!(OrderBook::place_order(Origin::signed(2), BASE, QUOTE, Side::Buy, 10, 7));
+			assert_ok!(OrderBook::place_order(Origin::signed(3), BASE, QUOTE, Side::Buy, 12, 1));
+			assert_ok!(OrderBook::place_order(Origin::signed(3), BASE, QUOTE, Side::Buy, 9, 1));
+
+			assert_eq!(OrderBook::book_depth(BASE, QUOTE, 2).0, vec![(12, 1), (10, 12)]);
+			assert_eq!(OrderBook::book_depth(BASE, QUOTE, 10).0.len(), 3);
+		});
+	}
+}
@@ -0,0 +1,250 @@
+// Copyright (C) 2019 Centrality Investments Limited
+// This file is part of CENNZnet.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//!
+//! Proxy accounts
+//!
+//! A principal account may authorize a proxy account to dispatch calls on its behalf.
+//! Each authorization carries a `ProxyType` which restricts the calls the proxy may make.
+//! The proxy signs and pays for the extrinsic, the inner call is dispatched with the principal
+//! as its signed origin.
+//!
+use crate::dispatch::DispatchAs;
+use parity_codec::{Decode, Encode};
+use rstd::prelude::*;
+use runtime_primitives::traits::Dispatchable;
+use support::{decl_event, decl_module, decl_storage, dispatch::Result, ensure, Parameter, StorageMap};
+use system::ensure_signed;
+
+/// The module's configuration trait.
+pub trait Trait: system::Trait {
+	/// The overarching event type.
+	type Event: From<Event<Self>> + Into<<Self as system::Trait>::Event>;
+	/// The call type which may be dispatched by a proxy
+	type Call: Parameter + Dispatchable<Origin = <Self as system::Trait>::Origin>;
+	/// Decides which calls each `ProxyType` may dispatch
+	type ProxyFilter: ProxyFilter<Self::AccountId, <Self as Trait>::Call>;
+	/// Dispatches calls on behalf of the principal
+	type Dispatcher: DispatchAs<Self::AccountId, <Self as Trait>::Call>;
+}
+
+/// The kinds of calls a proxy is allowed to dispatch
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub enum ProxyType {
+	/// Any call
+	Any,
+	/// Staking calls only
+	Staking,
+	/// Exchange and trading calls paying out to the principal only
+	Trading,
+}
+
+/// Decides whether a proxy of a given type may dispatch a call
+pub trait ProxyFilter<AccountId, Call> {
+	/// Return whether a proxy of `proxy_type` may dispatch `call` on behalf of `principal`
+	fn allows(proxy_type: ProxyType, principal: &AccountId, call: &Call) -> bool;
+}
+
+decl_storage! {
+	trait Store for Module<T: Trait> as Proxy {
+		/// The authorization granted by a principal to a proxy, keyed by (principal, proxy)
+		pub Proxies get(proxies): map (T::AccountId, T::AccountId) => Option<ProxyType>;
+	}
+}
+
+decl_module! {
+	pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+		fn deposit_event<T>() = default;
+
+		/// Authorize `proxy` to dispatch calls of `proxy_type` on behalf of the caller.
+		/// Replaces any existing authorization of `proxy`.
+		pub fn add_proxy(origin, proxy: T::AccountId, proxy_type: ProxyType) -> Result {
+			let principal = ensure_signed(origin)?;
+			ensure!(principal != proxy, "an account cannot be its own proxy");
+
+			<Proxies<T>>::insert((principal.clone(), proxy.clone()), proxy_type);
+			Self::deposit_event(RawEvent::ProxyAdded(principal, proxy, proxy_type));
+			Ok(())
+		}
+
+		/// Revoke the authorization of `proxy`
+		pub fn remove_proxy(origin, proxy: T::AccountId) -> Result {
+			let principal = ensure_signed(origin)?;
+			let key = (principal.clone(), proxy.clone());
+			ensure!(<Proxies<T>>::exists(&key), "account is not a proxy of the caller");
+
+			<Proxies<T>>::remove(&key);
+			Self::deposit_event(RawEvent::ProxyRemoved(principal, proxy));
+			Ok(())
+		}
+
+		/// Dispatch `call` with `principal` as the signed origin.
+		/// The caller must be a proxy of `principal` whose type allows `call`.
+		pub fn proxy(origin, principal: T::AccountId, call: Box<<T as Trait>::Call>) -> Result {
+			let proxy = ensure_signed(origin)?;
+			let proxy_type = Self::proxies((principal.clone(), proxy.clone())).ok_or("caller is not a proxy of the principal")?;
+			ensure!(T::ProxyFilter::allows(proxy_type, &principal, &call), "call is not allowed for this proxy type");
+
+			let ok = T::Dispatcher::dispatch_as(principal.clone(), *call).is_ok();
+			Self::deposit_event(RawEvent::ProxyExecuted(principal, proxy, ok));
+			Ok(())
+		}
+	}
+}
+
+decl_event!(
+	pub enum Event<T> where AccountId = <T as system::Trait>::AccountId {
+		/// A proxy was authorized: (principal, proxy, proxy type)
+		ProxyAdded(AccountId, AccountId, ProxyType),
+		/// A proxy authorization was revoked: (principal, proxy)
+		ProxyRemoved(AccountId, AccountId),
+		/// A proxy dispatched a call: (principal, proxy, success)
+		ProxyExecuted(AccountId, AccountId, bool),
+	}
+);
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use primitives::{Blake2Hasher, H256};
+	use runtime_io::with_externalities;
+	use runtime_primitives::{
+		testing::{Digest, DigestItem, Header},
+		traits::{BlakeTwo256, IdentityLookup},
+		BuildStorage,
+	};
+	use std::cell::RefCell;
+	use support::{assert_noop, assert_ok, impl_outer_origin};
+
+	impl_outer_origin! {
+		pub enum Origin for Test {}
+	}
+
+	#[derive(Clone, Eq, PartialEq)]
+	pub struct Test;
+	impl system::Trait for Test {
+		type Origin = Origin;
+		type Index = u64;
+		type BlockNumber = u64;
+		type Hash = H256;
+		type Hashing = BlakeTwo256;
+		type Digest = Digest;
+		type AccountId = u64;
+		type Lookup = IdentityLookup<Self::AccountId>;
+		type Header = Header;
+		type Event = ();
+		type Log = DigestItem;
+	}
+
+	thread_local! {
+		static DISPATCHED: RefCell<Vec<(u64, TestCall)>> = RefCell::new(Vec::new());
+	}
+
+	/// Calls recorded with their origin when dispatched
+	#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+	pub enum TestCall {
+		/// Pay out to a recipient
+		Transfer(u64),
+		/// Bond funds
+		Bond,
+	}
+	impl Dispatchable for TestCall {
+		type Origin = Origin;
+		type Trait = Test;
+		fn dispatch(self, origin: Origin) -> Result {
+			let who = ensure_signed(origin)?;
+			DISPATCHED.with(|d| d.borrow_mut().push((who, self)));
+			Ok(())
+		}
+	}
+
+	// Staking proxies may bond, trading proxies may transfer to the principal only
+	pub struct TestProxyFilter;
+	impl ProxyFilter<u64, TestCall> for TestProxyFilter {
+		fn allows(proxy_type: ProxyType, principal: &u64, call: &TestCall) -> bool {
+			match (proxy_type, call) {
+				(ProxyType::Any, _) => true,
+				(ProxyType::Staking, TestCall::Bond) => true,
+				(ProxyType::Trading, TestCall::Transfer(recipient)) => recipient == principal,
+				_ => false,
+			}
+		}
+	}
+	impl Trait for Test {
+		type Event = ();
+		type Call = TestCall;
+		type ProxyFilter = TestProxyFilter;
+		type Dispatcher = ();
+	}
+	type Proxy = Module<Test>;
+
+	fn new_test_ext() -> runtime_io::TestExternalities<Blake2Hasher> {
+		DISPATCHED.with(|d| d.borrow_mut().clear());
+		system::GenesisConfig::<Test>::default().build_storage().unwrap().0.into()
+	}
+
+	fn dispatched() -> Vec<(u64, TestCall)> {
+		DISPATCHED.with(|d| d.borrow().clone())
+	}
+
+	#[test]
+	fn proxy_dispatches_as_principal() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_noop!(Proxy::add_proxy(Origin::signed(1), 1, ProxyType::Any), "an account cannot be its own proxy");
+			assert_noop!(
+				Proxy::proxy(Origin::signed(2), 1, Box::new(TestCall::Bond)),
+				"caller is not a proxy of the principal"
+			);
+
+			assert_ok!(Proxy::add_proxy(Origin::signed(1), 2, ProxyType::Any));
+			assert_ok!(Proxy::proxy(Origin::signed(2), 1, Box::new(TestCall::Transfer(3))));
+			assert_eq!(dispatched(), vec![(1, TestCall::Transfer(3))]);
+
+			assert_ok!(Proxy::remove_proxy(Origin::signed(1), 2));
+			assert_noop!(
+				Proxy::proxy(Origin::signed(2), 1, Box::new(TestCall::Bond)),
+				"caller is not a proxy of the principal"
+			);
+		});
+	}
+
+	#[test]
+	fn calls_outside_proxy_type_are_rejected() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_ok!(Proxy::add_proxy(Origin::signed(1), 2, ProxyType::Staking));
+			assert_noop!(
+				Proxy::proxy(Origin::signed(2), 1, Box::new(TestCall::Transfer(1))),
+				"call is not allowed for this proxy type"
+			);
+			assert_ok!(Proxy::proxy(Origin::signed(2), 1, Box::new(TestCall::Bond)));
+			assert_eq!(dispatched(), vec![(1, TestCall::Bond)]);
+		});
+	}
+
+	#[test]
+	fn trading_proxy_cannot_pay_out_to_another_recipient() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_ok!(Proxy::add_proxy(Origin::signed(1), 2, ProxyType::Trading));
+			assert_noop!(
+				Proxy::proxy(Origin::signed(2), 1, Box::new(TestCall::Transfer(2))),
+				"call is not allowed for this proxy type"
+			);
+			assert_ok!(Proxy::proxy(Origin::signed(2), 1, Box::new(TestCall::Transfer(1))));
+			assert_eq!(dispatched(), vec![(1, TestCall::Transfer(1))]);
+		});
+	}
+}
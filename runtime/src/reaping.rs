@@ -0,0 +1,357 @@
+// Copyright (C) 2019 Centrality Investments Limited
+// This file is part of CENNZnet.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//!
+//! Dead accounts and dust reaping
+//!
+//! An account is dead when it holds no free or reserved balance of any generic asset and has no
+//! staking bond. Every asset id from the lowest of the staking, spending and tracked assets up to
+//! the next asset id is checked, together with tracked assets beyond it. The scan is bounded by
+//! `MAX_SCANNED_ASSETS`; while more asset ids exist no account is regarded as dead.
+//!
+//! `Indices` uses this module as its `IsDeadAccount`, so the index of a dead account may be
+//! reassigned to an account claiming an index with `claim_index`. An account left with only dust
+//! may be reaped by anyone. The dust is transferred to the dust receiver set by genesis config or
+//! root, never to the reaper.
+//!
+use rstd::prelude::*;
+use runtime_primitives::traits::{One, StaticLookup, Zero};
+use support::{
+	decl_event, decl_module, decl_storage,
+	dispatch::Result,
+	ensure,
+	traits::{IsDeadAccount, OnNewAccount},
+	StorageMap, StorageValue,
+};
+use system::{ensure_root, ensure_signed};
+
+/// The module's configuration trait.
+pub trait Trait: generic_asset::Trait {
+	/// The overarching event type.
+	type Event: From<Event<Self>> + Into<<Self as system::Trait>::Event>;
+	/// Determines whether an account has a staking bond
+	type Bond: HasBond<Self::AccountId>;
+	/// Handler for accounts claiming an index
+	type OnNewAccount: OnNewAccount<Self::AccountId>;
+}
+
+/// Determines whether an account has a staking bond
+pub trait HasBond<AccountId> {
+	/// Return whether `who` is bonded
+	fn has_bond(who: &AccountId) -> bool;
+}
+
+impl<AccountId> HasBond<AccountId> for () {
+	fn has_bond(_: &AccountId) -> bool {
+		false
+	}
+}
+
+/// The largest number of asset ids scanned for the balances of an account
+pub const MAX_SCANNED_ASSETS: u32 = 4_096;
+
+type AssetIdOf<T> = <T as generic_asset::Trait>::AssetId;
+type BalanceOf<T> = <T as generic_asset::Trait>::Balance;
+
+decl_storage! {
+	trait Store for Module<T: Trait> as Reaping {
+		/// Assets checked in addition to the staking and spending assets
+		pub TrackedAssets get(tracked_assets) config(): Vec<AssetIdOf<T>>;
+		/// The largest balance of an asset regarded as dust
+		pub DustThreshold get(dust_threshold) config(): BalanceOf<T>;
+		/// The account receiving the dust of reaped accounts
+		pub DustReceiver get(dust_receiver) config(): T::AccountId;
+		/// Accounts which have claimed an index since they were last reaped
+		pub IndexClaimed get(index_claimed): map T::AccountId => bool;
+	}
+}
+
+decl_module! {
+	pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+		fn deposit_event<T>() = default;
+
+		/// Reap `target`, which may hold no more than dust of any asset and no reserved balance or
+		/// staking bond. The dust is transferred to the dust receiver.
+		pub fn reap(origin, target: <T::Lookup as StaticLookup>::Source) -> Result {
+			let reaper = ensure_signed(origin)?;
+			let who = T::Lookup::lookup(target)?;
+			ensure!(reaper != who, "an account cannot reap itself");
+			let receiver = Self::dust_receiver();
+			ensure!(receiver != who, "the dust receiver cannot be reaped");
+			ensure!(!T::Bond::has_bond(&who), "account has a staking bond");
+
+			let assets = Self::held_assets().ok_or("too many assets to check the account")?;
+			let threshold = Self::dust_threshold();
+			for asset_id in &assets {
+				ensure!(
+					<generic_asset::Module<T>>::reserved_balance(asset_id, &who).is_zero(),
+					"account has reserved balance"
+				);
+				ensure!(
+					<generic_asset::Module<T>>::free_balance(asset_id, &who) <= threshold,
+					"account holds more than dust"
+				);
+			}
+
+			for asset_id in &assets {
+				let dust = <generic_asset::Module<T>>::free_balance(asset_id, &who);
+				if !dust.is_zero() {
+					<generic_asset::Module<T>>::make_transfer(asset_id, &who, &receiver, dust)?;
+				}
+			}
+
+			<IndexClaimed<T>>::remove(&who);
+			Self::deposit_event(RawEvent::Reaped(who, reaper));
+			Ok(())
+		}
+
+		/// Assign an account index to the caller, reusing the index of a dead account when
+		/// available. An account may claim once until it is reaped.
+		pub fn claim_index(origin) -> Result {
+			let who = ensure_signed(origin)?;
+			ensure!(!Self::index_claimed(&who), "account has already claimed an index");
+
+			<IndexClaimed<T>>::insert(&who, true);
+			T::OnNewAccount::on_new_account(&who);
+			Ok(())
+		}
+
+		/// Track `asset_id` when determining dead accounts. Must be called by root.
+		pub fn track_asset(origin, asset_id: AssetIdOf<T>) -> Result {
+			ensure_root(origin)?;
+			ensure!(!Self::assets().contains(&asset_id), "asset is already tracked");
+			<TrackedAssets<T>>::mutate(|assets| assets.push(asset_id));
+			Ok(())
+		}
+
+		/// Set the account receiving the dust of reaped accounts. Must be called by root.
+		pub fn set_dust_receiver(origin, receiver: T::AccountId) -> Result {
+			ensure_root(origin)?;
+			<DustReceiver<T>>::put(receiver);
+			Ok(())
+		}
+
+		/// Set the largest balance regarded as dust. Must be called by root.
+		pub fn set_dust_threshold(origin, #[compact] threshold: BalanceOf<T>) -> Result {
+			ensure_root(origin)?;
+			<DustThreshold<T>>::put(threshold);
+			Ok(())
+		}
+	}
+}
+
+decl_event!(
+	pub enum Event<T> where AccountId = <T as system::Trait>::AccountId {
+		/// An account was reaped: (reaped account, reaper)
+		Reaped(AccountId, AccountId),
+	}
+);
+
+impl<T: Trait> Module<T> {
+	/// The staking, spending and tracked assets
+	pub fn assets() -> Vec<AssetIdOf<T>> {
+		let mut assets = vec![
+			<generic_asset::Module<T>>::staking_asset_id(),
+			<generic_asset::Module<T>>::spending_asset_id(),
+		];
+		for asset_id in Self::tracked_assets() {
+			if !assets.contains(&asset_id) {
+				assets.push(asset_id);
+			}
+		}
+		assets
+	}
+
+	/// Every asset which may hold balances: assets from the lowest of `assets()` up to the next
+	/// asset id which are tracked or have been issued, and tracked assets beyond it. `None` if
+	/// more than `MAX_SCANNED_ASSETS` asset ids would be scanned.
+	pub fn held_assets() -> Option<Vec<AssetIdOf<T>>> {
+		let tracked = Self::assets();
+		let next = <generic_asset::Module<T>>::next_asset_id();
+		let mut asset_id = tracked.iter().min().cloned().unwrap_or_default();
+		let mut assets = Vec::new();
+		let mut scanned = 0;
+		while asset_id < next {
+			scanned += 1;
+			if scanned > MAX_SCANNED_ASSETS {
+				return None;
+			}
+			if tracked.contains(&asset_id) || !<generic_asset::Module<T>>::total_issuance(&asset_id).is_zero() {
+				assets.push(asset_id);
+			}
+			asset_id = asset_id + One::one();
+		}
+		assets.extend(tracked.into_iter().filter(|asset_id| *asset_id >= next));
+		Some(assets)
+	}
+}
+
+impl<T: Trait> IsDeadAccount<T::AccountId> for Module<T> {
+	fn is_dead_account(who: &T::AccountId) -> bool {
+		!T::Bond::has_bond(who)
+			&& Self::held_assets().map_or(false, |assets| {
+				assets.iter().all(|asset_id| {
+					<generic_asset::Module<T>>::free_balance(asset_id, who).is_zero()
+						&& <generic_asset::Module<T>>::reserved_balance(asset_id, who).is_zero()
+				})
+			})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use primitives::{Blake2Hasher, H256};
+	use runtime_io::with_externalities;
+	use runtime_primitives::{
+		testing::{Digest, DigestItem, Header},
+		traits::{BlakeTwo256, IdentityLookup},
+		BuildStorage,
+	};
+	use support::{assert_noop, assert_ok, impl_outer_origin};
+
+	impl_outer_origin! {
+		pub enum Origin for Test {}
+	}
+
+	#[derive(Clone, Eq, PartialEq)]
+	pub struct Test;
+	impl system::Trait for Test {
+		type Origin = Origin;
+		type Index = u64;
+		type BlockNumber = u64;
+		type Hash = H256;
+		type Hashing = BlakeTwo256;
+		type Digest = Digest;
+		type AccountId = u64;
+		type Lookup = IdentityLookup<Self::AccountId>;
+		type Header = Header;
+		type Event = ();
+		type Log = DigestItem;
+	}
+	impl generic_asset::Trait for Test {
+		type Balance = u64;
+		type AssetId = u32;
+		type Event = ();
+	}
+	// Account 9 is bonded
+	pub struct Bonded;
+	impl HasBond<u64> for Bonded {
+		fn has_bond(who: &u64) -> bool {
+			*who == 9
+		}
+	}
+	impl Trait for Test {
+		type Event = ();
+		type Bond = Bonded;
+		type OnNewAccount = ();
+	}
+	type Reaping = Module<Test>;
+	type GenericAsset = generic_asset::Module<Test>;
+
+	const STAKING_ASSET: u32 = 16000;
+	const SPENDING_ASSET: u32 = 16001;
+	const UNTRACKED_ASSET: u32 = 16500;
+	const OTHER_ASSET: u32 = 17000;
+	const DUST_RECEIVER: u64 = 100;
+
+	fn new_test_ext() -> runtime_io::TestExternalities<Blake2Hasher> {
+		let mut t = system::GenesisConfig::<Test>::default().build_storage().unwrap().0;
+		t.extend(
+			generic_asset::GenesisConfig::<Test> {
+				assets: vec![STAKING_ASSET, SPENDING_ASSET, UNTRACKED_ASSET, OTHER_ASSET],
+				initial_balance: 100,
+				endowed_accounts: vec![1, 2],
+				next_asset_id: 17001,
+				create_asset_stake: 0,
+				staking_asset_id: STAKING_ASSET,
+				spending_asset_id: SPENDING_ASSET,
+			}
+			.build_storage()
+			.unwrap()
+			.0,
+		);
+		t.extend(
+			GenesisConfig::<Test> {
+				tracked_assets: vec![OTHER_ASSET],
+				dust_threshold: 5,
+				dust_receiver: DUST_RECEIVER,
+			}
+			.build_storage()
+			.unwrap()
+			.0,
+		);
+		t.into()
+	}
+
+	#[test]
+	fn unknown_account_is_dead() {
+		with_externalities(&mut new_test_ext(), || {
+			assert!(Reaping::is_dead_account(&3));
+			assert!(!Reaping::is_dead_account(&1));
+			assert!(!Reaping::is_dead_account(&9));
+		});
+	}
+
+	#[test]
+	fn account_which_empties_itself_is_dead() {
+		with_externalities(&mut new_test_ext(), || {
+			for asset_id in &[STAKING_ASSET, SPENDING_ASSET] {
+				assert_ok!(GenericAsset::transfer(Origin::signed(1), *asset_id, 2, 100));
+			}
+			assert!(!Reaping::is_dead_account(&1));
+
+			assert_ok!(GenericAsset::transfer(Origin::signed(1), OTHER_ASSET, 2, 100));
+			assert!(!Reaping::is_dead_account(&1));
+
+			assert_ok!(GenericAsset::transfer(Origin::signed(1), UNTRACKED_ASSET, 2, 100));
+			assert!(Reaping::is_dead_account(&1));
+		});
+	}
+
+	#[test]
+	fn untracked_asset_keeps_account_alive() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_ok!(GenericAsset::transfer(Origin::signed(1), UNTRACKED_ASSET, 3, 50));
+			assert!(!Reaping::is_dead_account(&3));
+			assert_noop!(Reaping::reap(Origin::signed(2), 3), "account holds more than dust");
+		});
+	}
+
+	#[test]
+	fn reap_sweeps_dust_to_receiver() {
+		with_externalities(&mut new_test_ext(), || {
+			for asset_id in &[STAKING_ASSET, SPENDING_ASSET, UNTRACKED_ASSET, OTHER_ASSET] {
+				assert_ok!(GenericAsset::transfer(Origin::signed(1), *asset_id, 2, 97));
+			}
+			assert_noop!(Reaping::reap(Origin::signed(1), 1), "an account cannot reap itself");
+
+			assert_ok!(Reaping::reap(Origin::signed(3), 1));
+			assert!(Reaping::is_dead_account(&1));
+			assert_eq!(GenericAsset::free_balance(&OTHER_ASSET, &3), 0);
+			assert_eq!(GenericAsset::free_balance(&UNTRACKED_ASSET, &DUST_RECEIVER), 3);
+			assert_eq!(GenericAsset::free_balance(&OTHER_ASSET, &DUST_RECEIVER), 3);
+		});
+	}
+
+	#[test]
+	fn reap_fails_above_dust_threshold() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_noop!(Reaping::reap(Origin::signed(3), 1), "account holds more than dust");
+			assert_noop!(Reaping::reap(Origin::signed(3), 9), "account has a staking bond");
+		});
+	}
+}
@@ -0,0 +1,378 @@
+// Copyright (C) 2019 Centrality Investments Limited
+// This file is part of CENNZnet.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//!
+//! Multi-hop swap router over CENNZX-Spot exchanges
+//!
+//! Every CENNZX-Spot pool is a pair of `core_asset_id` and one other asset, so a single
+//! `asset_swap_input` already routes `A -> core -> B`. This module chains such swaps along a
+//! caller supplied path (e.g. `[A, B, C]`) in a single extrinsic.
+//!
+//! The whole route is priced against a local copy of the pool reserves before any balance is
+//! touched. Only when every hop is known to succeed and the final output meets `min_receive`
+//! are the swaps executed, each hop guarded by its exact simulated output.
+//!
+//! There are no storage transactions, so a hop failing after an earlier one was executed cannot
+//! be rolled back. The simulation therefore applies the same pricing and the same reserve checks
+//! as CENNZX-Spot itself: a route is refused up front whenever any of its hops would fail.
+//!
+use cennzx_spot::{ExchangeAddressFor, FeeRate};
+use rstd::{collections::btree_map::BTreeMap, prelude::*, result};
+use runtime_primitives::traits::{CheckedAdd, Zero};
+use support::{decl_event, decl_module, dispatch::Result, ensure};
+use system::ensure_signed;
+
+/// Maximum number of assets allowed in a swap path
+pub const MAX_PATH_LENGTH: usize = 5;
+
+/// The module's configuration trait.
+pub trait Trait: cennzx_spot::Trait {
+	/// The overarching event type.
+	type Event: From<Event<Self>> + Into<<Self as system::Trait>::Event>;
+}
+
+type AssetIdOf<T> = <T as generic_asset::Trait>::AssetId;
+type BalanceOf<T> = <T as generic_asset::Trait>::Balance;
+
+decl_module! {
+	pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+		fn deposit_event<T>() = default;
+
+		/// Sell `sell_amount` of `path[0]` and swap hop by hop along `path`, delivering at least
+		/// `min_receive` of the last asset in `path` to `recipient` (or the caller if `None`).
+		pub fn swap_with_path(
+			origin,
+			recipient: Option<T::AccountId>,
+			path: Vec<AssetIdOf<T>>,
+			#[compact] sell_amount: BalanceOf<T>,
+			#[compact] min_receive: BalanceOf<T>
+		) -> Result {
+			let trader = ensure_signed(origin)?;
+			let recipient = recipient.unwrap_or_else(|| trader.clone());
+
+			let hop_outputs = Self::quote_path(&path, sell_amount)?;
+			let amount_received = *hop_outputs.last().ok_or("path must contain at least two assets")?;
+			ensure!(amount_received >= min_receive, "route output is below the minimum receive amount");
+			ensure!(
+				<generic_asset::Module<T>>::free_balance(&path[0], &trader) >= sell_amount,
+				"insufficient balance to sell"
+			);
+
+			let fee_rate = <cennzx_spot::Module<T>>::fee_rate();
+			let last_hop = hop_outputs.len() - 1;
+			let mut amount_in = sell_amount;
+			for (i, (pair, amount_out)) in path.windows(2).zip(hop_outputs.iter()).enumerate() {
+				// Intermediate proceeds stay with the trader so they can be sold in the next hop
+				let hop_recipient = if i == last_hop { &recipient } else { &trader };
+				<cennzx_spot::Module<T>>::make_asset_swap_input(
+					&trader,
+					hop_recipient,
+					&pair[0],
+					&pair[1],
+					amount_in,
+					*amount_out,
+					fee_rate,
+				)?;
+				amount_in = *amount_out;
+			}
+
+			Self::deposit_event(RawEvent::RouteSwapped(trader, recipient, path, sell_amount, amount_received));
+			Ok(())
+		}
+	}
+}
+
+decl_event!(
+	pub enum Event<T> where
+		AccountId = <T as system::Trait>::AccountId,
+		AssetId = AssetIdOf<T>,
+		Balance = BalanceOf<T>
+	{
+		/// A multi-hop swap was executed: (trader, recipient, path, amount sold, amount received)
+		RouteSwapped(AccountId, AccountId, Vec<AssetId>, Balance, Balance),
+	}
+);
+
+impl<T: Trait> Module<T> {
+	/// Price a swap of `sell_amount` along `path` without touching any balances.
+	/// Returns the amount of asset received at each hop, in order.
+	pub fn quote_path(
+		path: &[AssetIdOf<T>],
+		sell_amount: BalanceOf<T>,
+	) -> result::Result<Vec<BalanceOf<T>>, &'static str> {
+		ensure!(path.len() >= 2, "path must contain at least two assets");
+		ensure!(path.len() <= MAX_PATH_LENGTH, "path is too long");
+		ensure!(!sell_amount.is_zero(), "sell amount should be non-zero");
+		for (i, asset) in path.iter().enumerate() {
+			ensure!(!path[i + 1..].contains(asset), "path must not visit an asset twice");
+		}
+
+		let core_asset_id = <cennzx_spot::Module<T>>::core_asset_id();
+		let fee_rate = <cennzx_spot::Module<T>>::fee_rate();
+		// Pool reserves as (core reserve, asset reserve), updated as each hop is simulated
+		let mut reserves = BTreeMap::<AssetIdOf<T>, (BalanceOf<T>, BalanceOf<T>)>::new();
+
+		let mut hop_outputs = Vec::with_capacity(path.len() - 1);
+		let mut amount_in = sell_amount;
+		for pair in path.windows(2) {
+			let (asset_sold, asset_bought) = (pair[0], pair[1]);
+			let core_amount = if asset_sold == core_asset_id {
+				amount_in
+			} else {
+				Self::simulate_to_core(&mut reserves, asset_sold, amount_in, fee_rate)?
+			};
+			let amount_out = if asset_bought == core_asset_id {
+				core_amount
+			} else {
+				Self::simulate_from_core(&mut reserves, asset_bought, core_amount, fee_rate)?
+			};
+			ensure!(!amount_out.is_zero(), "hop output is zero");
+			hop_outputs.push(amount_out);
+			amount_in = amount_out;
+		}
+
+		Ok(hop_outputs)
+	}
+
+	/// Sell `amount` of `asset_id` into its pool for core asset, updating the local `reserves`
+	fn simulate_to_core(
+		reserves: &mut BTreeMap<AssetIdOf<T>, (BalanceOf<T>, BalanceOf<T>)>,
+		asset_id: AssetIdOf<T>,
+		amount: BalanceOf<T>,
+		fee_rate: FeeRate,
+	) -> result::Result<BalanceOf<T>, &'static str> {
+		let (core_reserve, asset_reserve) = Self::pool_reserves(reserves, asset_id)?;
+		let core_out = <cennzx_spot::Module<T>>::get_input_price(amount, asset_reserve, core_reserve, fee_rate)?;
+		// CENNZX-Spot never drains a pool, so neither may the simulated hop
+		ensure!(core_reserve > core_out, "insufficient core asset liquidity");
+		let updated = (
+			core_reserve - core_out,
+			asset_reserve.checked_add(&amount).ok_or("pool reserve overflow")?,
+		);
+		reserves.insert(asset_id, updated);
+		Ok(core_out)
+	}
+
+	/// Buy `asset_id` with `core_amount` of core asset, updating the local `reserves`
+	fn simulate_from_core(
+		reserves: &mut BTreeMap<AssetIdOf<T>, (BalanceOf<T>, BalanceOf<T>)>,
+		asset_id: AssetIdOf<T>,
+		core_amount: BalanceOf<T>,
+		fee_rate: FeeRate,
+	) -> result::Result<BalanceOf<T>, &'static str> {
+		let (core_reserve, asset_reserve) = Self::pool_reserves(reserves, asset_id)?;
+		let asset_out = <cennzx_spot::Module<T>>::get_input_price(core_amount, core_reserve, asset_reserve, fee_rate)?;
+		ensure!(asset_reserve > asset_out, "insufficient asset liquidity");
+		let updated = (
+			core_reserve.checked_add(&core_amount).ok_or("pool reserve overflow")?,
+			asset_reserve - asset_out,
+		);
+		reserves.insert(asset_id, updated);
+		Ok(asset_out)
+	}
+
+	/// Current (core, asset) reserves of the pool for `asset_id`, preferring simulated values
+	fn pool_reserves(
+		reserves: &BTreeMap<AssetIdOf<T>, (BalanceOf<T>, BalanceOf<T>)>,
+		asset_id: AssetIdOf<T>,
+	) -> result::Result<(BalanceOf<T>, BalanceOf<T>), &'static str> {
+		if let Some(r) = reserves.get(&asset_id) {
+			return Ok(*r);
+		}
+		let core_asset_id = <cennzx_spot::Module<T>>::core_asset_id();
+		let exchange_address = T::ExchangeAddressGenerator::exchange_address_for(core_asset_id, asset_id);
+		let core_reserve = <generic_asset::Module<T>>::free_balance(&core_asset_id, &exchange_address);
+		let asset_reserve = <generic_asset::Module<T>>::free_balance(&asset_id, &exchange_address);
+		ensure!(
+			!core_reserve.is_zero() && !asset_reserve.is_zero(),
+			"no liquidity for an asset in the path"
+		);
+		Ok((core_reserve, asset_reserve))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use primitives::{Blake2Hasher, H256};
+	use runtime_io::with_externalities;
+	use runtime_primitives::{
+		testing::{Digest, DigestItem, Header},
+		traits::{BlakeTwo256, IdentityLookup},
+		BuildStorage,
+	};
+	use support::{assert_noop, assert_ok, impl_outer_origin};
+
+	impl_outer_origin! {
+		pub enum Origin for Test {}
+	}
+
+	#[derive(Clone, Eq, PartialEq)]
+	pub struct Test;
+	impl system::Trait for Test {
+		type Origin = Origin;
+		type Index = u64;
+		type BlockNumber = u64;
+		type Hash = H256;
+		type Hashing = BlakeTwo256;
+		type Digest = Digest;
+		type AccountId = u64;
+		type Lookup = IdentityLookup<Self::AccountId>;
+		type Header = Header;
+		type Event = ();
+		type Log = DigestItem;
+	}
+	impl generic_asset::Trait for Test {
+		type Balance = u128;
+		type AssetId = u32;
+		type Event = ();
+	}
+	// Each exchange lives at its asset id offset by 1000
+	pub struct ExchangeAddress;
+	impl ExchangeAddressFor<u32, u64> for ExchangeAddress {
+		fn exchange_address_for(_core_asset_id: u32, asset_id: u32) -> u64 {
+			1000 + asset_id as u64
+		}
+	}
+	impl cennzx_spot::Trait for Test {
+		type Call = cennzx_spot::Call<Test>;
+		type Event = ();
+		type ExchangeAddressGenerator = ExchangeAddress;
+		type BalanceToU128 = u128;
+		type U128ToBalance = u128;
+	}
+	impl Trait for Test {
+		type Event = ();
+	}
+	type Router = Module<Test>;
+	type CennzxSpot = cennzx_spot::Module<Test>;
+	type GenericAsset = generic_asset::Module<Test>;
+
+	const CORE_ASSET: u32 = 16001;
+	const ASSET_A: u32 = 17000;
+	const ASSET_B: u32 = 17001;
+	const ASSET_C: u32 = 17002;
+	// Has no exchange
+	const ASSET_D: u32 = 17003;
+
+	// Account 1 provides 10_000 of liquidity to the exchanges of A, B and C, account 2 trades
+	fn new_test_ext() -> runtime_io::TestExternalities<Blake2Hasher> {
+		let mut t = system::GenesisConfig::<Test>::default().build_storage().unwrap().0;
+		t.extend(
+			generic_asset::GenesisConfig::<Test> {
+				assets: vec![16000, CORE_ASSET, ASSET_A, ASSET_B, ASSET_C, ASSET_D],
+				initial_balance: 1_000_000,
+				endowed_accounts: vec![1, 2],
+				next_asset_id: 17004,
+				create_asset_stake: 0,
+				staking_asset_id: 16000,
+				spending_asset_id: CORE_ASSET,
+			}
+			.build_storage()
+			.unwrap()
+			.0,
+		);
+		t.extend(
+			cennzx_spot::GenesisConfig::<Test> {
+				core_asset_id: CORE_ASSET,
+				fee_rate: FeeRate::from_milli(3),
+			}
+			.build_storage()
+			.unwrap()
+			.0,
+		);
+		let mut ext: runtime_io::TestExternalities<Blake2Hasher> = t.into();
+		with_externalities(&mut ext, || {
+			for asset_id in &[ASSET_A, ASSET_B, ASSET_C] {
+				assert_ok!(CennzxSpot::add_liquidity(
+					Origin::signed(1),
+					*asset_id,
+					0,
+					10_000,
+					10_000
+				));
+			}
+		});
+		ext
+	}
+
+	#[test]
+	fn quote_matches_executed_swap() {
+		with_externalities(&mut new_test_ext(), || {
+			let path = vec![ASSET_A, ASSET_B, ASSET_C];
+			let quote = Router::quote_path(&path, 500).unwrap();
+			assert_eq!(quote.len(), 2);
+
+			assert_ok!(Router::swap_with_path(Origin::signed(2), None, path, 500, 0));
+			assert_eq!(GenericAsset::free_balance(&ASSET_A, &2), 1_000_000 - 500);
+			// The intermediate asset is bought and sold again in full
+			assert_eq!(GenericAsset::free_balance(&ASSET_B, &2), 1_000_000);
+			assert_eq!(GenericAsset::free_balance(&ASSET_C, &2), 1_000_000 + quote[1]);
+		});
+	}
+
+	#[test]
+	fn swap_delivers_to_recipient() {
+		with_externalities(&mut new_test_ext(), || {
+			let path = vec![ASSET_A, CORE_ASSET, ASSET_C];
+			let quote = Router::quote_path(&path, 500).unwrap();
+
+			assert_ok!(Router::swap_with_path(Origin::signed(2), Some(3), path, 500, quote[1]));
+			assert_eq!(GenericAsset::free_balance(&ASSET_C, &3), quote[1]);
+			assert_eq!(GenericAsset::free_balance(&ASSET_C, &2), 1_000_000);
+			assert_eq!(GenericAsset::free_balance(&CORE_ASSET, &2), 1_000_000);
+		});
+	}
+
+	#[test]
+	fn route_is_refused_if_a_later_hop_would_fail() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_noop!(
+				Router::swap_with_path(Origin::signed(2), None, vec![ASSET_A, ASSET_B, ASSET_D], 500, 0),
+				"no liquidity for an asset in the path"
+			);
+			// The first hop was not executed either
+			assert_eq!(GenericAsset::free_balance(&ASSET_A, &2), 1_000_000);
+			assert_eq!(GenericAsset::free_balance(&ASSET_B, &2), 1_000_000);
+		});
+	}
+
+	#[test]
+	fn route_is_refused_below_min_receive() {
+		with_externalities(&mut new_test_ext(), || {
+			let path = vec![ASSET_A, ASSET_B, ASSET_C];
+			let quote = Router::quote_path(&path, 500).unwrap();
+			assert_noop!(
+				Router::swap_with_path(Origin::signed(2), None, path, 500, quote[1] + 1),
+				"route output is below the minimum receive amount"
+			);
+		});
+	}
+
+	#[test]
+	fn invalid_paths_are_refused() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_noop!(
+				Router::swap_with_path(Origin::signed(2), None, vec![ASSET_A], 500, 0),
+				"path must contain at least two assets"
+			);
+			assert_noop!(
+				Router::swap_with_path(Origin::signed(2), None, vec![ASSET_A, ASSET_B, ASSET_A], 500, 0),
+				"path must not visit an asset twice"
+			);
+		});
+	}
+}
@@ -0,0 +1,527 @@
+// Copyright (C) 2019 Centrality Investments Limited
+// This file is part of CENNZnet.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//!
+//! Scheduled and recurring call dispatch
+//!
+//! A signed account or root may schedule a call to be dispatched with its origin at a future
+//! block, optionally repeating every `period` blocks. Due tasks are dispatched in
+//! `on_initialize`, at most `MAX_TASKS_PER_BLOCK` per block.
+//!
+//! An account has at most `MAX_TASKS_PER_ACCOUNT` pending tasks, each holding `TaskDeposit` of
+//! the spending asset in reserve until it completes or is cancelled. At most `MAX_PENDING_TASKS`
+//! signed tasks are pending in total; root tasks are exempt so root can always schedule.
+//!
+//! The call fee of every dispatch of a signed task is paid up front when it is scheduled.
+//! Signed tasks are checked against the call filter again when dispatched, and the fee of each
+//! dispatch which is filtered, cancelled or dropped is refunded to the owner.
+//!
+use crate::dispatch::DispatchAs;
+use crate::filter::CallFilter;
+use client::decl_runtime_apis;
+use parity_codec::{Codec, Decode, Encode};
+use rstd::prelude::*;
+use fees::CheckCallFee;
+use generic_asset::SpendingAssetCurrency;
+use runtime_primitives::traits::{As, Dispatchable, One, Saturating, Zero};
+use support::{
+	decl_event, decl_module, decl_storage, dispatch::Result, ensure, traits::Currency, Parameter, StorageMap,
+	StorageValue,
+};
+
+/// Maximum number of tasks dispatched in a single block
+pub const MAX_TASKS_PER_BLOCK: usize = 16;
+/// Maximum number of signed tasks pending at any time
+pub const MAX_PENDING_TASKS: usize = 1_024;
+/// Maximum number of tasks pending for a single account
+pub const MAX_TASKS_PER_ACCOUNT: u32 = 16;
+/// Number of blocks searched for a free slot when rescheduling a recurring task
+const MAX_RESCHEDULE_DELAY: u32 = 16;
+
+/// The module's configuration trait.
+pub trait Trait: generic_asset::Trait {
+	/// The overarching event type.
+	type Event: From<Event<Self>> + Into<<Self as system::Trait>::Event>;
+	/// The call type which may be scheduled
+	type Call: Parameter + Dispatchable<Origin = <Self as system::Trait>::Origin>;
+	/// Checks the owner of a signed task may dispatch its call when it is due
+	type CallFilter: CallFilter<Self::AccountId, <Self as Trait>::Call>;
+	/// Dispatches signed tasks on behalf of their owner
+	type Dispatcher: DispatchAs<Self::AccountId, <Self as Trait>::Call>;
+	/// The fee charged for each dispatch of a scheduled call
+	type CallFee: CheckCallFee<BalanceOf<Self>, <Self as Trait>::Call>;
+}
+
+type BalanceOf<T> = <T as generic_asset::Trait>::Balance;
+
+/// Unique identifier of a scheduled task
+pub type TaskId = u64;
+
+/// The origin a task is dispatched with
+#[derive(Encode, Decode, Clone, PartialEq)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub enum TaskOrigin<AccountId> {
+	/// Dispatched as root
+	Root,
+	/// Dispatched as signed by the account
+	Signed(AccountId),
+}
+
+/// A call scheduled for dispatch
+#[derive(Encode, Decode, Clone, PartialEq)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct Task<AccountId, BlockNumber, Call> {
+	/// The block the task is next dispatched at
+	pub when: BlockNumber,
+	/// The origin the call is dispatched with
+	pub origin: TaskOrigin<AccountId>,
+	/// The call to dispatch
+	pub call: Call,
+	/// For recurring tasks, (period in blocks, remaining dispatches including the next)
+	pub periodic: Option<(BlockNumber, u32)>,
+}
+
+/// The task type of a runtime
+pub type TaskOf<T> = Task<<T as system::Trait>::AccountId, <T as system::Trait>::BlockNumber, <T as Trait>::Call>;
+
+decl_storage! {
+	trait Store for Module<T: Trait> as Scheduler {
+		/// The next task ID to be assigned
+		pub NextTaskId get(next_task_id): TaskId;
+		/// Pending tasks by ID
+		pub Tasks get(task): map TaskId => Option<TaskOf<T>>;
+		/// IDs of the tasks due at a block
+		pub Agenda get(agenda): map T::BlockNumber => Vec<TaskId>;
+		/// IDs of all pending tasks
+		pub PendingTasks get(pending_tasks): Vec<TaskId>;
+		/// Number of pending tasks of an account
+		pub TaskCount get(task_count): map T::AccountId => u32;
+		/// The spending asset reserved from the owner of a signed task
+		pub Deposits get(deposit_of): map TaskId => BalanceOf<T>;
+		/// The call fee paid up front for each dispatch of a signed task
+		pub CallFees get(call_fee_of): map TaskId => BalanceOf<T>;
+		/// Number of pending signed tasks
+		pub SignedTaskCount get(signed_task_count): u32;
+		/// The deposit reserved for each new signed task
+		pub TaskDeposit get(task_deposit) config(): BalanceOf<T>;
+	}
+}
+
+decl_module! {
+	pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+		fn deposit_event<T>() = default;
+
+		/// Schedule `call` to be dispatched with the caller's origin at block `when`.
+		/// If `periodic` is `(period, count)` the call is dispatched `count` times, every `period` blocks.
+		pub fn schedule(
+			origin,
+			when: T::BlockNumber,
+			periodic: Option<(T::BlockNumber, u32)>,
+			call: Box<<T as Trait>::Call>
+		) -> Result {
+			let origin: Option<system::RawOrigin<T::AccountId>> = origin.into();
+			let origin = match origin {
+				Some(system::RawOrigin::Root) => TaskOrigin::Root,
+				Some(system::RawOrigin::Signed(who)) => TaskOrigin::Signed(who),
+				_ => return Err("bad origin: expected to be a root or signed origin"),
+			};
+			ensure!(when > <system::Module<T>>::block_number(), "task must be scheduled in the future");
+			if let Some((period, count)) = periodic {
+				ensure!(!period.is_zero(), "period should be non-zero");
+				ensure!(count > 0, "count should be non-zero");
+			}
+			ensure!(Self::agenda(when).len() < MAX_TASKS_PER_BLOCK, "agenda for the block is full");
+
+			let task_id = Self::next_task_id();
+			if let TaskOrigin::Signed(ref who) = origin {
+				ensure!((Self::signed_task_count() as usize) < MAX_PENDING_TASKS, "too many pending tasks");
+				ensure!(Self::task_count(who) < MAX_TASKS_PER_ACCOUNT, "too many pending tasks for the account");
+				let deposit = Self::task_deposit();
+				let spending_asset_id = <generic_asset::Module<T>>::spending_asset_id();
+				<generic_asset::Module<T>>::reserve(&spending_asset_id, who, deposit)?;
+				<Deposits<T>>::insert(task_id, deposit);
+				<CallFees<T>>::insert(task_id, T::CallFee::check_call_fee(&call));
+				<TaskCount<T>>::mutate(who, |count| *count += 1);
+				<SignedTaskCount<T>>::mutate(|count| *count += 1);
+			}
+			<NextTaskId<T>>::put(task_id + 1);
+			<Agenda<T>>::mutate(when, |agenda| agenda.push(task_id));
+			<PendingTasks<T>>::mutate(|pending| pending.push(task_id));
+			<Tasks<T>>::insert(task_id, Task { when, origin, call: *call, periodic });
+
+			Self::deposit_event(RawEvent::Scheduled(task_id, when));
+			Ok(())
+		}
+
+		/// Cancel a pending task. Signed tasks may be cancelled by their owner or root,
+		/// root tasks by root only.
+		pub fn cancel(origin, #[compact] task_id: TaskId) -> Result {
+			let task = Self::task(task_id).ok_or("task does not exist")?;
+			let origin: Option<system::RawOrigin<T::AccountId>> = origin.into();
+			match (origin, &task.origin) {
+				(Some(system::RawOrigin::Root), _) => (),
+				(Some(system::RawOrigin::Signed(ref who)), TaskOrigin::Signed(ref owner)) if who == owner => (),
+				_ => return Err("only the owner or root can cancel a task"),
+			}
+
+			<Agenda<T>>::mutate(task.when, |agenda| agenda.retain(|id| *id != task_id));
+			let remaining = task.periodic.map(|(_, count)| count).unwrap_or(1);
+			Self::refund_call_fees(task_id, &task.origin, remaining);
+			Self::remove_task(task_id, &task.origin);
+			Self::deposit_event(RawEvent::Cancelled(task_id));
+			Ok(())
+		}
+
+		fn on_initialize(now: T::BlockNumber) {
+			for task_id in <Agenda<T>>::take(now) {
+				if let Some(task) = Self::task(task_id) {
+					Self::dispatch_task(task_id, task, now);
+				}
+			}
+		}
+	}
+}
+
+decl_event!(
+	pub enum Event<T> where
+		BlockNumber = <T as system::Trait>::BlockNumber,
+		Balance = BalanceOf<T>
+	{
+		/// A task was scheduled: (task ID, block)
+		Scheduled(TaskId, BlockNumber),
+		/// A task was dispatched: (task ID, success)
+		Dispatched(TaskId, bool),
+		/// A task was cancelled: (task ID)
+		Cancelled(TaskId),
+		/// A recurring task could not be rescheduled and was dropped: (task ID)
+		Dropped(TaskId),
+		/// Call fees of undispatched calls were refunded to the owner: (task ID, amount)
+		Refunded(TaskId, Balance),
+	}
+);
+
+impl<T: Trait> Module<T> {
+	/// All pending tasks with their IDs
+	pub fn tasks() -> Vec<(TaskId, TaskOf<T>)> {
+		Self::pending_tasks()
+			.into_iter()
+			.filter_map(|id| Self::task(id).map(|task| (id, task)))
+			.collect()
+	}
+
+	/// Dispatch a due task and reschedule it if it recurs
+	fn dispatch_task(task_id: TaskId, mut task: TaskOf<T>, now: T::BlockNumber) {
+		// The call was filtered when scheduled, but the filter may have changed since
+		let ok = match task.origin.clone() {
+			TaskOrigin::Root => task.call.clone().dispatch(system::RawOrigin::Root.into()).is_ok(),
+			TaskOrigin::Signed(who) => {
+				if T::CallFilter::filter(&who, &task.call).is_ok() {
+					T::Dispatcher::dispatch_as(who, task.call.clone()).is_ok()
+				} else {
+					Self::refund_call_fees(task_id, &task.origin, 1);
+					false
+				}
+			}
+		};
+		Self::deposit_event(RawEvent::Dispatched(task_id, ok));
+
+		let (period, count) = match task.periodic {
+			Some((period, count)) if count > 1 => (period, count),
+			_ => return Self::remove_task(task_id, &task.origin),
+		};
+
+		// Use the first block from `now + period` with room in its agenda
+		let mut when = now + period;
+		for _ in 0..MAX_RESCHEDULE_DELAY {
+			if Self::agenda(when).len() < MAX_TASKS_PER_BLOCK {
+				<Agenda<T>>::mutate(when, |agenda| agenda.push(task_id));
+				task.when = when;
+				task.periodic = Some((period, count - 1));
+				<Tasks<T>>::insert(task_id, task);
+				return;
+			}
+			when += One::one();
+		}
+
+		Self::refund_call_fees(task_id, &task.origin, count - 1);
+		Self::remove_task(task_id, &task.origin);
+		Self::deposit_event(RawEvent::Dropped(task_id));
+	}
+
+	/// Refund the call fees paid up front for `dispatches` undispatched calls of a signed task
+	fn refund_call_fees(task_id: TaskId, origin: &TaskOrigin<T::AccountId>, dispatches: u32) {
+		if let TaskOrigin::Signed(who) = origin {
+			let refund = Self::call_fee_of(task_id).saturating_mul(As::sa(u64::from(dispatches)));
+			if !refund.is_zero() {
+				// The fee was burned when charged, so the refund is minted back
+				let _ = <SpendingAssetCurrency<T>>::deposit_creating(who, refund);
+				Self::deposit_event(RawEvent::Refunded(task_id, refund));
+			}
+		}
+	}
+
+	/// Remove a task, returning the deposit of a signed task to its owner
+	fn remove_task(task_id: TaskId, origin: &TaskOrigin<T::AccountId>) {
+		<Tasks<T>>::remove(task_id);
+		<PendingTasks<T>>::mutate(|pending| pending.retain(|id| *id != task_id));
+		if let TaskOrigin::Signed(who) = origin {
+			let spending_asset_id = <generic_asset::Module<T>>::spending_asset_id();
+			<generic_asset::Module<T>>::unreserve(&spending_asset_id, who, <Deposits<T>>::take(task_id));
+			<CallFees<T>>::remove(task_id);
+			<TaskCount<T>>::mutate(who, |count| *count = count.saturating_sub(1));
+			<SignedTaskCount<T>>::mutate(|count| *count = count.saturating_sub(1));
+		}
+	}
+}
+
+decl_runtime_apis! {
+	/// The API to query scheduled tasks
+	pub trait SchedulerApi<Task> where Task: Codec {
+		/// All pending tasks with their IDs
+		fn pending_tasks() -> Vec<(TaskId, Task)>;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use primitives::{Blake2Hasher, H256};
+	use runtime_io::with_externalities;
+	use runtime_primitives::{
+		testing::{Digest, DigestItem, Header},
+		traits::{BlakeTwo256, IdentityLookup, OnInitialize},
+		BuildStorage,
+	};
+	use std::cell::RefCell;
+	use support::{assert_noop, assert_ok, impl_outer_origin};
+	use system::ensure_signed;
+
+	impl_outer_origin! {
+		pub enum Origin for Test {}
+	}
+
+	#[derive(Clone, Eq, PartialEq)]
+	pub struct Test;
+	impl system::Trait for Test {
+		type Origin = Origin;
+		type Index = u64;
+		type BlockNumber = u64;
+		type Hash = H256;
+		type Hashing = BlakeTwo256;
+		type Digest = Digest;
+		type AccountId = u64;
+		type Lookup = IdentityLookup<Self::AccountId>;
+		type Header = Header;
+		type Event = ();
+		type Log = DigestItem;
+	}
+	impl generic_asset::Trait for Test {
+		type Balance = u64;
+		type AssetId = u32;
+		type Event = ();
+	}
+
+	thread_local! {
+		static DISPATCHED: RefCell<Vec<(u64, TestCall)>> = RefCell::new(Vec::new());
+		static FORBIDDEN: RefCell<bool> = RefCell::new(false);
+	}
+
+	/// Calls recorded with their signer when dispatched
+	#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+	pub struct TestCall(u32);
+	impl Dispatchable for TestCall {
+		type Origin = Origin;
+		type Trait = Test;
+		fn dispatch(self, origin: Origin) -> Result {
+			let who = ensure_signed(origin)?;
+			DISPATCHED.with(|d| d.borrow_mut().push((who, self)));
+			Ok(())
+		}
+	}
+
+	// Rejects every call once `FORBIDDEN` is set
+	pub struct TestFilter;
+	impl CallFilter<u64, TestCall> for TestFilter {
+		fn filter(_: &u64, _: &TestCall) -> Result {
+			if FORBIDDEN.with(|f| *f.borrow()) {
+				Err("call is filtered")
+			} else {
+				Ok(())
+			}
+		}
+	}
+	// Each dispatch costs 3
+	pub struct TestCallFee;
+	impl CheckCallFee<u64, TestCall> for TestCallFee {
+		fn check_call_fee(_: &TestCall) -> u64 {
+			3
+		}
+	}
+	impl Trait for Test {
+		type Event = ();
+		type Call = TestCall;
+		type CallFilter = TestFilter;
+		type Dispatcher = ();
+		type CallFee = TestCallFee;
+	}
+	type Scheduler = Module<Test>;
+	type GenericAsset = generic_asset::Module<Test>;
+
+	const SPENDING_ASSET: u32 = 16001;
+
+	// Accounts 1 and 2 hold 1000 of the spending asset, tasks take a deposit of 10
+	fn new_test_ext() -> runtime_io::TestExternalities<Blake2Hasher> {
+		DISPATCHED.with(|d| d.borrow_mut().clear());
+		FORBIDDEN.with(|f| *f.borrow_mut() = false);
+		let mut t = system::GenesisConfig::<Test>::default().build_storage().unwrap().0;
+		t.extend(
+			generic_asset::GenesisConfig::<Test> {
+				assets: vec![16000, SPENDING_ASSET],
+				initial_balance: 1_000,
+				endowed_accounts: vec![1, 2],
+				next_asset_id: 17000,
+				create_asset_stake: 0,
+				staking_asset_id: 16000,
+				spending_asset_id: SPENDING_ASSET,
+			}
+			.build_storage()
+			.unwrap()
+			.0,
+		);
+		t.extend(GenesisConfig::<Test> { task_deposit: 10 }.build_storage().unwrap().0);
+		t.into()
+	}
+
+	fn dispatched() -> Vec<(u64, TestCall)> {
+		DISPATCHED.with(|d| d.borrow().clone())
+	}
+
+	#[test]
+	fn task_deposit_is_held_until_dispatch() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_ok!(Scheduler::schedule(Origin::signed(1), 5, None, Box::new(TestCall(1))));
+			assert_eq!(GenericAsset::reserved_balance(&SPENDING_ASSET, &1), 10);
+			assert_eq!(Scheduler::task_count(1), 1);
+
+			Scheduler::on_initialize(5);
+			assert_eq!(dispatched(), vec![(1, TestCall(1))]);
+			assert_eq!(GenericAsset::reserved_balance(&SPENDING_ASSET, &1), 0);
+			assert_eq!(GenericAsset::free_balance(&SPENDING_ASSET, &1), 1_000);
+			assert_eq!(Scheduler::task_count(1), 0);
+			assert_eq!(Scheduler::pending_tasks(), vec![]);
+		});
+	}
+
+	#[test]
+	fn scheduling_requires_deposit() {
+		with_externalities(&mut new_test_ext(), || {
+			assert!(Scheduler::schedule(Origin::signed(3), 5, None, Box::new(TestCall(1))).is_err());
+			assert_eq!(Scheduler::pending_tasks(), vec![]);
+			assert_eq!(Scheduler::task_count(3), 0);
+		});
+	}
+
+	#[test]
+	fn pending_tasks_are_capped_per_account() {
+		with_externalities(&mut new_test_ext(), || {
+			for i in 0..MAX_TASKS_PER_ACCOUNT {
+				assert_ok!(Scheduler::schedule(Origin::signed(1), 5 + u64::from(i), None, Box::new(TestCall(i))));
+			}
+			assert_noop!(
+				Scheduler::schedule(Origin::signed(1), 5, None, Box::new(TestCall(0))),
+				"too many pending tasks for the account"
+			);
+			assert_ok!(Scheduler::schedule(Origin::signed(2), 5, None, Box::new(TestCall(0))));
+			assert_eq!(
+				GenericAsset::reserved_balance(&SPENDING_ASSET, &1),
+				10 * u64::from(MAX_TASKS_PER_ACCOUNT)
+			);
+
+			// Completing a task makes room for another
+			Scheduler::on_initialize(5);
+			assert_ok!(Scheduler::schedule(Origin::signed(1), 50, None, Box::new(TestCall(0))));
+		});
+	}
+
+	#[test]
+	fn cancel_returns_deposit() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_ok!(Scheduler::schedule(Origin::signed(1), 5, Some((2, 3)), Box::new(TestCall(1))));
+			assert_noop!(Scheduler::cancel(Origin::signed(2), 0), "only the owner or root can cancel a task");
+			assert_ok!(Scheduler::cancel(Origin::signed(1), 0));
+
+			assert_eq!(GenericAsset::reserved_balance(&SPENDING_ASSET, &1), 0);
+			assert_eq!(Scheduler::task_count(1), 0);
+			Scheduler::on_initialize(5);
+			assert_eq!(dispatched(), vec![]);
+		});
+	}
+
+	#[test]
+	fn cancel_refunds_fees_of_remaining_dispatches() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_ok!(Scheduler::schedule(Origin::signed(1), 5, Some((2, 3)), Box::new(TestCall(1))));
+			Scheduler::on_initialize(5);
+			assert_eq!(GenericAsset::free_balance(&SPENDING_ASSET, &1), 990);
+
+			// Two of the three dispatches paid for are still pending
+			assert_ok!(Scheduler::cancel(Origin::signed(1), 0));
+			assert_eq!(GenericAsset::free_balance(&SPENDING_ASSET, &1), 1_000 + 2 * 3);
+			assert_eq!(Scheduler::call_fee_of(0), 0);
+		});
+	}
+
+	#[test]
+	fn root_tasks_are_not_capped() {
+		with_externalities(&mut new_test_ext(), || {
+			<SignedTaskCount<Test>>::put(MAX_PENDING_TASKS as u32);
+			assert_noop!(
+				Scheduler::schedule(Origin::signed(1), 5, None, Box::new(TestCall(1))),
+				"too many pending tasks"
+			);
+			assert_ok!(Scheduler::schedule(Origin::ROOT, 5, None, Box::new(TestCall(1))));
+		});
+	}
+
+	#[test]
+	fn recurring_task_keeps_deposit_until_last_dispatch() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_ok!(Scheduler::schedule(Origin::signed(1), 5, Some((2, 2)), Box::new(TestCall(1))));
+			Scheduler::on_initialize(5);
+			assert_eq!(GenericAsset::reserved_balance(&SPENDING_ASSET, &1), 10);
+			assert_eq!(Scheduler::agenda(7), vec![0]);
+
+			Scheduler::on_initialize(7);
+			assert_eq!(dispatched(), vec![(1, TestCall(1)), (1, TestCall(1))]);
+			assert_eq!(GenericAsset::reserved_balance(&SPENDING_ASSET, &1), 0);
+		});
+	}
+
+	#[test]
+	fn filter_is_checked_at_dispatch() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_ok!(Scheduler::schedule(Origin::signed(1), 5, None, Box::new(TestCall(1))));
+			FORBIDDEN.with(|f| *f.borrow_mut() = true);
+
+			Scheduler::on_initialize(5);
+			assert_eq!(dispatched(), vec![]);
+			assert_eq!(Scheduler::task(0), None);
+			assert_eq!(GenericAsset::reserved_balance(&SPENDING_ASSET, &1), 0);
+			// The fee paid for the filtered dispatch is refunded
+			assert_eq!(GenericAsset::free_balance(&SPENDING_ASSET, &1), 1_000 + 3);
+		});
+	}
+}
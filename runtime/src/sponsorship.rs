@@ -0,0 +1,340 @@
+// Copyright (C) 2019 Centrality Investments Limited
+// This file is part of CENNZnet.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//!
+//! Sponsored transaction fees
+//!
+//! A sponsor account may pay the extrinsic fees of a user up to a budget and until an expiry
+//! block. A sponsor may further restrict which calls it pays for by `(module index, call index)`,
+//! the first two bytes of an encoded runtime `Call`. An empty list sponsors every call.
+//!
+//! A user has at most one sponsor, and only sponsorships the user accepts take effect. Other
+//! accounts offer a sponsorship, which the user accepts to replace the current one, so no
+//! account can lock a user into an unwanted sponsorship. The current sponsor may update its
+//! sponsorship directly, and the user may drop it at any time.
+//!
+//! The fee payer is resolved by the runtime's `ExtrinsicFeeCharger`, falling back to the
+//! transactor when there is no usable sponsorship or the sponsor cannot pay.
+//!
+use parity_codec::{Decode, Encode};
+use rstd::prelude::*;
+use runtime_primitives::traits::Zero;
+use support::{decl_event, decl_module, decl_storage, dispatch::Result, ensure, StorageMap};
+use system::ensure_signed;
+
+/// Maximum number of call indices a sponsor may whitelist
+pub const MAX_SPONSORED_CALLS: usize = 64;
+
+/// The module's configuration trait.
+pub trait Trait: generic_asset::Trait {
+	/// The overarching event type.
+	type Event: From<Event<Self>> + Into<<Self as system::Trait>::Event>;
+}
+
+type BalanceOf<T> = <T as generic_asset::Trait>::Balance;
+
+/// A runtime call identified by its (module index, call index)
+pub type CallIndex = (u8, u8);
+
+/// A sponsor's commitment to pay a user's fees
+#[derive(Encode, Decode, Clone, PartialEq)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct Sponsorship<AccountId, Balance, BlockNumber> {
+	/// The account paying the fees
+	pub sponsor: AccountId,
+	/// The remaining amount of fees the sponsor will pay
+	pub budget: Balance,
+	/// The sponsorship is no longer usable from this block
+	pub expiry: BlockNumber,
+}
+
+type SponsorshipOf<T> = Sponsorship<<T as system::Trait>::AccountId, BalanceOf<T>, <T as system::Trait>::BlockNumber>;
+
+decl_storage! {
+	trait Store for Module<T: Trait> as Sponsorship {
+		/// The sponsorship of a user's fees
+		pub Sponsorships get(sponsorship): map T::AccountId => Option<SponsorshipOf<T>>;
+		/// Sponsorships offered by a sponsor to a user, pending the user's acceptance
+		pub Offers get(offer): map (T::AccountId, T::AccountId) => Option<SponsorshipOf<T>>;
+		/// The calls a sponsor pays for, all calls if empty
+		pub SponsoredCalls get(sponsored_calls): map T::AccountId => Vec<CallIndex>;
+	}
+}
+
+decl_module! {
+	pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+		fn deposit_event<T>() = default;
+
+		/// Pay the fees of `user` up to `budget` until block `expiry`.
+		/// Updates the sponsorship if the caller is the current sponsor of `user`, otherwise
+		/// offers it to `user`, who accepts it with `accept`.
+		pub fn sponsor(origin, user: T::AccountId, #[compact] budget: BalanceOf<T>, expiry: T::BlockNumber) -> Result {
+			let sponsor = ensure_signed(origin)?;
+			ensure!(sponsor != user, "an account cannot sponsor itself");
+			ensure!(!budget.is_zero(), "budget should be non-zero");
+			let now = <system::Module<T>>::block_number();
+			ensure!(expiry > now, "expiry must be in the future");
+
+			let sponsorship = Sponsorship { sponsor: sponsor.clone(), budget, expiry };
+			if Self::sponsorship(&user).map_or(false, |existing| existing.sponsor == sponsor) {
+				<Sponsorships<T>>::insert(&user, sponsorship);
+				Self::deposit_event(RawEvent::Sponsored(sponsor, user, budget, expiry));
+			} else {
+				<Offers<T>>::insert(&(user.clone(), sponsor.clone()), sponsorship);
+				Self::deposit_event(RawEvent::SponsorshipOffered(sponsor, user, budget, expiry));
+			}
+			Ok(())
+		}
+
+		/// Accept the sponsorship offered by `sponsor`, replacing the caller's current sponsorship.
+		pub fn accept(origin, sponsor: T::AccountId) -> Result {
+			let user = ensure_signed(origin)?;
+			let key = (user.clone(), sponsor.clone());
+			let offer = Self::offer(&key).ok_or("no sponsorship is offered by this account")?;
+			ensure!(offer.expiry > <system::Module<T>>::block_number(), "offer has expired");
+
+			<Offers<T>>::remove(&key);
+			let (budget, expiry) = (offer.budget, offer.expiry);
+			<Sponsorships<T>>::insert(&user, offer);
+			Self::deposit_event(RawEvent::Sponsored(sponsor, user, budget, expiry));
+			Ok(())
+		}
+
+		/// End the sponsorship of `user`. May be called by its sponsor or by `user`.
+		/// A sponsor whose offer to `user` is pending withdraws the offer instead.
+		pub fn revoke(origin, user: T::AccountId) -> Result {
+			let who = ensure_signed(origin)?;
+			let key = (user.clone(), who.clone());
+			if <Offers<T>>::exists(&key) {
+				<Offers<T>>::remove(&key);
+				Self::deposit_event(RawEvent::Revoked(who, user));
+				return Ok(());
+			}
+
+			let existing = Self::sponsorship(&user).ok_or("user is not sponsored")?;
+			ensure!(
+				existing.sponsor == who || user == who,
+				"only the sponsor or the user can revoke a sponsorship"
+			);
+
+			<Sponsorships<T>>::remove(&user);
+			Self::deposit_event(RawEvent::Revoked(existing.sponsor, user));
+			Ok(())
+		}
+
+		/// Restrict the calls the caller pays for. An empty list sponsors every call.
+		pub fn set_sponsored_calls(origin, calls: Vec<CallIndex>) -> Result {
+			let sponsor = ensure_signed(origin)?;
+			ensure!(calls.len() <= MAX_SPONSORED_CALLS, "too many sponsored calls");
+
+			<SponsoredCalls<T>>::insert(&sponsor, calls.clone());
+			Self::deposit_event(RawEvent::SponsoredCallsSet(sponsor, calls));
+			Ok(())
+		}
+	}
+}
+
+decl_event!(
+	pub enum Event<T> where
+		AccountId = <T as system::Trait>::AccountId,
+		Balance = BalanceOf<T>,
+		BlockNumber = <T as system::Trait>::BlockNumber
+	{
+		/// A sponsor offered to pay a user's fees, pending acceptance: (sponsor, user, budget, expiry)
+		SponsorshipOffered(AccountId, AccountId, Balance, BlockNumber),
+		/// A sponsor committed to pay a user's fees: (sponsor, user, budget, expiry)
+		Sponsored(AccountId, AccountId, Balance, BlockNumber),
+		/// A sponsorship or offer was revoked: (sponsor, user)
+		Revoked(AccountId, AccountId),
+		/// A sponsor paid a user's fee: (sponsor, user, fee)
+		FeeSponsored(AccountId, AccountId, Balance),
+		/// A sponsor restricted the calls it pays for, all calls if empty: (sponsor, calls)
+		SponsoredCallsSet(AccountId, Vec<CallIndex>),
+	}
+);
+
+impl<T: Trait> Module<T> {
+	/// The sponsor who will pay `fee` for `user` submitting `call`, if any
+	pub fn sponsor_for<C: Encode>(user: &T::AccountId, call: &C, fee: BalanceOf<T>) -> Option<T::AccountId> {
+		let sponsorship = Self::sponsorship(user)?;
+		if sponsorship.expiry <= <system::Module<T>>::block_number() || sponsorship.budget < fee {
+			return None;
+		}
+
+		let allowed = Self::sponsored_calls(&sponsorship.sponsor);
+		if !allowed.is_empty() {
+			let index = call.using_encoded(|c| if c.len() >= 2 { Some((c[0], c[1])) } else { None })?;
+			if !allowed.contains(&index) {
+				return None;
+			}
+		}
+
+		Some(sponsorship.sponsor)
+	}
+
+	/// Deduct `fee` paid by the sponsor from `user`'s sponsorship budget
+	pub fn note_fee_sponsored(user: &T::AccountId, fee: BalanceOf<T>) {
+		if let Some(mut sponsorship) = Self::sponsorship(user) {
+			sponsorship.budget = sponsorship.budget - fee;
+			let sponsor = sponsorship.sponsor.clone();
+			if sponsorship.budget.is_zero() {
+				<Sponsorships<T>>::remove(user);
+			} else {
+				<Sponsorships<T>>::insert(user, sponsorship);
+			}
+			Self::deposit_event(RawEvent::FeeSponsored(sponsor, user.clone(), fee));
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use primitives::{Blake2Hasher, H256};
+	use runtime_io::with_externalities;
+	use runtime_primitives::{
+		testing::{Digest, DigestItem, Header},
+		traits::{BlakeTwo256, IdentityLookup},
+		BuildStorage,
+	};
+	use support::{assert_noop, assert_ok, impl_outer_origin};
+
+	impl_outer_origin! {
+		pub enum Origin for Test {}
+	}
+
+	#[derive(Clone, Eq, PartialEq)]
+	pub struct Test;
+	impl system::Trait for Test {
+		type Origin = Origin;
+		type Index = u64;
+		type BlockNumber = u64;
+		type Hash = H256;
+		type Hashing = BlakeTwo256;
+		type Digest = Digest;
+		type AccountId = u64;
+		type Lookup = IdentityLookup<Self::AccountId>;
+		type Header = Header;
+		type Event = ();
+		type Log = DigestItem;
+	}
+	impl generic_asset::Trait for Test {
+		type Balance = u64;
+		type AssetId = u32;
+		type Event = ();
+	}
+	impl Trait for Test {
+		type Event = ();
+	}
+	type Sponsorship = Module<Test>;
+	type System = system::Module<Test>;
+
+	// Encodes as the call index (3, 7)
+	const CALL: (u8, u8, u32) = (3, 7, 0);
+
+	fn new_test_ext() -> runtime_io::TestExternalities<Blake2Hasher> {
+		system::GenesisConfig::<Test>::default().build_storage().unwrap().0.into()
+	}
+
+	// `sponsor` sponsors `user`, who accepts
+	fn sponsored(sponsor: u64, user: u64, budget: u64, expiry: u64) {
+		assert_ok!(Sponsorship::sponsor(Origin::signed(sponsor), user, budget, expiry));
+		assert_ok!(Sponsorship::accept(Origin::signed(user), sponsor));
+	}
+
+	#[test]
+	fn sponsor_pays_within_budget_and_expiry() {
+		with_externalities(&mut new_test_ext(), || {
+			System::set_block_number(1);
+			assert_noop!(Sponsorship::sponsor(Origin::signed(1), 2, 0, 10), "budget should be non-zero");
+			assert_noop!(Sponsorship::sponsor(Origin::signed(1), 2, 100, 1), "expiry must be in the future");
+			assert_ok!(Sponsorship::sponsor(Origin::signed(1), 2, 100, 10));
+			assert_eq!(Sponsorship::sponsor_for(&2, &CALL, 60), None);
+			assert_ok!(Sponsorship::accept(Origin::signed(2), 1));
+
+			assert_eq!(Sponsorship::sponsor_for(&2, &CALL, 60), Some(1));
+			Sponsorship::note_fee_sponsored(&2, 60);
+			assert_eq!(Sponsorship::sponsor_for(&2, &CALL, 60), None);
+			assert_eq!(Sponsorship::sponsor_for(&2, &CALL, 40), Some(1));
+			Sponsorship::note_fee_sponsored(&2, 40);
+			// The exhausted sponsorship is removed
+			assert_eq!(Sponsorship::sponsorship(2), None);
+
+			sponsored(1, 2, 100, 10);
+			System::set_block_number(10);
+			assert_eq!(Sponsorship::sponsor_for(&2, &CALL, 1), None);
+		});
+	}
+
+	#[test]
+	fn sponsored_calls_restrict_sponsorship() {
+		with_externalities(&mut new_test_ext(), || {
+			sponsored(1, 2, 100, 10);
+			assert_ok!(Sponsorship::set_sponsored_calls(Origin::signed(1), vec![(3, 8)]));
+			assert_eq!(Sponsorship::sponsor_for(&2, &CALL, 1), None);
+
+			assert_ok!(Sponsorship::set_sponsored_calls(Origin::signed(1), vec![(3, 8), (3, 7)]));
+			assert_eq!(Sponsorship::sponsor_for(&2, &CALL, 1), Some(1));
+		});
+	}
+
+	#[test]
+	fn offers_cannot_displace_the_accepted_sponsor() {
+		with_externalities(&mut new_test_ext(), || {
+			System::set_block_number(1);
+			sponsored(1, 2, 100, 1_000);
+			// A dust offer with a far expiry does not replace the sponsor
+			assert_ok!(Sponsorship::sponsor(Origin::signed(3), 2, 1, 1_000_000));
+			assert_eq!(Sponsorship::sponsor_for(&2, &CALL, 100), Some(1));
+			// The current sponsor tops up directly
+			assert_ok!(Sponsorship::sponsor(Origin::signed(1), 2, 200, 1_000));
+			assert_eq!(Sponsorship::sponsorship(2).map(|s| s.budget), Some(200));
+
+			// The user accepts another offer in place of the current sponsorship
+			assert_ok!(Sponsorship::sponsor(Origin::signed(4), 2, 500, 2_000));
+			assert_noop!(Sponsorship::accept(Origin::signed(2), 5), "no sponsorship is offered by this account");
+			assert_ok!(Sponsorship::accept(Origin::signed(2), 4));
+			assert_eq!(Sponsorship::sponsor_for(&2, &CALL, 500), Some(4));
+			assert_eq!(Sponsorship::offer((2, 4)), None);
+
+			System::set_block_number(1_000_000);
+			assert_noop!(Sponsorship::accept(Origin::signed(2), 3), "offer has expired");
+		});
+	}
+
+	#[test]
+	fn sponsor_or_user_can_revoke() {
+		with_externalities(&mut new_test_ext(), || {
+			sponsored(1, 2, 1, 1_000);
+			assert_noop!(
+				Sponsorship::revoke(Origin::signed(3), 2),
+				"only the sponsor or the user can revoke a sponsorship"
+			);
+			// The user drops an unwanted sponsorship and can accept another
+			assert_ok!(Sponsorship::revoke(Origin::signed(2), 2));
+			sponsored(3, 2, 100, 1_000);
+
+			// A pending offer is withdrawn by its sponsor
+			assert_ok!(Sponsorship::sponsor(Origin::signed(4), 2, 100, 1_000));
+			assert_ok!(Sponsorship::revoke(Origin::signed(4), 2));
+			assert_eq!(Sponsorship::offer((2, 4)), None);
+			assert_eq!(Sponsorship::sponsor_for(&2, &CALL, 1), Some(3));
+
+			assert_ok!(Sponsorship::revoke(Origin::signed(3), 2));
+			assert_noop!(Sponsorship::revoke(Origin::signed(3), 2), "user is not sponsored");
+		});
+	}
+}
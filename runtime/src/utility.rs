@@ -0,0 +1,209 @@
+// Copyright (C) 2019 Centrality Investments Limited
+// This file is part of CENNZnet.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//!
+//! Batch dispatch of runtime calls
+//!
+//! `batch_until_failure` dispatches a list of calls in order under the caller's origin, stopping
+//! at the first call which fails. The failing index is reported by a `BatchInterrupted` event
+//! and the extrinsic fails with that call's error.
+//!
+//! A batch is not all-or-nothing, as its name says. The runtime has no storage transactions, and
+//! a call's failure conditions may depend on the calls before it, so they cannot all be checked
+//! up front. The effects of calls dispatched before the failing one are kept. Only calls
+//! accepted by the runtime's `BatchFilter` may be batched: calls which check every failure
+//! condition before writing to storage, so the failing call itself has no effect and the batch
+//! is cut off cleanly between two calls.
+//!
+use crate::dispatch::DispatchAs;
+use rstd::prelude::*;
+use runtime_primitives::traits::Dispatchable;
+use support::{decl_event, decl_module, dispatch::Result, ensure, Parameter};
+use system::ensure_signed;
+
+/// Maximum number of calls in a batch
+pub const MAX_BATCH_SIZE: usize = 64;
+
+/// The module's configuration trait.
+pub trait Trait: system::Trait {
+	/// The overarching event type.
+	type Event: From<Event> + Into<<Self as system::Trait>::Event>;
+	/// The call type which may be batched
+	type Call: Parameter + Dispatchable<Origin = <Self as system::Trait>::Origin>;
+	/// Decides which calls may be batched
+	type BatchFilter: BatchFilter<<Self as Trait>::Call>;
+	/// Dispatches the calls on behalf of the caller
+	type Dispatcher: DispatchAs<Self::AccountId, <Self as Trait>::Call>;
+}
+
+/// Decides whether a call may be batched
+pub trait BatchFilter<Call> {
+	/// Return whether `call` fails without side effects, checking before it writes to storage
+	fn allows(call: &Call) -> bool;
+}
+
+decl_module! {
+	pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+		fn deposit_event() = default;
+
+		/// Dispatch `calls` in order with the caller's signed origin, stopping at the first failure.
+		/// Calls dispatched before a failing call are not reverted.
+		pub fn batch_until_failure(origin, calls: Vec<<T as Trait>::Call>) -> Result {
+			let who = ensure_signed(origin)?;
+			ensure!(calls.len() <= MAX_BATCH_SIZE, "too many calls in batch");
+			ensure!(calls.iter().all(T::BatchFilter::allows), "call cannot be batched");
+
+			for (index, call) in calls.into_iter().enumerate() {
+				if let Err(e) = T::Dispatcher::dispatch_as(who.clone(), call) {
+					Self::deposit_event(Event::BatchInterrupted(index as u32));
+					return Err(e);
+				}
+			}
+
+			Self::deposit_event(Event::BatchCompleted);
+			Ok(())
+		}
+	}
+}
+
+decl_event!(
+	pub enum Event {
+		/// A call in a batch failed and the remaining calls were skipped: (index of the failing call)
+		BatchInterrupted(u32),
+		/// Every call in a batch succeeded
+		BatchCompleted,
+	}
+);
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use parity_codec::{Decode, Encode};
+	use primitives::{Blake2Hasher, H256};
+	use runtime_io::with_externalities;
+	use runtime_primitives::{
+		testing::{Digest, DigestItem, Header},
+		traits::{BlakeTwo256, IdentityLookup},
+		BuildStorage,
+	};
+	use std::cell::RefCell;
+	use support::{assert_noop, assert_ok, impl_outer_origin};
+
+	impl_outer_origin! {
+		pub enum Origin for Test {}
+	}
+
+	#[derive(Clone, Eq, PartialEq)]
+	pub struct Test;
+	impl system::Trait for Test {
+		type Origin = Origin;
+		type Index = u64;
+		type BlockNumber = u64;
+		type Hash = H256;
+		type Hashing = BlakeTwo256;
+		type Digest = Digest;
+		type AccountId = u64;
+		type Lookup = IdentityLookup<Self::AccountId>;
+		type Header = Header;
+		type Event = ();
+		type Log = DigestItem;
+	}
+
+	thread_local! {
+		static DISPATCHED: RefCell<Vec<(u64, TestCall)>> = RefCell::new(Vec::new());
+	}
+
+	/// Calls recorded with their origin when dispatched successfully
+	#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+	pub enum TestCall {
+		/// Succeeds
+		Ok(u32),
+		/// Fails without side effects
+		Fail,
+		/// May fail after writing, so cannot be batched
+		Unchecked,
+	}
+	impl Dispatchable for TestCall {
+		type Origin = Origin;
+		type Trait = Test;
+		fn dispatch(self, origin: Origin) -> Result {
+			let who = ensure_signed(origin)?;
+			if self == TestCall::Fail {
+				return Err("call failed");
+			}
+			DISPATCHED.with(|d| d.borrow_mut().push((who, self)));
+			Ok(())
+		}
+	}
+
+	pub struct TestBatchFilter;
+	impl BatchFilter<TestCall> for TestBatchFilter {
+		fn allows(call: &TestCall) -> bool {
+			*call != TestCall::Unchecked
+		}
+	}
+	impl Trait for Test {
+		type Event = ();
+		type Call = TestCall;
+		type BatchFilter = TestBatchFilter;
+		type Dispatcher = ();
+	}
+	type Utility = Module<Test>;
+
+	fn new_test_ext() -> runtime_io::TestExternalities<Blake2Hasher> {
+		DISPATCHED.with(|d| d.borrow_mut().clear());
+		system::GenesisConfig::<Test>::default().build_storage().unwrap().0.into()
+	}
+
+	fn dispatched() -> Vec<(u64, TestCall)> {
+		DISPATCHED.with(|d| d.borrow().clone())
+	}
+
+	#[test]
+	fn batch_dispatches_calls_in_order() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_ok!(Utility::batch_until_failure(Origin::signed(1), vec![TestCall::Ok(1), TestCall::Ok(2)]));
+			assert_eq!(dispatched(), vec![(1, TestCall::Ok(1)), (1, TestCall::Ok(2))]);
+		});
+	}
+
+	#[test]
+	fn batch_stops_at_failing_call() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_noop!(
+				Utility::batch_until_failure(Origin::signed(1), vec![TestCall::Ok(1), TestCall::Fail, TestCall::Ok(2)]),
+				"call failed"
+			);
+			// Calls before the failure are kept, calls after it are skipped
+			assert_eq!(dispatched(), vec![(1, TestCall::Ok(1))]);
+		});
+	}
+
+	#[test]
+	fn batch_rejects_calls_not_allowed() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_noop!(
+				Utility::batch_until_failure(Origin::signed(1), vec![TestCall::Ok(1), TestCall::Unchecked]),
+				"call cannot be batched"
+			);
+			assert_noop!(
+				Utility::batch_until_failure(Origin::signed(1), vec![TestCall::Ok(1); MAX_BATCH_SIZE + 1]),
+				"too many calls in batch"
+			);
+			assert_eq!(dispatched(), vec![]);
+		});
+	}
+}
@@ -0,0 +1,357 @@
+// Copyright (C) 2019 Centrality Investments Limited
+// This file is part of CENNZnet.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//!
+//! Time-locked vesting of generic asset balances
+//!
+//! The unvested part of a schedule is held as reserved balance of the beneficiary, so it cannot
+//! be moved by `generic_asset::transfer` or any other call spending free balance.
+//! Nothing vests before `start + cliff`, after which the balance vests linearly until
+//! `start + duration`. Vested funds are released to free balance by calling `vest`.
+//!
+use parity_codec::{Decode, Encode};
+use rstd::prelude::*;
+use runtime_primitives::traits::{As, SimpleArithmetic, StaticLookup, Zero};
+use support::{decl_event, decl_module, decl_storage, dispatch::Result, ensure, StorageMap};
+use system::{ensure_root, ensure_signed};
+
+/// The module's configuration trait.
+pub trait Trait: generic_asset::Trait {
+	/// The overarching event type.
+	type Event: From<Event<Self>> + Into<<Self as system::Trait>::Event>;
+}
+
+type AssetIdOf<T> = <T as generic_asset::Trait>::AssetId;
+type BalanceOf<T> = <T as generic_asset::Trait>::Balance;
+
+/// A linear vesting schedule with a cliff
+#[derive(Encode, Decode, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct VestingSchedule<Balance, BlockNumber> {
+	/// The total amount being vested
+	pub total: Balance,
+	/// The amount already released to free balance
+	pub released: Balance,
+	/// The block vesting starts from
+	pub start: BlockNumber,
+	/// Number of blocks after `start` before anything vests
+	pub cliff: BlockNumber,
+	/// Number of blocks after `start` until `total` has vested
+	pub duration: BlockNumber,
+}
+
+impl<Balance: SimpleArithmetic + Copy, BlockNumber: As<u64> + Copy> VestingSchedule<Balance, BlockNumber> {
+	/// The amount vested at block `now`
+	pub fn vested_at(&self, now: BlockNumber) -> Balance {
+		let (now, start) = (now.as_(), self.start.as_());
+		let (cliff, duration) = (self.cliff.as_(), self.duration.as_());
+		if now < start.saturating_add(cliff) {
+			return Zero::zero();
+		}
+		let elapsed = now - start;
+		if elapsed >= duration {
+			return self.total;
+		}
+		// Block counts fit in any balance type, so the amounts are computed in full precision.
+		// `total * elapsed` is split into two products that cannot overflow.
+		let (elapsed, duration) = (Balance::sa(elapsed), Balance::sa(duration));
+		(self.total / duration).saturating_mul(elapsed) + (self.total % duration).saturating_mul(elapsed) / duration
+	}
+
+	/// Check the schedule can be locked
+	fn validate(&self) -> Result {
+		ensure!(!self.total.is_zero(), "vesting amount should be non-zero");
+		ensure!(self.duration.as_() != 0, "vesting duration should be non-zero");
+		ensure!(self.cliff.as_() <= self.duration.as_(), "vesting cliff must not exceed its duration");
+		Ok(())
+	}
+}
+
+type ScheduleOf<T> = VestingSchedule<BalanceOf<T>, <T as system::Trait>::BlockNumber>;
+
+decl_storage! {
+	trait Store for Module<T: Trait> as Vesting {
+		/// Vesting schedule of an account for an asset
+		pub Vesting get(vesting): map (T::AccountId, AssetIdOf<T>) => Option<ScheduleOf<T>>;
+	}
+	add_extra_genesis {
+		/// Genesis vesting schedules as (beneficiary, asset, total, start, cliff, duration).
+		/// `total` is locked out of the beneficiary's genesis balance.
+		config(vesting): Vec<(T::AccountId, AssetIdOf<T>, BalanceOf<T>, T::BlockNumber, T::BlockNumber, T::BlockNumber)>;
+		build(|storage: &mut runtime_primitives::StorageOverlay, _: &mut runtime_primitives::ChildrenStorageOverlay, config: &GenesisConfig<T>| {
+			runtime_io::with_storage(storage, || {
+				for &(ref who, asset_id, total, start, cliff, duration) in &config.vesting {
+					let schedule = VestingSchedule { total, released: Zero::zero(), start, cliff, duration };
+					<Module<T>>::lock(who, asset_id, schedule).expect("invalid genesis vesting schedule");
+				}
+			});
+		});
+	}
+}
+
+decl_module! {
+	pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+		fn deposit_event<T>() = default;
+
+		/// Release the vested balance of the caller's `asset_id` schedule
+		pub fn vest(origin, asset_id: AssetIdOf<T>) -> Result {
+			let who = ensure_signed(origin)?;
+			Self::release(&who, asset_id)
+		}
+
+		/// Release the vested balance of `target`'s `asset_id` schedule
+		pub fn vest_other(origin, target: <T::Lookup as StaticLookup>::Source, asset_id: AssetIdOf<T>) -> Result {
+			ensure_signed(origin)?;
+			let who = T::Lookup::lookup(target)?;
+			Self::release(&who, asset_id)
+		}
+
+		/// Move `total` of `asset_id` from `source` to `target` and lock it under a new schedule.
+		/// Must be called by root.
+		pub fn add_vesting_schedule(
+			origin,
+			source: <T::Lookup as StaticLookup>::Source,
+			target: <T::Lookup as StaticLookup>::Source,
+			asset_id: AssetIdOf<T>,
+			#[compact] total: BalanceOf<T>,
+			start: T::BlockNumber,
+			cliff: T::BlockNumber,
+			duration: T::BlockNumber
+		) -> Result {
+			ensure_root(origin)?;
+			let source = T::Lookup::lookup(source)?;
+			let who = T::Lookup::lookup(target)?;
+			ensure!(!<Vesting<T>>::exists((who.clone(), asset_id)), "account already has a vesting schedule for this asset");
+
+			// Nothing may be transferred for a schedule `lock` would reject
+			let schedule = VestingSchedule { total, released: Zero::zero(), start, cliff, duration };
+			schedule.validate()?;
+			if source != who {
+				<generic_asset::Module<T>>::make_transfer(&asset_id, &source, &who, total)?;
+			}
+			Self::lock(&who, asset_id, schedule)
+		}
+	}
+}
+
+decl_event!(
+	pub enum Event<T> where
+		AccountId = <T as system::Trait>::AccountId,
+		AssetId = AssetIdOf<T>,
+		Balance = BalanceOf<T>
+	{
+		/// A vesting schedule was created: (beneficiary, asset, total)
+		VestingScheduleAdded(AccountId, AssetId, Balance),
+		/// Vested balance was released: (beneficiary, asset, amount released)
+		Vested(AccountId, AssetId, Balance),
+		/// A vesting schedule has fully vested and was removed: (beneficiary, asset)
+		VestingCompleted(AccountId, AssetId),
+	}
+);
+
+impl<T: Trait> Module<T> {
+	/// The amount of `asset_id` still locked for `who`
+	pub fn locked_balance(who: &T::AccountId, asset_id: AssetIdOf<T>) -> BalanceOf<T> {
+		Self::vesting((who.clone(), asset_id))
+			.map(|s| s.total - s.released)
+			.unwrap_or_else(Zero::zero)
+	}
+
+	/// Reserve the schedule's total from `who`'s free balance and store the schedule
+	fn lock(who: &T::AccountId, asset_id: AssetIdOf<T>, schedule: ScheduleOf<T>) -> Result {
+		schedule.validate()?;
+
+		<generic_asset::Module<T>>::reserve(&asset_id, who, schedule.total)?;
+		let total = schedule.total;
+		<Vesting<T>>::insert((who.clone(), asset_id), schedule);
+
+		Self::deposit_event(RawEvent::VestingScheduleAdded(who.clone(), asset_id, total));
+		Ok(())
+	}
+
+	/// Unreserve the portion of `who`'s `asset_id` schedule vested since the last release
+	fn release(who: &T::AccountId, asset_id: AssetIdOf<T>) -> Result {
+		let key = (who.clone(), asset_id);
+		let mut schedule = Self::vesting(&key).ok_or("no vesting schedule for this asset")?;
+
+		let vested = schedule.vested_at(<system::Module<T>>::block_number());
+		ensure!(vested > schedule.released, "no balance has vested since the last release");
+		let amount = vested - schedule.released;
+		<generic_asset::Module<T>>::unreserve(&asset_id, who, amount);
+		schedule.released = vested;
+		Self::deposit_event(RawEvent::Vested(who.clone(), asset_id, amount));
+
+		if schedule.released == schedule.total {
+			<Vesting<T>>::remove(&key);
+			Self::deposit_event(RawEvent::VestingCompleted(who.clone(), asset_id));
+		} else {
+			<Vesting<T>>::insert(&key, schedule);
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use primitives::{Blake2Hasher, H256};
+	use runtime_io::with_externalities;
+	use runtime_primitives::{
+		testing::{Digest, DigestItem, Header},
+		traits::{BlakeTwo256, IdentityLookup},
+		BuildStorage,
+	};
+	use support::{assert_noop, assert_ok, impl_outer_origin};
+
+	impl_outer_origin! {
+		pub enum Origin for Test {}
+	}
+
+	#[derive(Clone, Eq, PartialEq)]
+	pub struct Test;
+	impl system::Trait for Test {
+		type Origin = Origin;
+		type Index = u64;
+		type BlockNumber = u64;
+		type Hash = H256;
+		type Hashing = BlakeTwo256;
+		type Digest = Digest;
+		type AccountId = u64;
+		type Lookup = IdentityLookup<Self::AccountId>;
+		type Header = Header;
+		type Event = ();
+		type Log = DigestItem;
+	}
+	impl generic_asset::Trait for Test {
+		type Balance = u64;
+		type AssetId = u32;
+		type Event = ();
+	}
+	impl Trait for Test {
+		type Event = ();
+	}
+	type Vesting = Module<Test>;
+	type GenericAsset = generic_asset::Module<Test>;
+	type System = system::Module<Test>;
+
+	const ASSET: u32 = 16000;
+
+	// Account 1 vests 1000 over blocks 10..110 with a 20 block cliff
+	fn new_test_ext() -> runtime_io::TestExternalities<Blake2Hasher> {
+		let mut t = system::GenesisConfig::<Test>::default().build_storage().unwrap().0;
+		t.extend(
+			generic_asset::GenesisConfig::<Test> {
+				assets: vec![ASSET],
+				initial_balance: 1_000,
+				endowed_accounts: vec![1, 2],
+				next_asset_id: 17000,
+				create_asset_stake: 0,
+				staking_asset_id: ASSET,
+				spending_asset_id: ASSET,
+			}
+			.build_storage()
+			.unwrap()
+			.0,
+		);
+		let mut ext: runtime_io::TestExternalities<Blake2Hasher> = t.into();
+		with_externalities(&mut ext, || {
+			let schedule = VestingSchedule { total: 1_000, released: 0, start: 10, cliff: 20, duration: 100 };
+			assert_ok!(Vesting::lock(&1, ASSET, schedule));
+		});
+		ext
+	}
+
+	#[test]
+	fn vested_amount_is_linear_after_cliff() {
+		let schedule = VestingSchedule::<u64, u64> { total: 1_000, released: 0, start: 10, cliff: 20, duration: 100 };
+		assert_eq!(schedule.vested_at(0), 0);
+		assert_eq!(schedule.vested_at(29), 0);
+		assert_eq!(schedule.vested_at(30), 200);
+		assert_eq!(schedule.vested_at(60), 500);
+		assert_eq!(schedule.vested_at(110), 1_000);
+		assert_eq!(schedule.vested_at(500), 1_000);
+	}
+
+	#[test]
+	fn vested_amount_keeps_full_balance_precision() {
+		let total = 1u128 << 100;
+		let schedule = VestingSchedule::<u128, u64> { total, released: 0, start: 0, cliff: 0, duration: 3 };
+		assert_eq!(schedule.vested_at(1), total / 3);
+		assert_eq!(schedule.vested_at(2), total / 3 * 2);
+		assert_eq!(schedule.vested_at(3), total);
+	}
+
+	#[test]
+	fn locked_balance_cannot_be_transferred() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_eq!(GenericAsset::free_balance(&ASSET, &1), 0);
+			assert_eq!(Vesting::locked_balance(&1, ASSET), 1_000);
+			assert!(GenericAsset::transfer(Origin::signed(1), ASSET, 2, 1).is_err());
+		});
+	}
+
+	#[test]
+	fn vest_releases_vested_balance() {
+		with_externalities(&mut new_test_ext(), || {
+			System::set_block_number(20);
+			assert_noop!(Vesting::vest(Origin::signed(1), ASSET), "no balance has vested since the last release");
+
+			System::set_block_number(60);
+			assert_ok!(Vesting::vest(Origin::signed(1), ASSET));
+			assert_eq!(GenericAsset::free_balance(&ASSET, &1), 500);
+			assert_eq!(Vesting::locked_balance(&1, ASSET), 500);
+
+			System::set_block_number(200);
+			assert_ok!(Vesting::vest_other(Origin::signed(2), 1, ASSET));
+			assert_eq!(GenericAsset::free_balance(&ASSET, &1), 1_000);
+			assert_eq!(Vesting::vesting((1, ASSET)), None);
+		});
+	}
+
+	#[test]
+	fn add_vesting_schedule_requires_root() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_noop!(
+				Vesting::add_vesting_schedule(Origin::signed(2), 2, 3, ASSET, 100, 0, 0, 10),
+				"bad origin: expected to be a root origin"
+			);
+			assert_ok!(Vesting::add_vesting_schedule(Origin::ROOT, 2, 3, ASSET, 100, 0, 0, 10));
+			assert_eq!(GenericAsset::free_balance(&ASSET, &2), 900);
+			assert_eq!(GenericAsset::reserved_balance(&ASSET, &3), 100);
+		});
+	}
+
+	#[test]
+	fn invalid_schedules_are_rejected_before_transfer() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_noop!(
+				Vesting::add_vesting_schedule(Origin::ROOT, 2, 3, ASSET, 0, 0, 0, 10),
+				"vesting amount should be non-zero"
+			);
+			assert_noop!(
+				Vesting::add_vesting_schedule(Origin::ROOT, 2, 3, ASSET, 100, 0, 0, 0),
+				"vesting duration should be non-zero"
+			);
+			assert_noop!(
+				Vesting::add_vesting_schedule(Origin::ROOT, 2, 3, ASSET, 100, 0, 11, 10),
+				"vesting cliff must not exceed its duration"
+			);
+			assert_eq!(GenericAsset::free_balance(&ASSET, &2), 1_000);
+			assert_eq!(GenericAsset::free_balance(&ASSET, &3), 0);
+			assert_eq!(Vesting::vesting((3, ASSET)), None);
+		});
+	}
+}
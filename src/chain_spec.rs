@@ -1,7 +1,8 @@
 use cennznet_runtime_template_runtime::{
-    fees, generic_asset, AccountId, CennzxSpotConfig, ConsensusConfig, ContractConfig, Fee,
-    FeeRate, FeesConfig, GenericAssetConfig, GenesisConfig, GrandpaConfig, IndicesConfig, Schedule,
-    SessionConfig, StakerStatus, StakingConfig, SudoConfig, TimestampConfig,
+    fees, generic_asset, AccountId, CennzxSpotConfig, ConsensusConfig, ContractConfig, ContractGasConfig, Fee,
+    FeeRate, FeesConfig, GenericAssetConfig, GenesisConfig, GrandpaConfig, IndicesConfig, OracleConfig,
+    OrderBookConfig, ReapingConfig, Schedule, SchedulerConfig, SessionConfig, StakerStatus, StakingConfig, SudoConfig,
+    TimestampConfig, VestingConfig,
 };
 use primitives::{ed25519, sr25519, Pair};
 use substrate_service;
@@ -178,6 +179,26 @@ fn testnet_genesis(
 			fee_rate: FeeRate::from_milli(3),
 			core_asset_id: 16001,
 		}),
+		oracle: Some(OracleConfig {
+			feeders: vec![root_key.clone()],
+			round_duration: 10,
+			min_submissions: 1,
+			max_price_age: 30,
+		}),
+		reaping: Some(ReapingConfig {
+			tracked_assets: vec![],
+			dust_threshold: 0,
+			dust_receiver: root_key.clone(),
+		}),
+		vesting: Some(VestingConfig {
+			vesting: vec![],
+		}),
+		orderbook: Some(OrderBookConfig {
+			order_deposit: 100,
+		}),
+		scheduler: Some(SchedulerConfig {
+			task_deposit: 100,
+		}),
 		sudo: Some(SudoConfig {
 			key: root_key,
 		}),
@@ -206,5 +227,8 @@ fn testnet_genesis(
 				..Default::default()
 			},
 		}),
+		contract_gas: Some(ContractGasConfig {
+			gas_asset_id: 16001,
+		}),
 	}
 }
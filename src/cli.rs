@@ -1,14 +1,112 @@
 use crate::chain_spec;
+use crate::indexer;
+use crate::key::{self, KeyCmd};
+use crate::metrics;
+use crate::price_feed::PriceFeedConfig;
+use crate::rpc;
+use crate::sealing::{self, Sealer, Sealing};
 use crate::service;
+use crate::signer::{Endpoint, RemoteSigner};
 use futures::{future, sync::oneshot, Future};
+use jsonrpc_pubsub::PubSubHandler;
 use log::info;
+use primitives::{crypto::Ss58Codec, ed25519, sr25519, Pair};
 use std::cell::RefCell;
+use std::net::{Ipv4Addr, SocketAddr};
 use std::ops::Deref;
+use std::path::PathBuf;
+use std::sync::Arc;
+use structopt::StructOpt;
 pub use substrate_cli::{error, IntoExit, VersionInfo};
-use substrate_cli::{informant, parse_and_execute, NoCustom};
+use substrate_cli::{impl_augment_clap, informant, parse_and_execute, GetLogFilter};
 use substrate_service::{Roles as ServiceRoles, ServiceFactory};
 use tokio::runtime::Runtime;
 
+/// Node-specific options of the `run` command.
+#[derive(Debug, StructOpt, Clone, Default)]
+pub struct NodeParams {
+    /// Serve the node-specific RPC methods (e.g. `contracts_call`) over HTTP on this local port
+    #[structopt(long = "rpc-extensions-port", value_name = "PORT")]
+    pub rpc_extensions_port: Option<u16>,
+
+    /// Serve the node-specific subscriptions (e.g. `events_subscribe`) over WebSocket on this
+    /// local port
+    #[structopt(long = "rpc-extensions-ws-port", value_name = "PORT")]
+    pub rpc_extensions_ws_port: Option<u16>,
+
+    /// Report equivocations seen in imported blocks, signed by the sr25519 account with the
+    /// secret URI (e.g. `//Alice`) on the first line of this file
+    #[structopt(long = "equivocation-reporter-file", value_name = "PATH", parse(from_os_str))]
+    pub equivocation_reporter_file: Option<PathBuf>,
+
+    /// Seal blocks as soon as transactions enter the pool (`instant`) or on `engine_createBlock`
+    /// RPC calls (`manual`) instead of authoring with Aura. For development only.
+    #[structopt(
+        long = "sealing",
+        value_name = "MODE",
+        raw(possible_values = r#"&["instant", "manual"]"#)
+    )]
+    pub sealing: Option<Sealing>,
+
+    /// Finalize blocks as soon as they are sealed instantly
+    #[structopt(long = "instant-finality")]
+    pub instant_finality: bool,
+
+    /// Serve Prometheus metrics over HTTP on this local port
+    #[structopt(long = "prometheus-port", value_name = "PORT")]
+    pub prometheus_port: Option<u16>,
+
+    /// Index transfers, fees, CENNZX trades and staking events of the best chain into the
+    /// SQLite database at this path, queryable via the `indexer_*` RPC methods
+    #[structopt(long = "indexer-db", value_name = "PATH", parse(from_os_str))]
+    pub indexer_db: Option<PathBuf>,
+
+    /// Also author with the password-encrypted keys in the keystore (see `key insert
+    /// --password-file`), decrypted with the password in this file
+    #[structopt(long = "keystore-password-file", value_name = "PATH", parse(from_os_str))]
+    pub keystore_password_file: Option<PathBuf>,
+
+    /// Author with a key held by the signing service at this endpoint (`unix:<path>` or
+    /// `<host>:<port>`) instead of a keystore key
+    #[structopt(
+        long = "remote-signer",
+        value_name = "ENDPOINT",
+        requires = "remote_signer_key",
+        conflicts_with = "keystore_password_file"
+    )]
+    pub remote_signer: Option<Endpoint>,
+
+    /// The SS58 address of the authority key held by the remote signer
+    #[structopt(long = "remote-signer-key", value_name = "ADDRESS")]
+    pub remote_signer_key: Option<String>,
+
+    /// Feed the oracle the prices served as JSON at this HTTP URL once per round, as unsigned
+    /// transactions signed by the feeder in `--price-feed-feeder-file`
+    #[structopt(long = "price-feed-url", value_name = "URL", requires = "price_feed_feeder_file")]
+    pub price_feed_url: Option<hyper::Uri>,
+
+    /// The sr25519 oracle feeder account signing price submissions, by the secret URI on the
+    /// first line of this file
+    #[structopt(long = "price-feed-feeder-file", value_name = "PATH", parse(from_os_str))]
+    pub price_feed_feeder_file: Option<PathBuf>,
+}
+
+impl_augment_clap!(NodeParams);
+
+/// Node-specific subcommands.
+#[derive(Debug, StructOpt, Clone)]
+pub enum CustomSubcommands {
+    /// Generate, inspect and insert keys
+    #[structopt(name = "key")]
+    Key(KeyCmd),
+}
+
+impl GetLogFilter for CustomSubcommands {
+    fn get_log_filter(&self) -> Option<String> {
+        None
+    }
+}
+
 /// Parse command line arguments into service configuration.
 pub fn run<I, T, E>(args: I, exit: E, version: VersionInfo) -> error::Result<()>
 where
@@ -16,13 +114,22 @@ where
     T: Into<std::ffi::OsString> + Clone,
     E: IntoExit,
 {
-    parse_and_execute::<service::Factory, NoCustom, NoCustom, _, _, _, _, _>(
+    let subcommand = parse_and_execute::<
+        service::Factory,
+        CustomSubcommands,
+        NodeParams,
+        _,
+        _,
+        _,
+        _,
+        _,
+    >(
         load_spec,
         &version,
         "cennznet-runtime-template-node",
         args,
         exit,
-        |exit, _cli_args, _custom_args, config| {
+        |exit, _cli_args, node_params, mut config| {
             info!("{}", version.name);
             info!("  version {}", config.full_version());
             info!("  by {}, 2019", version.author);
@@ -38,20 +145,113 @@ where
                         .map_err(|e| format!("{:?}", e))?,
                     exit,
                 ),
-                _ => run_until_exit(
-                    runtime,
-                    service::Factory::new_full(config, executor).map_err(|e| format!("{:?}", e))?,
-                    exit,
-                ),
+                _ => {
+                    if node_params.sealing == Some(Sealing::Manual)
+                        && node_params.rpc_extensions_port.is_none()
+                    {
+                        return Err("Manual sealing requires --rpc-extensions-port".into());
+                    }
+                    config.custom.sealing = node_params.sealing;
+                    configure_authority_key(&node_params, &mut config.custom)?;
+                    if let Some(path) = &node_params.equivocation_reporter_file {
+                        let suri = key::read_password(path)?;
+                        let pair = sr25519::Pair::from_string(&suri, None)
+                            .map_err(|e| format!("Invalid equivocation reporter: {:?}", e))?;
+                        config.custom.equivocation_reporter = Some(Arc::new(pair));
+                    }
+                    if let (Some(url), Some(path)) =
+                        (&node_params.price_feed_url, &node_params.price_feed_feeder_file)
+                    {
+                        let suri = key::read_password(path)?;
+                        let feeder = sr25519::Pair::from_string(&suri, None)
+                            .map_err(|e| format!("Invalid price feed feeder: {:?}", e))?;
+                        config.custom.price_feed =
+                            Some(PriceFeedConfig { url: url.clone(), feeder: Arc::new(feeder) });
+                    }
+                    let service = service::Factory::new_full(config, executor.clone())
+                        .map_err(|e| format!("{:?}", e))?;
+                    let sealer = node_params.sealing.map(|mode| {
+                        let sealer = Arc::new(Sealer::new(&service));
+                        if mode == Sealing::Instant {
+                            sealing::start_instant_sealing(
+                                sealer.clone(),
+                                &service,
+                                &executor,
+                                node_params.instant_finality,
+                            );
+                        }
+                        sealer
+                    });
+                    if let Some(port) = node_params.prometheus_port {
+                        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+                        metrics::start(&service, &executor, &addr)?;
+                    }
+                    let indexer = match &node_params.indexer_db {
+                        Some(path) => Some(indexer::start(&service, &executor, path)?),
+                        None => None,
+                    };
+                    let _rpc_extensions = match node_params.rpc_extensions_port {
+                        Some(port) => {
+                            let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+                            info!("Node RPC extensions listening on {}", addr);
+                            let mut io = rpc::extensions(service.client());
+                            if let Some(sealer) = sealer {
+                                io.extend_with(rpc::EngineRpc::to_delegate(rpc::Engine::new(sealer)));
+                            }
+                            if let Some(indexer) = indexer {
+                                io.extend_with(rpc::IndexerRpc::to_delegate(rpc::IndexerQueries::new(
+                                    indexer,
+                                )));
+                            }
+                            Some(rpc::start_http(&addr, io).map_err(|e| format!("{:?}", e))?)
+                        }
+                        None => None,
+                    };
+                    let _rpc_extensions_ws = match node_params.rpc_extensions_ws_port {
+                        Some(port) => {
+                            let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+                            info!("Node RPC extensions (WebSocket) listening on {}", addr);
+                            let mut io = PubSubHandler::default();
+                            io.extend_with(rpc::EventsRpc::to_delegate(rpc::Events::new(
+                                service.client(),
+                                executor.clone(),
+                            )));
+                            Some(rpc::start_ws(&addr, io).map_err(|e| format!("{:?}", e))?)
+                        }
+                        None => None,
+                    };
+                    run_until_exit(runtime, service, exit)
+                }
             }
             .map_err(|e| format!("{:?}", e))
         },
-    )
-    .map_err(Into::into)
-    .map(|_| ())
+    )?;
+
+    match subcommand {
+        Some(CustomSubcommands::Key(cmd)) => cmd.run().map_err(Into::into),
+        None => Ok(()),
+    }
+}
+
+/// Configure authoring with encrypted keystore keys or a remote signer, if requested.
+fn configure_authority_key(
+    node_params: &NodeParams,
+    config: &mut service::NodeConfig,
+) -> Result<(), String> {
+    if let Some(password_file) = &node_params.keystore_password_file {
+        config.keystore_password = Some(key::read_password(password_file)?);
+    }
+    if let (Some(endpoint), Some(address)) =
+        (&node_params.remote_signer, &node_params.remote_signer_key)
+    {
+        let public = ed25519::Public::from_ss58check(address)
+            .map_err(|e| format!("Invalid remote signer key: {:?}", e))?;
+        config.remote_signer = Some(RemoteSigner::new(endpoint.clone(), public));
+    }
+    Ok(())
 }
 
-fn load_spec(id: &str) -> Result<Option<chain_spec::ChainSpec>, String> {
+pub(crate) fn load_spec(id: &str) -> Result<Option<chain_spec::ChainSpec>, String> {
     Ok(match chain_spec::Alternative::from(id) {
         Some(spec) => Some(spec.load()?),
         None => None,
@@ -0,0 +1,144 @@
+//! Detection of Aura equivocations in imported blocks, reported to the runtime's
+//! `AuraEquivocation` module by an extrinsic signed with a reporter account.
+
+use cennznet_runtime_template_runtime::{
+    aura_equivocation, opaque::Block, AccountId, Call, Index, RuntimeApi, UncheckedExtrinsic,
+};
+use log::{info, warn};
+use parity_codec::{Compact, Decode, Encode};
+use primitives::{blake2_256, sr25519, storage::StorageKey, Blake2Hasher, Pair};
+use runtime_primitives::generic::{BlockId, DigestItem, Era};
+use runtime_primitives::traits::{Block as BlockT, Header as HeaderT};
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+use substrate_client::{backend::Backend, CallExecutor, Client};
+use transaction_pool::txpool::{ChainApi, Pool};
+
+type Header = <Block as BlockT>::Header;
+
+/// Number of most recent slots whose headers are kept for detection.
+const SLOT_HISTORY: u64 = 1024;
+
+/// Watches imported headers for two different headers sealed in the same slot.
+pub struct EquivocationReporter<B, E, A: ChainApi> {
+    client: Arc<Client<B, E, Block, RuntimeApi>>,
+    transaction_pool: Arc<Pool<A>>,
+    reporter: Arc<sr25519::Pair>,
+    headers: BTreeMap<u64, Vec<Header>>,
+    reported: HashSet<u64>,
+    next_index: Option<Index>,
+}
+
+impl<B, E, A> EquivocationReporter<B, E, A>
+where
+    B: Backend<Block, Blake2Hasher>,
+    E: CallExecutor<Block, Blake2Hasher> + Clone,
+    A: ChainApi<Block = Block>,
+{
+    /// Create a reporter submitting reports to `transaction_pool`, signed by `reporter`.
+    pub fn new(
+        client: Arc<Client<B, E, Block, RuntimeApi>>,
+        transaction_pool: Arc<Pool<A>>,
+        reporter: Arc<sr25519::Pair>,
+    ) -> Self {
+        EquivocationReporter {
+            client,
+            transaction_pool,
+            reporter,
+            headers: BTreeMap::new(),
+            reported: HashSet::new(),
+            next_index: None,
+        }
+    }
+
+    /// Check the newly imported `header` against the headers seen for its slot.
+    pub fn on_block_imported(&mut self, header: &Header) {
+        let slot = match header.digest().logs.last() {
+            Some(DigestItem::Seal(slot, _)) => *slot,
+            _ => return,
+        };
+
+        let seen = self.headers.entry(slot).or_insert_with(Vec::new);
+        if seen.iter().any(|h| h.hash() == header.hash()) {
+            return;
+        }
+        let equivocation = seen.first().cloned();
+        seen.push(header.clone());
+
+        let latest = *self.headers.keys().next_back().expect("a slot was just inserted; qed");
+        self.headers = self.headers.split_off(&latest.saturating_sub(SLOT_HISTORY));
+
+        if let Some(first) = equivocation {
+            if self.reported.insert(slot) {
+                warn!("Slot {} author equivocated: {} and {}", slot, first.hash(), header.hash());
+                if let Err(e) = self.report(first, header.clone()) {
+                    warn!("Failed to report equivocation in slot {}: {}", slot, e);
+                }
+            }
+        }
+    }
+
+    /// Submit a report of `first` and `second` to the transaction pool.
+    fn report(&mut self, first: Header, second: Header) -> Result<(), String> {
+        let info = self.client.info().map_err(|e| format!("{:?}", e))?;
+        let at = BlockId::hash(info.chain.best_hash);
+        let call = Call::AuraEquivocation(aura_equivocation::Call::report_equivocation(first, second));
+        let xt_index = self.next_index(&at)?;
+        let xt = self.sign(call, xt_index, info.chain.genesis_hash)?;
+        let xt = Decode::decode(&mut &xt.encode()[..]).ok_or("failed to convert extrinsic")?;
+        let hash = self
+            .transaction_pool
+            .submit_one(&at, xt)
+            .map_err(|e| format!("{:?}", e))?;
+        self.next_index = Some(xt_index + 1);
+        info!("Submitted equivocation report {:?}", hash);
+        Ok(())
+    }
+
+    /// The nonce for the next report: the reporter's nonce at `at`, or the nonce after the last
+    /// submitted report if that is not yet included, so reports in the same block do not reuse
+    /// a nonce.
+    fn next_index(&self, at: &BlockId<Block>) -> Result<Index, String> {
+        let nonce = self.account_nonce(at, &self.reporter.public())?;
+        Ok(self.next_index.map_or(nonce, |next| next.max(nonce)))
+    }
+
+    /// Sign `call` as an immortal extrinsic of the reporter account with nonce `index`.
+    fn sign(
+        &self,
+        call: Call,
+        index: Index,
+        genesis_hash: <Block as BlockT>::Hash,
+    ) -> Result<UncheckedExtrinsic, String> {
+        let account: AccountId = self.reporter.public();
+        let payload = (Compact(index), call, Era::Immortal, genesis_hash);
+        let signature = payload.using_encoded(|b| {
+            if b.len() > 256 {
+                self.reporter.sign(&blake2_256(b))
+            } else {
+                self.reporter.sign(b)
+            }
+        });
+        let (_, call, era, _) = payload;
+        Ok(UncheckedExtrinsic::new_signed(
+            index,
+            call,
+            account.into(),
+            signature.into(),
+            era,
+        ))
+    }
+
+    /// The nonce of `account` at block `at`, read from `System` storage.
+    fn account_nonce(&self, at: &BlockId<Block>, account: &AccountId) -> Result<Index, String> {
+        let mut key = b"System AccountNonce".to_vec();
+        account.encode_to(&mut key);
+        let nonce = self
+            .client
+            .storage(at, &StorageKey(blake2_256(&key).to_vec()))
+            .map_err(|e| format!("{:?}", e))?;
+        Ok(nonce
+            .and_then(|data| Decode::decode(&mut &data.0[..]))
+            .unwrap_or_default())
+    }
+}
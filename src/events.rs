@@ -0,0 +1,163 @@
+//! Decoding and filtering of the runtime events deposited in a block.
+
+use crate::service::Factory;
+use cennznet_runtime_template_runtime::{opaque::Block, system, AccountId, Event, Hash};
+use parity_codec::{Compact, Decode, Encode};
+use primitives::{storage::StorageKey, twox_128, Bytes};
+use runtime_primitives::generic::BlockId;
+use runtime_primitives::traits::{Block as BlockT, Header as HeaderT};
+use serde::{Deserialize, Serialize};
+use substrate_service::FullClient;
+
+type Header = <Block as BlockT>::Header;
+
+/// Selects the events sent to a subscriber.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct EventFilter {
+    /// Modules whose events are included by runtime name (e.g. `CennzxSpot`), or all if empty
+    pub modules: Vec<String>,
+    /// Event variants included (e.g. `AssetPurchase`), or all if empty
+    pub variants: Vec<String>,
+    /// Only include events with this account among their fields. Fields are decoded by the
+    /// type names in the event metadata, up to the first field of a type unknown to the node.
+    pub account: Option<AccountId>,
+    /// Emit events when their block is finalized instead of when it becomes the best block
+    pub finalized: bool,
+}
+
+/// A runtime event matching a subscriber's filter.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FilteredEvent {
+    block_hash: Hash,
+    block_number: u64,
+    extrinsic_index: Option<u32>,
+    module: String,
+    variant: String,
+    /// The decoded event, `Debug` formatted
+    description: String,
+    /// The SCALE encoded event
+    data: Bytes,
+}
+
+impl EventFilter {
+    fn matches(&self, module: &str, variant: &str, fields: &[&str], data: &[u8]) -> bool {
+        (self.modules.is_empty() || self.modules.iter().any(|m| same_module(m, module)))
+            && (self.variants.is_empty() || self.variants.iter().any(|v| v == variant))
+            && self.account.as_ref().map_or(true, |account| {
+                // The outer event is encoded as the module and variant indices, then the fields
+                data.get(2..)
+                    .map_or(false, |data| accounts(fields, data).contains(account))
+            })
+    }
+}
+
+/// Whether `name`, as given by a subscriber, refers to the module `module`. Runtime names
+/// (`CennzxSpot`) and crate names (`cennzx_spot`) are both accepted.
+fn same_module(name: &str, module: &str) -> bool {
+    let normalize = |s: &str| s.replace('_', "").to_lowercase();
+    normalize(name) == normalize(module)
+}
+
+/// The accounts among the SCALE encoded event `fields`, whose type names are `types`.
+fn accounts(types: &[&str], mut fields: &[u8]) -> Vec<AccountId> {
+    let mut accounts = Vec::new();
+    for ty in types {
+        if decode_field(&ty.replace(' ', ""), &mut fields, &mut accounts).is_none() {
+            break;
+        }
+    }
+    accounts
+}
+
+/// Decode a field of the type named `ty` from `input`, collecting the accounts in it. `None` if
+/// the type is unknown or the input is invalid.
+fn decode_field(ty: &str, input: &mut &[u8], accounts: &mut Vec<AccountId>) -> Option<()> {
+    let argument = |wrapper: &str| {
+        if ty.starts_with(wrapper) && ty[wrapper.len()..].starts_with('<') && ty.ends_with('>') {
+            Some(&ty[wrapper.len() + 1..ty.len() - 1])
+        } else {
+            None
+        }
+    };
+    if let Some(inner) = argument("Option") {
+        return match u8::decode(input)? {
+            0 => Some(()),
+            1 => decode_field(inner, input, accounts),
+            _ => None,
+        };
+    }
+    if let Some(inner) = argument("Vec") {
+        let Compact(len) = Compact::<u32>::decode(input)?;
+        for _ in 0..len {
+            decode_field(inner, input, accounts)?;
+        }
+        return Some(());
+    }
+
+    let len = match ty {
+        "AccountId" => {
+            accounts.push(AccountId::decode(input)?);
+            return Some(());
+        }
+        "Hash" | "AuthorityId" | "SessionKey" => 32,
+        "Balance" | "u128" => 16,
+        "BlockNumber" | "Gas" | "OrderId" | "Round" | "TaskId" | "u64" => 8,
+        "AssetId" | "AccountIndex" | "u32" => 4,
+        "CallIndex" | "u16" => 2,
+        "ProxyType" | "Side" | "bool" | "u8" => 1,
+        _ => return None,
+    };
+    if input.len() < len {
+        return None;
+    }
+    *input = &input[len..];
+    Some(())
+}
+
+/// The events deposited in block `id`, read from `System` storage.
+pub(crate) fn records(
+    client: &FullClient<Factory>,
+    id: &BlockId<Block>,
+) -> Result<Vec<system::EventRecord<Event, Hash>>, String> {
+    let events = client
+        .storage(id, &StorageKey(twox_128(b"System Events").to_vec()))
+        .map_err(|e| format!("{:?}", e))?;
+    Ok(events
+        .and_then(|data| Decode::decode(&mut &data.0[..]))
+        .unwrap_or_default())
+}
+
+/// The events deposited in the block `header` matching `filter`.
+pub fn block_events(
+    client: &FullClient<Factory>,
+    header: &Header,
+    filter: &EventFilter,
+) -> Result<Vec<FilteredEvent>, String> {
+    let block_hash = header.hash();
+    let records = records(client, &BlockId::hash(block_hash))?;
+    Ok(records
+        .into_iter()
+        .filter_map(|record| {
+            let (module, variant) = record.event.metadata();
+            let (variant, fields) = variant.unwrap_or_default();
+            let data = record.event.encode();
+            if !filter.matches(module, variant, fields, &data) {
+                return None;
+            }
+            Some(FilteredEvent {
+                block_hash,
+                block_number: *header.number(),
+                extrinsic_index: match record.phase {
+                    system::Phase::ApplyExtrinsic(index) => Some(index),
+                    _ => None,
+                },
+                module: module.into(),
+                variant: variant.into(),
+                data: data.into(),
+                description: format!("{:?}", record.event),
+            })
+        })
+        .collect())
+}
@@ -0,0 +1,475 @@
+//! Indexes transfers, fees, CENNZX trades and staking events of the best chain into SQLite.
+//!
+//! Each new best block is indexed along with any of its ancestors not yet indexed. Blocks
+//! retracted by a reorganisation are removed first, so the database follows the best chain.
+//! Indexing runs on its own thread and commits `BATCH_SIZE` blocks at a time, so the first run's
+//! backfill of the whole chain neither holds up block import nor locks out queries.
+//! Fees are attributed to the sponsor who paid them, or else to the signer of the extrinsic they
+//! were charged for. Signers given by account index are resolved to their account id.
+
+use crate::events;
+use crate::service::Factory;
+use cennznet_runtime_template_runtime::{
+    cennzx_spot, fees, generic_asset, indices, opaque::Block, sponsorship, staking, system,
+    AccountId, AccountIndex, Address, Event, UncheckedExtrinsic,
+};
+use futures::{Future, Stream};
+use log::{info, warn};
+use parity_codec::{Decode, Encode};
+use parking_lot::Mutex;
+use primitives::{blake2_256, crypto::Ss58Codec, storage::StorageKey};
+use runtime_primitives::generic::BlockId;
+use runtime_primitives::traits::{Block as BlockT, Header as HeaderT};
+use rusqlite::{params, Connection, Transaction};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use substrate_service::{FullClient, FullComponents, TaskExecutor};
+
+type Header = <Block as BlockT>::Header;
+
+/// Number of blocks indexed per database transaction.
+const BATCH_SIZE: usize = 1024;
+
+/// Number of accounts in an `Indices` enum set.
+const ENUM_SET_SIZE: AccountIndex = 64;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS blocks (
+    number INTEGER PRIMARY KEY,
+    hash TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS extrinsics (
+    block_number INTEGER NOT NULL,
+    extrinsic_index INTEGER NOT NULL,
+    hash TEXT NOT NULL,
+    signer TEXT,
+    module TEXT NOT NULL,
+    PRIMARY KEY (block_number, extrinsic_index)
+);
+CREATE TABLE IF NOT EXISTS transfers (
+    block_number INTEGER NOT NULL,
+    extrinsic_index INTEGER,
+    asset_id INTEGER NOT NULL,
+    sender TEXT NOT NULL,
+    recipient TEXT NOT NULL,
+    amount TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS transfers_sender ON transfers (sender);
+CREATE INDEX IF NOT EXISTS transfers_recipient ON transfers (recipient);
+CREATE TABLE IF NOT EXISTS fees (
+    block_number INTEGER NOT NULL,
+    extrinsic_index INTEGER NOT NULL,
+    payer TEXT,
+    amount TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS fees_payer ON fees (payer);
+CREATE TABLE IF NOT EXISTS trades (
+    block_number INTEGER NOT NULL,
+    extrinsic_index INTEGER,
+    trader TEXT NOT NULL,
+    asset_sold INTEGER NOT NULL,
+    asset_bought INTEGER NOT NULL,
+    amount_sold TEXT NOT NULL,
+    amount_bought TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS trades_trader ON trades (trader);
+CREATE TABLE IF NOT EXISTS staking_events (
+    block_number INTEGER NOT NULL,
+    extrinsic_index INTEGER,
+    kind TEXT NOT NULL,
+    account TEXT,
+    amount TEXT
+);
+CREATE INDEX IF NOT EXISTS staking_events_account ON staking_events (account);
+";
+
+/// Tables holding rows per block, cleared when their block is retracted.
+const BLOCK_TABLES: &[&str] = &["blocks", "extrinsics", "transfers", "fees", "trades", "staking_events"];
+
+/// A generic asset transfer.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Transfer {
+    block_number: u64,
+    extrinsic_index: Option<u32>,
+    asset_id: u32,
+    from: String,
+    to: String,
+    amount: String,
+}
+
+/// A fee charged for an extrinsic.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FeeCharged {
+    block_number: u64,
+    extrinsic_index: u32,
+    payer: Option<String>,
+    amount: String,
+}
+
+/// A CENNZX-Spot asset swap.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Trade {
+    block_number: u64,
+    extrinsic_index: Option<u32>,
+    trader: String,
+    asset_sold: u32,
+    asset_bought: u32,
+    amount_sold: String,
+    amount_bought: String,
+}
+
+/// A staking reward, offline warning or slash.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StakingEvent {
+    block_number: u64,
+    extrinsic_index: Option<u32>,
+    kind: String,
+    account: Option<String>,
+    amount: Option<String>,
+}
+
+/// An SQLite index of the best chain.
+pub struct Indexer {
+    db: Mutex<Connection>,
+}
+
+impl Indexer {
+    /// Open or create the database at `path`.
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        let db = Connection::open(path)?;
+        db.execute_batch(SCHEMA)?;
+        Ok(Indexer { db: Mutex::new(db) })
+    }
+
+    /// Index the new best block `header` and any of its ancestors not yet indexed, in batches
+    /// of `BATCH_SIZE` blocks. An interrupted run resumes after the last committed batch.
+    fn on_new_best(&self, client: &FullClient<Factory>, header: &Header) -> Result<(), String> {
+        let mut retracted_above = *header.number();
+        let mut path = Vec::new();
+        let mut current = header.clone();
+        while *current.number() > 0
+            && indexed_hash(&self.db.lock(), *current.number())? != Some(hex(&current.hash()))
+        {
+            let parent = client
+                .header(&BlockId::hash(*current.parent_hash()))
+                .map_err(|e| format!("{:?}", e))?
+                .ok_or("Missing parent header")?;
+            path.push(current);
+            current = parent;
+            retracted_above = *current.number();
+        }
+        path.reverse();
+
+        let mut db = self.db.lock();
+        let tx = db.transaction().map_err(|e| format!("{:?}", e))?;
+        for table in BLOCK_TABLES {
+            let column = if *table == "blocks" { "number" } else { "block_number" };
+            tx.execute(
+                &format!("DELETE FROM {} WHERE {} > ?1", table, column),
+                params![retracted_above as i64],
+            )
+            .map_err(|e| format!("{:?}", e))?;
+        }
+        tx.commit().map_err(|e| format!("{:?}", e))?;
+        drop(db);
+
+        for batch in path.chunks(BATCH_SIZE) {
+            let mut db = self.db.lock();
+            let tx = db.transaction().map_err(|e| format!("{:?}", e))?;
+            for header in batch {
+                index_block(&tx, client, header)?;
+            }
+            tx.commit().map_err(|e| format!("{:?}", e))?;
+            if path.len() > BATCH_SIZE {
+                info!("Indexed blocks up to #{}", batch[batch.len() - 1].number());
+            }
+        }
+        Ok(())
+    }
+
+    /// Transfers from or to `account`, most recent first.
+    pub fn transfers(&self, account: &AccountId, limit: u32) -> rusqlite::Result<Vec<Transfer>> {
+        let db = self.db.lock();
+        let mut statement = db.prepare(
+            "SELECT block_number, extrinsic_index, asset_id, sender, recipient, amount FROM transfers
+             WHERE sender = ?1 OR recipient = ?1 ORDER BY block_number DESC LIMIT ?2",
+        )?;
+        let rows = statement.query_map(params![account.to_ss58check(), limit], |row| {
+            Ok(Transfer {
+                block_number: row.get::<_, i64>(0)? as u64,
+                extrinsic_index: row.get(1)?,
+                asset_id: row.get(2)?,
+                from: row.get(3)?,
+                to: row.get(4)?,
+                amount: row.get(5)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Fees paid by `account`, most recent first.
+    pub fn fees(&self, account: &AccountId, limit: u32) -> rusqlite::Result<Vec<FeeCharged>> {
+        let db = self.db.lock();
+        let mut statement = db.prepare(
+            "SELECT block_number, extrinsic_index, payer, amount FROM fees
+             WHERE payer = ?1 ORDER BY block_number DESC LIMIT ?2",
+        )?;
+        let rows = statement.query_map(params![account.to_ss58check(), limit], |row| {
+            Ok(FeeCharged {
+                block_number: row.get::<_, i64>(0)? as u64,
+                extrinsic_index: row.get(1)?,
+                payer: row.get(2)?,
+                amount: row.get(3)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// CENNZX-Spot trades by `account`, most recent first.
+    pub fn trades(&self, account: &AccountId, limit: u32) -> rusqlite::Result<Vec<Trade>> {
+        let db = self.db.lock();
+        let mut statement = db.prepare(
+            "SELECT block_number, extrinsic_index, trader, asset_sold, asset_bought, amount_sold,
+             amount_bought FROM trades WHERE trader = ?1 ORDER BY block_number DESC LIMIT ?2",
+        )?;
+        let rows = statement.query_map(params![account.to_ss58check(), limit], |row| {
+            Ok(Trade {
+                block_number: row.get::<_, i64>(0)? as u64,
+                extrinsic_index: row.get(1)?,
+                trader: row.get(2)?,
+                asset_sold: row.get(3)?,
+                asset_bought: row.get(4)?,
+                amount_sold: row.get(5)?,
+                amount_bought: row.get(6)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Staking events of `account`, or of all accounts if `None`, most recent first.
+    pub fn staking_events(
+        &self,
+        account: Option<&AccountId>,
+        limit: u32,
+    ) -> rusqlite::Result<Vec<StakingEvent>> {
+        let db = self.db.lock();
+        let mut statement = db.prepare(
+            "SELECT block_number, extrinsic_index, kind, account, amount FROM staking_events
+             WHERE ?1 IS NULL OR account = ?1 ORDER BY block_number DESC LIMIT ?2",
+        )?;
+        let account = account.map(|a| a.to_ss58check());
+        let rows = statement.query_map(params![account, limit], |row| {
+            Ok(StakingEvent {
+                block_number: row.get::<_, i64>(0)? as u64,
+                extrinsic_index: row.get(1)?,
+                kind: row.get(2)?,
+                account: row.get(3)?,
+                amount: row.get(4)?,
+            })
+        })?;
+        rows.collect()
+    }
+}
+
+/// The hash of the indexed block at `number`, if any.
+fn indexed_hash(db: &Connection, number: u64) -> Result<Option<String>, String> {
+    let mut statement = db
+        .prepare_cached("SELECT hash FROM blocks WHERE number = ?1")
+        .map_err(|e| format!("{:?}", e))?;
+    let mut rows = statement
+        .query(params![number as i64])
+        .map_err(|e| format!("{:?}", e))?;
+    match rows.next().map_err(|e| format!("{:?}", e))? {
+        Some(row) => row.get(0).map(Some).map_err(|e| format!("{:?}", e)),
+        None => Ok(None),
+    }
+}
+
+/// Write the extrinsics and events of the block `header` into `tx`.
+fn index_block(tx: &Transaction, client: &FullClient<Factory>, header: &Header) -> Result<(), String> {
+    let id = BlockId::hash(header.hash());
+    let number = *header.number() as i64;
+    let sql_error = |e: rusqlite::Error| format!("{:?}", e);
+    tx.execute(
+        "INSERT INTO blocks (number, hash) VALUES (?1, ?2)",
+        params![number, hex(&header.hash())],
+    )
+    .map_err(sql_error)?;
+
+    let body = client.body(&id).map_err(|e| format!("{:?}", e))?;
+    let mut signers = Vec::new();
+    for (index, extrinsic) in body.unwrap_or_default().iter().enumerate() {
+        let encoded = extrinsic.encode();
+        let xt: UncheckedExtrinsic = match Decode::decode(&mut &encoded[..]) {
+            Some(xt) => xt,
+            None => {
+                // Keep signers aligned with extrinsic indices
+                signers.push(None);
+                continue;
+            }
+        };
+        let signer = match &xt.signature {
+            Some((address, ..)) => account_of(client, &id, address)?,
+            None => None,
+        };
+        tx.execute(
+            "INSERT INTO extrinsics (block_number, extrinsic_index, hash, signer, module)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![number, index as u32, hex(&blake2_256(&encoded)), signer, xt.function.module_name()],
+        )
+        .map_err(sql_error)?;
+        signers.push(signer);
+    }
+
+    let records = events::records(client, &id)?;
+    let sponsors: HashMap<u32, String> = records
+        .iter()
+        .filter_map(|record| match (&record.phase, &record.event) {
+            (
+                system::Phase::ApplyExtrinsic(index),
+                Event::sponsorship(sponsorship::RawEvent::FeeSponsored(sponsor, ..)),
+            ) => Some((*index, sponsor.to_ss58check())),
+            _ => None,
+        })
+        .collect();
+
+    for record in records {
+        let extrinsic_index = match record.phase {
+            system::Phase::ApplyExtrinsic(index) => Some(index),
+            _ => None,
+        };
+        match record.event {
+            Event::generic_asset(generic_asset::RawEvent::Transferred(asset_id, from, to, amount)) => {
+                tx.execute(
+                    "INSERT INTO transfers (block_number, extrinsic_index, asset_id, sender, recipient, amount)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![number, extrinsic_index, asset_id, from.to_ss58check(), to.to_ss58check(), amount.to_string()],
+                )
+                .map_err(sql_error)?;
+            }
+            Event::fees(fees::RawEvent::Charged(index, amount)) => {
+                let payer = match sponsors.get(&index) {
+                    Some(sponsor) => Some(sponsor.clone()),
+                    None => signers.get(index as usize).cloned().unwrap_or_default(),
+                };
+                tx.execute(
+                    "INSERT INTO fees (block_number, extrinsic_index, payer, amount) VALUES (?1, ?2, ?3, ?4)",
+                    params![number, index, payer, amount.to_string()],
+                )
+                .map_err(sql_error)?;
+            }
+            Event::cennzx_spot(cennzx_spot::RawEvent::AssetPurchase(sold, bought, trader, sold_amount, bought_amount)) => {
+                tx.execute(
+                    "INSERT INTO trades (block_number, extrinsic_index, trader, asset_sold, asset_bought, amount_sold, amount_bought)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    params![
+                        number,
+                        extrinsic_index,
+                        trader.to_ss58check(),
+                        sold,
+                        bought,
+                        sold_amount.to_string(),
+                        bought_amount.to_string()
+                    ],
+                )
+                .map_err(sql_error)?;
+            }
+            Event::staking(event) => {
+                let (kind, account, amount) = match event {
+                    staking::RawEvent::Reward(amount) => ("Reward", None, Some(amount.to_string())),
+                    staking::RawEvent::OfflineWarning(who, _) => ("OfflineWarning", Some(who), None),
+                    staking::RawEvent::OfflineSlash(who, amount) => {
+                        ("OfflineSlash", Some(who), Some(amount.to_string()))
+                    }
+                    #[allow(unreachable_patterns)]
+                    _ => continue,
+                };
+                tx.execute(
+                    "INSERT INTO staking_events (block_number, extrinsic_index, kind, account, amount)
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![number, extrinsic_index, kind, account.map(|a| a.to_ss58check()), amount],
+                )
+                .map_err(sql_error)?;
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// The SS58 account id of `address` at block `id`, or `None` for an unassigned account index.
+fn account_of(
+    client: &FullClient<Factory>,
+    id: &BlockId<Block>,
+    address: &Address,
+) -> Result<Option<String>, String> {
+    let index = match address {
+        indices::address::Address::Id(account) => return Ok(Some(account.to_ss58check())),
+        indices::address::Address::Index(index) => *index,
+    };
+    let mut key = b"Indices EnumSet".to_vec();
+    (index / ENUM_SET_SIZE).encode_to(&mut key);
+    let set: Vec<AccountId> = client
+        .storage(id, &StorageKey(blake2_256(&key).to_vec()))
+        .map_err(|e| format!("{:?}", e))?
+        .and_then(|data| Decode::decode(&mut &data.0[..]))
+        .unwrap_or_default();
+    Ok(set
+        .get((index % ENUM_SET_SIZE) as usize)
+        .map(|account| account.to_ss58check()))
+}
+
+fn hex<T: AsRef<[u8]>>(bytes: &T) -> String {
+    format!("0x{}", primitives::hexdisplay::HexDisplay::from(bytes))
+}
+
+/// Index the best chain of `service` into the SQLite database at `path`.
+pub fn start(
+    service: &FullComponents<Factory>,
+    executor: &TaskExecutor,
+    path: &Path,
+) -> Result<Arc<Indexer>, String> {
+    let indexer = Arc::new(Indexer::open(path).map_err(|e| format!("{:?}", e))?);
+    info!("Indexing the best chain into {}", path.display());
+
+    // Blocks are indexed off the import notification task; the worker exits once the sender is
+    // dropped on shutdown
+    let (sender, receiver) = mpsc::channel::<Header>();
+    {
+        let indexer = indexer.clone();
+        let client = service.client();
+        thread::Builder::new()
+            .name("indexer".into())
+            .spawn(move || {
+                while let Ok(mut header) = receiver.recv() {
+                    // Ancestors of the latest best block are indexed along with it
+                    while let Ok(newer) = receiver.try_recv() {
+                        header = newer;
+                    }
+                    if let Err(e) = indexer.on_new_best(&client, &header) {
+                        warn!("Failed to index block {}: {}", header.hash(), e);
+                    }
+                }
+            })
+            .map_err(|e| format!("{:?}", e))?;
+    }
+
+    let on_import = service
+        .client()
+        .import_notification_stream()
+        .filter(|notification| notification.is_new_best)
+        .for_each(move |notification| {
+            let _ = sender.send(notification.header);
+            Ok(())
+        });
+    executor.spawn(on_import.select(service.on_exit()).then(|_| Ok(())));
+    Ok(indexer)
+}
+
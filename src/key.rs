@@ -0,0 +1,206 @@
+//! `key` subcommands to generate, inspect and insert keys without external tools.
+
+use crate::cli::load_spec;
+use crate::keystore;
+use crate::signer::{self, Endpoint};
+use primitives::crypto::Ss58Codec;
+use primitives::hexdisplay::HexDisplay;
+use primitives::{ed25519, sr25519, Pair};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use structopt::StructOpt;
+
+/// A signature scheme of keys.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scheme {
+    /// Schnorr over Ristretto, used by accounts
+    Sr25519,
+    /// Ed25519, used by authority (session) keys
+    Ed25519,
+}
+
+impl FromStr for Scheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sr25519" => Ok(Scheme::Sr25519),
+            "ed25519" => Ok(Scheme::Ed25519),
+            _ => Err(format!("Unknown signature scheme: {}", s)),
+        }
+    }
+}
+
+/// Key management subcommands.
+#[derive(Debug, StructOpt, Clone)]
+pub enum KeyCmd {
+    /// Generate a new key with a secret phrase
+    #[structopt(name = "generate")]
+    Generate {
+        /// The signature scheme of the key
+        #[structopt(
+            long = "scheme",
+            value_name = "SCHEME",
+            default_value = "sr25519",
+            raw(possible_values = r#"&["sr25519", "ed25519"]"#)
+        )]
+        scheme: Scheme,
+
+        /// Protect the secret phrase with the password in this file
+        #[structopt(long = "password-file", value_name = "PATH", parse(from_os_str))]
+        password_file: Option<PathBuf>,
+    },
+
+    /// Print the public key and SS58 address of a secret URI (e.g. `//Alice` or
+    /// `<phrase>//hard/soft`)
+    #[structopt(name = "inspect")]
+    Inspect {
+        /// The secret URI: a phrase or hex seed, optionally followed by a derivation path
+        #[structopt(value_name = "SURI")]
+        suri: String,
+
+        /// The signature scheme of the key
+        #[structopt(
+            long = "scheme",
+            value_name = "SCHEME",
+            default_value = "sr25519",
+            raw(possible_values = r#"&["sr25519", "ed25519"]"#)
+        )]
+        scheme: Scheme,
+
+        /// The file holding the password of the secret URI
+        #[structopt(long = "password-file", value_name = "PATH", parse(from_os_str))]
+        password_file: Option<PathBuf>,
+    },
+
+    /// Insert an authority (ed25519) key into the node keystore
+    #[structopt(name = "insert")]
+    Insert {
+        /// The secret URI of the key. Unless `--password-file` is given, it must be a plain
+        /// secret phrase, as the keystore stores phrases.
+        #[structopt(value_name = "SURI")]
+        suri: String,
+
+        /// Encrypt the key with the password in this file, to be loaded by a node run with
+        /// `--keystore-password-file`
+        #[structopt(long = "password-file", value_name = "PATH", parse(from_os_str))]
+        password_file: Option<PathBuf>,
+
+        /// The base path of the node
+        #[structopt(long = "base-path", short = "d", value_name = "PATH", parse(from_os_str))]
+        base_path: PathBuf,
+
+        /// The chain whose keystore the key is inserted into
+        #[structopt(long = "chain", value_name = "CHAIN_SPEC", default_value = "dev")]
+        chain: String,
+    },
+
+    /// Serve signatures by an authority (ed25519) key, as a stand-in for a remote signer used
+    /// with `--remote-signer`. For testing only: requests are not authenticated, so it only
+    /// listens on a Unix socket or localhost
+    #[structopt(name = "serve-signer")]
+    ServeSigner {
+        /// The secret URI of the key
+        #[structopt(value_name = "SURI")]
+        suri: String,
+
+        /// Where to listen: `unix:<path>` or `<host>:<port>`
+        #[structopt(long = "listen", value_name = "ENDPOINT")]
+        listen: Endpoint,
+    },
+}
+
+impl KeyCmd {
+    /// Run the subcommand, printing its output.
+    pub fn run(self) -> Result<(), String> {
+        match self {
+            KeyCmd::Generate { scheme, password_file } => {
+                let password = password_file.as_ref().map(|path| read_password(path)).transpose()?;
+                let password = password.as_ref().map(String::as_str);
+                let phrase = match scheme {
+                    Scheme::Sr25519 => sr25519::Pair::generate_with_phrase(password).1,
+                    Scheme::Ed25519 => ed25519::Pair::generate_with_phrase(password).1,
+                };
+                println!("Secret phrase: {}", phrase);
+                print_key(&phrase, scheme, password)
+            }
+            KeyCmd::Inspect { suri, scheme, password_file } => {
+                let password = password_file.as_ref().map(|path| read_password(path)).transpose()?;
+                print_key(&suri, scheme, password.as_ref().map(String::as_str))
+            }
+            KeyCmd::Insert { suri, password_file, base_path, chain } => {
+                let keystore = keystore_path(base_path, &chain)?;
+                match password_file {
+                    Some(password_file) => {
+                        let password = read_password(&password_file)?;
+                        let pair = ed25519::Pair::from_string(&suri, None)
+                            .map_err(|e| format!("Invalid secret URI: {:?}", e))?;
+                        let path = keystore::insert(&keystore, &pair, &password)?;
+                        println!("Inserted {} into {}", pair.public().to_ss58check(), path.display());
+                        Ok(())
+                    }
+                    None => insert(&suri, keystore),
+                }
+            }
+            KeyCmd::ServeSigner { suri, listen } => {
+                let pair = ed25519::Pair::from_string(&suri, None)
+                    .map_err(|e| format!("Invalid secret URI: {:?}", e))?;
+                signer::serve(&listen, pair)
+            }
+        }
+    }
+}
+
+/// Print the public key and address of the `scheme` key of `suri`.
+fn print_key(suri: &str, scheme: Scheme, password: Option<&str>) -> Result<(), String> {
+    fn print<P: Pair>(suri: &str, password: Option<&str>) -> Result<(), String>
+    where
+        P::Public: Ss58Codec + AsRef<[u8]>,
+    {
+        let pair = P::from_string(suri, password).map_err(|e| format!("Invalid secret URI: {:?}", e))?;
+        let public = pair.public();
+        println!("Public key (hex): 0x{}", HexDisplay::from(&public.as_ref()));
+        println!("Address (SS58): {}", public.to_ss58check());
+        Ok(())
+    }
+
+    match scheme {
+        Scheme::Sr25519 => print::<sr25519::Pair>(suri, password),
+        Scheme::Ed25519 => print::<ed25519::Pair>(suri, password),
+    }
+}
+
+/// The keystore path of `chain` under `base_path`, as used by the node.
+fn keystore_path(base_path: PathBuf, chain: &str) -> Result<PathBuf, String> {
+    let spec = load_spec(chain)?.ok_or_else(|| format!("Unknown chain: {}", chain))?;
+    let mut path = base_path;
+    path.push("chains");
+    path.push(spec.id());
+    path.push("keystore");
+    Ok(path)
+}
+
+/// Read a password from the first line of the file at `path`.
+pub fn read_password(path: &Path) -> Result<String, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("{:?}", e))?;
+    Ok(contents.lines().next().unwrap_or_default().to_string())
+}
+
+/// Write the ed25519 key of `phrase` into the keystore at `keystore`, in the keystore's format:
+/// a file named by the hex public key, holding the phrase as a JSON string.
+fn insert(phrase: &str, keystore: PathBuf) -> Result<(), String> {
+    // The node loads keystore keys with an empty password.
+    let pair = ed25519::Pair::from_phrase(phrase, Some(""))
+        .map_err(|_| "Invalid secret phrase".to_string())?;
+    let public = pair.public();
+
+    let mut path = keystore;
+    fs::create_dir_all(&path).map_err(|e| format!("{:?}", e))?;
+    path.push(format!("{}", HexDisplay::from(&public.as_ref())));
+
+    let json = serde_json::to_string(phrase).map_err(|e| format!("{:?}", e))?;
+    keystore::write_private(&path, json.as_bytes())?;
+    println!("Inserted {} into {}", public.to_ss58check(), path.display());
+    Ok(())
+}
@@ -0,0 +1,140 @@
+//! Loading of authority keys from the keystore, including password-encrypted keys.
+//!
+//! Each encrypted key file is named by the hex public key with an `.encrypted` extension, which the plain
+//! keystore ignores. It holds the SCALE encoded ed25519 seed, sealed with ChaCha20-Poly1305
+//! under a key derived from the password with PBKDF2.
+
+use log::warn;
+use parity_codec::{Decode, Encode};
+use primitives::{ed25519, hexdisplay::HexDisplay, Pair};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{aead, digest, pbkdf2};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+
+const EXTENSION: &str = "encrypted";
+const PBKDF2_ITERATIONS: u32 = 100_000;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+#[derive(Encode, Decode)]
+struct EncryptedKey {
+    salt: Vec<u8>,
+    nonce: Vec<u8>,
+    ciphertext: Vec<u8>,
+}
+
+fn derive_key(password: &str, salt: &[u8]) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    let iterations = NonZeroU32::new(PBKDF2_ITERATIONS).expect("iterations are non-zero; qed");
+    pbkdf2::derive(&digest::SHA256, iterations, salt, password.as_bytes(), &mut key);
+    key
+}
+
+fn nonce(bytes: &[u8]) -> Result<aead::Nonce, String> {
+    aead::Nonce::try_assume_unique_for_key(bytes).map_err(|_| "Invalid nonce".to_string())
+}
+
+/// Encrypt `pair` with `password` into the keystore at `keystore`, returning the key file path.
+pub fn insert(keystore: &Path, pair: &ed25519::Pair, password: &str) -> Result<PathBuf, String> {
+    let random = SystemRandom::new();
+    let mut salt = vec![0u8; SALT_LEN];
+    let mut nonce_bytes = vec![0u8; NONCE_LEN];
+    random
+        .fill(&mut salt)
+        .and_then(|_| random.fill(&mut nonce_bytes))
+        .map_err(|_| "Failed to generate randomness".to_string())?;
+
+    let key = aead::SealingKey::new(&aead::CHACHA20_POLY1305, &derive_key(password, &salt))
+        .map_err(|_| "Invalid encryption key".to_string())?;
+    let tag_len = aead::CHACHA20_POLY1305.tag_len();
+    let mut ciphertext = pair.seed().to_vec();
+    ciphertext.resize(ciphertext.len() + tag_len, 0);
+    let len = aead::seal_in_place(&key, nonce(&nonce_bytes)?, aead::Aad::empty(), &mut ciphertext, tag_len)
+        .map_err(|_| "Failed to encrypt key".to_string())?;
+    ciphertext.truncate(len);
+
+    fs::create_dir_all(keystore).map_err(|e| format!("{:?}", e))?;
+    let path = keystore.join(format!("{}.{}", HexDisplay::from(&pair.public().0), EXTENSION));
+    let encrypted = EncryptedKey { salt, nonce: nonce_bytes, ciphertext };
+    write_private(&path, &encrypted.encode())?;
+    Ok(path)
+}
+
+/// Write `contents` to the file at `path`, created readable and writable by its owner only.
+pub fn write_private(path: &Path, contents: &[u8]) -> Result<(), String> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path).map_err(|e| format!("{:?}", e))?;
+    file.write_all(contents).map_err(|e| format!("{:?}", e))
+}
+
+/// Decrypt the key in the file at `path` with `password`.
+fn decrypt(path: &Path, password: &str) -> Result<ed25519::Pair, String> {
+    let data = fs::read(path).map_err(|e| format!("{:?}", e))?;
+    let EncryptedKey { salt, nonce: nonce_bytes, mut ciphertext } =
+        Decode::decode(&mut &data[..]).ok_or("Invalid key file")?;
+
+    let key = aead::OpeningKey::new(&aead::CHACHA20_POLY1305, &derive_key(password, &salt))
+        .map_err(|_| "Invalid encryption key".to_string())?;
+    let seed = aead::open_in_place(&key, nonce(&nonce_bytes)?, aead::Aad::empty(), 0, &mut ciphertext)
+        .map_err(|_| "Wrong password or corrupted key file".to_string())?;
+    ed25519::Pair::from_seed_slice(seed).map_err(|e| format!("{:?}", e))
+}
+
+/// Load the phrase of the plain keystore key in the file at `path`.
+fn load_plain(path: &Path) -> Result<ed25519::Pair, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("{:?}", e))?;
+    let phrase: String = serde_json::from_str(&contents).map_err(|e| format!("{:?}", e))?;
+    // The node loads keystore keys with an empty password.
+    ed25519::Pair::from_phrase(&phrase, Some("")).map_err(|_| "Invalid secret phrase".to_string())
+}
+
+/// Bring `loaded` up to date with the keystore at `keystore`: drop removed keys and load keys
+/// not loaded yet, decrypting encrypted keys if `password` is given. Keys failing to load are
+/// recorded as `None` and retried on the next call, with the failure logged once.
+pub fn load(
+    keystore: &Path,
+    password: Option<&str>,
+    loaded: &mut HashMap<PathBuf, Option<ed25519::Pair>>,
+) {
+    let paths: Vec<PathBuf> = match fs::read_dir(keystore) {
+        Ok(entries) => entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect(),
+        Err(_) => Vec::new(),
+    };
+    loaded.retain(|path, _| paths.contains(path));
+    for path in paths {
+        if loaded.get(&path).map_or(false, Option::is_some) {
+            continue;
+        }
+        let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+        let pair = if path.extension().map_or(false, |extension| extension == EXTENSION) {
+            match password {
+                Some(password) => decrypt(&path, password),
+                None => continue,
+            }
+        } else if name.len() == 64 && name.chars().all(|c| c.is_ascii_hexdigit()) {
+            load_plain(&path)
+        } else {
+            continue;
+        };
+        let pair = match pair {
+            Ok(pair) => Some(pair),
+            Err(e) => {
+                if !loaded.contains_key(&path) {
+                    warn!("Failed to load key {}: {}", path.display(), e);
+                }
+                None
+            }
+        };
+        loaded.insert(path, pair);
+    }
+}
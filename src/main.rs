@@ -5,7 +5,17 @@
 
 mod chain_spec;
 mod cli;
+mod equivocation;
+mod events;
+mod indexer;
+mod key;
+mod keystore;
+mod metrics;
+mod price_feed;
+mod rpc;
+mod sealing;
 mod service;
+mod signer;
 
 pub use substrate_cli::{error, IntoExit, VersionInfo};
 
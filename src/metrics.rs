@@ -0,0 +1,247 @@
+//! Prometheus metrics of the full node, served over HTTP.
+
+use crate::service::Factory;
+use cennznet_runtime_template_runtime::{opaque::Block, Hash, UncheckedExtrinsic};
+use consensus::SlotDuration;
+use consensus_common::well_known_cache_keys::Id as CacheKeyId;
+use consensus_common::{BlockImport, Error as ConsensusError, ImportBlock, ImportResult};
+use futures::{future, Future, Stream};
+use hyper::{service::service_fn_ok, Body, Response, Server};
+use log::{info, warn};
+use network::SyncProvider;
+use parity_codec::{Decode, Encode};
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use runtime_primitives::generic::{BlockId, DigestItem};
+use runtime_primitives::traits::{Block as BlockT, Header as HeaderT};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use substrate_service::{FullClient, FullComponents, TaskExecutor};
+
+type Header = <Block as BlockT>::Header;
+
+/// The time taken to import blocks, recorded by the block imports made with `wrap`.
+///
+/// Created with the node's configuration, as the import queue is built before metrics are
+/// served.
+#[derive(Clone)]
+pub struct BlockImportTime(Histogram);
+
+impl Default for BlockImportTime {
+    fn default() -> Self {
+        let histogram = Histogram::with_opts(
+            HistogramOpts::new("block_import_seconds", "Time taken to import a block")
+                .buckets(vec![0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0]),
+        );
+        BlockImportTime(histogram.expect("Histogram options are valid; qed"))
+    }
+}
+
+impl BlockImportTime {
+    /// Time the blocks imported through `inner`.
+    pub fn wrap<I>(&self, inner: Arc<I>) -> TimedBlockImport<I> {
+        TimedBlockImport {
+            inner,
+            histogram: self.0.clone(),
+        }
+    }
+}
+
+/// Imports blocks through `inner`, recording how long each import took.
+pub struct TimedBlockImport<I> {
+    inner: Arc<I>,
+    histogram: Histogram,
+}
+
+impl<I: BlockImport<Block, Error = ConsensusError>> BlockImport<Block> for TimedBlockImport<I> {
+    type Error = ConsensusError;
+
+    fn check_block(&self, hash: Hash, parent_hash: Hash) -> Result<ImportResult, Self::Error> {
+        self.inner.check_block(hash, parent_hash)
+    }
+
+    fn import_block(
+        &self,
+        block: ImportBlock<Block>,
+        new_cache: HashMap<CacheKeyId, Vec<u8>>,
+    ) -> Result<ImportResult, Self::Error> {
+        let start = Instant::now();
+        let result = self.inner.import_block(block, new_cache);
+        let elapsed = start.elapsed();
+        self.histogram
+            .observe(elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9);
+        result
+    }
+}
+
+/// The metrics of a node.
+#[derive(Clone)]
+struct Metrics {
+    registry: Registry,
+    best_block: IntGauge,
+    finalized_block: IntGauge,
+    peers: IntGauge,
+    ready_transactions: IntGauge,
+    slot_delay: Histogram,
+    slots_missed: IntCounter,
+    extrinsics: IntCounterVec,
+}
+
+impl Metrics {
+    fn new(block_import_time: &BlockImportTime) -> prometheus::Result<Self> {
+        let registry = Registry::new_custom(Some("cennznet".into()), None)?;
+        let metrics = Metrics {
+            best_block: IntGauge::new("best_block", "Number of the best block")?,
+            finalized_block: IntGauge::new("finalized_block", "Number of the last finalized block")?,
+            peers: IntGauge::new("peers", "Number of connected peers")?,
+            ready_transactions: IntGauge::new(
+                "ready_transactions",
+                "Number of ready transactions in the pool",
+            )?,
+            slot_delay: Histogram::with_opts(
+                HistogramOpts::new(
+                    "block_slot_delay_seconds",
+                    "Time from the start of a block's slot until its import completed",
+                )
+                .buckets(vec![0.5, 1.0, 2.0, 3.0, 6.0, 12.0, 30.0, 60.0]),
+            )?,
+            slots_missed: IntCounter::new(
+                "aura_slots_missed",
+                "Number of Aura slots skipped by the best chain",
+            )?,
+            extrinsics: IntCounterVec::new(
+                Opts::new("extrinsics", "Number of extrinsics in imported blocks per module"),
+                &["module"],
+            )?,
+            registry,
+        };
+        metrics.registry.register(Box::new(metrics.best_block.clone()))?;
+        metrics.registry.register(Box::new(metrics.finalized_block.clone()))?;
+        metrics.registry.register(Box::new(metrics.peers.clone()))?;
+        metrics.registry.register(Box::new(metrics.ready_transactions.clone()))?;
+        metrics.registry.register(Box::new(metrics.slot_delay.clone()))?;
+        metrics.registry.register(Box::new(metrics.slots_missed.clone()))?;
+        metrics.registry.register(Box::new(metrics.extrinsics.clone()))?;
+        metrics.registry.register(Box::new(block_import_time.0.clone()))?;
+        Ok(metrics)
+    }
+
+    /// Record the block `header`, newly imported into `client`.
+    fn on_block_imported(
+        &self,
+        client: &FullClient<Factory>,
+        slot_duration: u64,
+        header: &Header,
+        is_new_best: bool,
+    ) {
+        if let Some(slot) = slot_of(header) {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs() as f64 + f64::from(d.subsec_millis()) / 1000.0)
+                .unwrap_or_default();
+            let slot_start = (slot * slot_duration) as f64;
+            self.slot_delay.observe((now - slot_start).max(0.0));
+
+            let parent = client.header(&BlockId::hash(*header.parent_hash()));
+            if let (true, Ok(Some(parent))) = (is_new_best, parent) {
+                if let Some(parent_slot) = slot_of(&parent) {
+                    let missed = slot.saturating_sub(parent_slot + 1);
+                    self.slots_missed.inc_by(missed as i64);
+                }
+            }
+        }
+
+        let body = client.body(&BlockId::hash(header.hash()));
+        if let Ok(Some(extrinsics)) = body {
+            for extrinsic in extrinsics {
+                let xt: Option<UncheckedExtrinsic> = Decode::decode(&mut &extrinsic.encode()[..]);
+                if let Some(xt) = xt {
+                    self.extrinsics
+                        .with_label_values(&[xt.function.module_name()])
+                        .inc();
+                }
+            }
+        }
+    }
+}
+
+/// The Aura slot `header` was sealed in, if sealed by Aura.
+fn slot_of(header: &Header) -> Option<u64> {
+    match header.digest().logs.last() {
+        Some(DigestItem::Seal(slot, _)) => Some(*slot),
+        _ => None,
+    }
+}
+
+/// Collect metrics of `service` and serve them for Prometheus on `addr`.
+pub fn start(
+    service: &FullComponents<Factory>,
+    executor: &TaskExecutor,
+    addr: &SocketAddr,
+) -> Result<(), String> {
+    let metrics = Metrics::new(&service.config.custom.block_import_time).map_err(|e| format!("{:?}", e))?;
+    let client = service.client();
+    let slot_duration = SlotDuration::get_or_compute(&*client)
+        .map_err(|e| format!("{:?}", e))?
+        .get();
+
+    let on_import = {
+        let metrics = metrics.clone();
+        let client = client.clone();
+        client
+            .import_notification_stream()
+            .for_each(move |notification| {
+                metrics.on_block_imported(
+                    &client,
+                    slot_duration,
+                    &notification.header,
+                    notification.is_new_best,
+                );
+                Ok(())
+            })
+    };
+
+    let on_status = {
+        let metrics = metrics.clone();
+        let transaction_pool = service.transaction_pool();
+        service.network().status().for_each(move |status| {
+            if let Ok(info) = client.info() {
+                metrics.best_block.set(info.chain.best_number as i64);
+                metrics.finalized_block.set(info.chain.finalized_number as i64);
+            }
+            metrics.peers.set(status.num_peers as i64);
+            metrics
+                .ready_transactions
+                .set(transaction_pool.status().ready as i64);
+            Ok(())
+        })
+    };
+
+    let registry = metrics.registry.clone();
+    let server = Server::try_bind(addr)
+        .map_err(|e| format!("{:?}", e))?
+        .serve(move || {
+            let registry = registry.clone();
+            service_fn_ok(move |_request| {
+                let mut buffer = Vec::new();
+                if let Err(e) = TextEncoder::new().encode(&registry.gather(), &mut buffer) {
+                    warn!("Failed to encode metrics: {:?}", e);
+                }
+                Response::new(Body::from(buffer))
+            })
+        })
+        .map_err(|e| warn!("Prometheus server failed: {:?}", e));
+    info!("Prometheus metrics listening on {}", addr);
+
+    let tasks = future::join_all(vec![
+        Box::new(on_import) as Box<dyn Future<Item = (), Error = ()> + Send>,
+        Box::new(on_status),
+        Box::new(server),
+    ]);
+    executor.spawn(tasks.map(|_| ()).select(service.on_exit()).then(|_| Ok(())));
+    Ok(())
+}
@@ -0,0 +1,159 @@
+//! An example off-chain worker feeding asset prices to the runtime's `Oracle` module.
+//!
+//! Once per oracle round the prices are fetched over HTTP from a configurable endpoint, which
+//! returns a JSON object of integer prices by asset ID (e.g. `{"16000": 1000000}`). Each price
+//! is signed by a feeder account and submitted as an unsigned transaction, paying no fee, which
+//! the runtime validates against the oracle's feeders.
+
+use crate::service::Factory;
+use cennznet_runtime_template_runtime::{
+    opaque::Block,
+    oracle::{self, PriceSubmission, Round},
+    AssetId, Balance, BlockNumber, Call, UncheckedExtrinsic,
+};
+use futures::{future, Future, Stream};
+use hyper::{client::HttpConnector, Client, Uri};
+use log::{info, warn};
+use parity_codec::{Decode, Encode};
+use primitives::{sr25519, storage::StorageKey, twox_128, Pair};
+use runtime_primitives::generic::BlockId;
+use runtime_primitives::traits::Header as HeaderT;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use substrate_service::{ComponentClient, FullComponents, TaskExecutor};
+
+/// Where prices are fetched from and the feeder signing them.
+#[derive(Clone)]
+pub struct PriceFeedConfig {
+    /// The HTTP endpoint serving prices
+    pub url: Uri,
+    /// The oracle feeder account signing submissions
+    pub feeder: Arc<sr25519::Pair>,
+}
+
+/// Fetch prices from `config.url` and submit them once per oracle round of the best block.
+pub fn start(service: &FullComponents<Factory>, executor: &TaskExecutor, config: PriceFeedConfig) {
+    info!("Feeding prices from {} as {}", config.url, config.feeder.public());
+    let client: Arc<ComponentClient<FullComponents<Factory>>> = service.client();
+    let transaction_pool = service.transaction_pool();
+    let http = Client::new();
+    let spawn = executor.clone();
+    let mut last_round = None;
+
+    let on_import = client
+        .import_notification_stream()
+        .filter(|notification| notification.is_new_best)
+        .for_each(move |notification| {
+            let number = *notification.header.number();
+            let at = BlockId::hash(notification.header.hash());
+            let round = match round_duration(&client, &at) {
+                Ok(0) => return Ok(()),
+                Ok(duration) => number / duration,
+                Err(e) => {
+                    warn!("Failed to read the oracle round duration: {}", e);
+                    return Ok(());
+                }
+            };
+            if last_round >= Some(round) {
+                return Ok(());
+            }
+            last_round = Some(round);
+
+            let feeder = config.feeder.clone();
+            let transaction_pool = transaction_pool.clone();
+            let submit = fetch_prices(&http, config.url.clone()).then(move |prices| {
+                let prices = prices.map_err(|e| warn!("Failed to fetch prices for round {}: {}", round, e))?;
+                for (asset_id, price) in prices {
+                    let xt = submission(&feeder, asset_id, round, price);
+                    let xt = Decode::decode(&mut &xt.encode()[..]).ok_or(())?;
+                    match transaction_pool.submit_one(&at, xt) {
+                        Ok(hash) => info!("Submitted price of asset {} for round {}: {:?}", asset_id, round, hash),
+                        Err(e) => {
+                            warn!("Failed to submit price of asset {} for round {}: {:?}", asset_id, round, e)
+                        }
+                    }
+                }
+                Ok(())
+            });
+            spawn.spawn(submit);
+            Ok(())
+        })
+        .select(service.on_exit())
+        .then(|_| Ok(()));
+
+    executor.spawn(on_import);
+}
+
+/// The oracle's round duration in blocks at block `at`, read from `Oracle` storage.
+fn round_duration(
+    client: &ComponentClient<FullComponents<Factory>>,
+    at: &BlockId<Block>,
+) -> Result<BlockNumber, String> {
+    let duration = client
+        .storage(at, &StorageKey(twox_128(b"Oracle RoundDuration").to_vec()))
+        .map_err(|e| format!("{:?}", e))?;
+    Ok(duration
+        .and_then(|data| Decode::decode(&mut &data.0[..]))
+        .unwrap_or_default())
+}
+
+/// Fetch the prices served at `url` by asset ID.
+fn fetch_prices(
+    http: &Client<HttpConnector>,
+    url: Uri,
+) -> impl Future<Item = BTreeMap<AssetId, Balance>, Error = String> {
+    http.get(url)
+        .map_err(|e| format!("{:?}", e))
+        .and_then(|response| {
+            let status = response.status();
+            let body = response.into_body().concat2().map_err(|e| format!("{:?}", e));
+            if status.is_success() {
+                future::Either::A(body)
+            } else {
+                future::Either::B(future::err(format!("HTTP status {}", status)))
+            }
+        })
+        .and_then(|body| serde_json::from_slice(&body).map_err(|e| format!("{:?}", e)))
+}
+
+/// The unsigned transaction submitting `price` of `asset_id` for `round`, signed by `feeder`.
+fn submission(feeder: &sr25519::Pair, asset_id: AssetId, round: Round, price: Balance) -> UncheckedExtrinsic {
+    let submission = PriceSubmission { feeder: feeder.public(), asset_id, round, price };
+    let signature = feeder.sign(&submission.encode());
+    let call = Call::Oracle(oracle::Call::submit_price_unsigned(submission, signature.into()));
+    UncheckedExtrinsic::new_unsigned(call)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::{service::service_fn_ok, Body, Response, Server};
+    use runtime_primitives::traits::Verify;
+
+    #[test]
+    fn fetches_prices_from_a_local_stand_in() {
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let server = Server::bind(&([127, 0, 0, 1], 0).into())
+            .serve(|| service_fn_ok(|_request| Response::new(Body::from(r#"{"16000": 1000000, "16001": 25}"#))));
+        let url: Uri = format!("http://{}/prices", server.local_addr()).parse().unwrap();
+        runtime.spawn(server.map_err(|e| panic!("Stand-in server failed: {:?}", e)));
+
+        let prices = runtime.block_on(fetch_prices(&Client::new(), url)).unwrap();
+        assert_eq!(prices.into_iter().collect::<Vec<_>>(), vec![(16000, 1_000_000), (16001, 25)]);
+    }
+
+    #[test]
+    fn submissions_are_unsigned_and_signed_by_the_feeder() {
+        let feeder = sr25519::Pair::from_string("//Alice", None).unwrap();
+        let xt = submission(&feeder, 16000, 7, 1_000_000);
+        assert!(xt.signature.is_none());
+        match xt.function {
+            Call::Oracle(oracle::Call::submit_price_unsigned(submission, signature)) => {
+                assert_eq!(submission.feeder, feeder.public());
+                assert_eq!((submission.asset_id, submission.round, submission.price), (16000, 7, 1_000_000));
+                assert!(signature.verify(&submission.encode()[..], &feeder.public()));
+            }
+            _ => panic!("expected an unsigned price submission"),
+        }
+    }
+}
@@ -0,0 +1,535 @@
+//! Node-specific RPC methods, served alongside the standard Substrate RPC.
+
+use crate::events::{self, EventFilter, FilteredEvent};
+use crate::indexer::{FeeCharged, Indexer, StakingEvent, Trade, Transfer};
+use crate::sealing::Sealer;
+use crate::service::Factory;
+use cennznet_runtime_template_runtime::{
+    account_state::{AccountInfo, AccountStateApi, AssetBalance},
+    contract_dry_run::{ContractExecResult, ContractsApi},
+    indices,
+    opaque::Block,
+    AccountId, AccountIndex, Address, AssetId, Balance, Event, Hash, Index,
+};
+use futures::{stream, sync::oneshot, Future, Sink, Stream};
+use jsonrpc_core::{Error, ErrorCode, IoHandler, Result};
+use jsonrpc_derive::rpc;
+use jsonrpc_http_server::{Server, ServerBuilder};
+use jsonrpc_pubsub::{typed::Subscriber, PubSubHandler, Session, SubscriptionId};
+use log::warn;
+use parity_codec::Encode;
+use parking_lot::Mutex;
+use primitives::Bytes;
+use runtime_primitives::{
+    generic::BlockId,
+    traits::{Block as BlockT, Header as HeaderT, ProvideRuntimeApi},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{net::SocketAddr, sync::Arc};
+use substrate_client::blockchain::HeaderBackend;
+use substrate_service::{FullClient, TaskExecutor};
+
+type Header = <Block as BlockT>::Header;
+
+/// Start an HTTP server for the node-specific RPC methods in `io` on `addr`.
+pub fn start_http(addr: &SocketAddr, io: IoHandler) -> std::io::Result<Server> {
+    ServerBuilder::new(io).threads(1).start_http(addr)
+}
+
+/// Start a WebSocket server for the node-specific subscriptions in `io` on `addr`.
+pub fn start_ws(
+    addr: &SocketAddr,
+    io: PubSubHandler<Arc<Session>>,
+) -> std::result::Result<jsonrpc_ws_server::Server, jsonrpc_ws_server::Error> {
+    jsonrpc_ws_server::ServerBuilder::with_meta_extractor(
+        io,
+        |context: &jsonrpc_ws_server::RequestContext| Arc::new(Session::new(context.sender())),
+    )
+    .start(addr)
+}
+
+/// Build the handler for all node-specific RPC methods backed by `client`.
+pub fn extensions<C>(client: Arc<C>) -> IoHandler
+where
+    C: ProvideRuntimeApi + HeaderBackend<Block> + Send + Sync + 'static,
+    C::Api: ContractsApi<Block, AccountId, Balance, Hash, Event>
+        + AccountStateApi<Block, Address, AccountId, AccountIndex, AssetId, Balance, Index>,
+{
+    let mut io = IoHandler::new();
+    io.extend_with(ContractsRpc::to_delegate(Contracts::new(client.clone())));
+    io.extend_with(AccountsRpc::to_delegate(Accounts::new(client)));
+    io
+}
+
+/// A contract call to dry-run.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallRequest {
+    origin: AccountId,
+    dest: AccountId,
+    value: Balance,
+    gas_limit: u64,
+    input_data: Bytes,
+}
+
+/// A contract instantiation to dry-run.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstantiateRequest {
+    origin: AccountId,
+    endowment: Balance,
+    gas_limit: u64,
+    code_hash: Hash,
+    data: Bytes,
+}
+
+/// The outcome of a dry-run, with events SCALE encoded.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExecResult {
+    error: Option<String>,
+    output: Bytes,
+    debug_buffer: Bytes,
+    address: Option<AccountId>,
+    gas_used: u64,
+    events: Vec<Bytes>,
+}
+
+impl From<ContractExecResult<AccountId, Event>> for ExecResult {
+    fn from(r: ContractExecResult<AccountId, Event>) -> Self {
+        ExecResult {
+            error: r.error.map(|e| String::from_utf8_lossy(&e).into_owned()),
+            output: r.output.into(),
+            debug_buffer: r.debug_buffer.into(),
+            address: r.address,
+            gas_used: r.gas_used,
+            events: r.events.iter().map(|e| e.encode().into()).collect(),
+        }
+    }
+}
+
+/// Contract RPC methods.
+#[rpc]
+pub trait ContractsRpc {
+    /// Execute a contract call against the state of block `at` (or the best block) without
+    /// committing it.
+    #[rpc(name = "contracts_call")]
+    fn call(&self, request: CallRequest, at: Option<Hash>) -> Result<ExecResult>;
+
+    /// Instantiate a contract against the state of block `at` (or the best block) without
+    /// committing it.
+    #[rpc(name = "contracts_instantiate")]
+    fn instantiate(&self, request: InstantiateRequest, at: Option<Hash>) -> Result<ExecResult>;
+}
+
+/// An account given by its id or its index.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum AccountAddress {
+    /// The account id
+    Id(AccountId),
+    /// The account index
+    Index(AccountIndex),
+}
+
+impl AccountAddress {
+    fn into_address(self) -> Address {
+        match self {
+            AccountAddress::Id(id) => indices::address::Address::Id(id),
+            AccountAddress::Index(index) => indices::address::Address::Index(index),
+        }
+    }
+}
+
+/// Account state RPC methods.
+#[rpc]
+pub trait AccountsRpc {
+    /// The free and reserved balances of `who` in each asset it holds at block `at` (or the best
+    /// block). `None` if `who` is an unknown index.
+    #[rpc(name = "account_balances")]
+    fn balances(
+        &self,
+        who: AccountAddress,
+        at: Option<Hash>,
+    ) -> Result<Option<Vec<AssetBalance<AssetId, Balance>>>>;
+
+    /// The balances, nonce and index of `who` at block `at` (or the best block). `None` if
+    /// `who` is an unknown index.
+    #[rpc(name = "account_info")]
+    fn info(
+        &self,
+        who: AccountAddress,
+        at: Option<Hash>,
+    ) -> Result<Option<AccountInfo<AccountId, AccountIndex, AssetId, Balance, Index>>>;
+}
+
+/// Implements account state RPC methods via the runtime's `AccountStateApi`.
+pub struct Accounts<C> {
+    client: Arc<C>,
+}
+
+impl<C> Accounts<C> {
+    /// Create new `Accounts` RPC methods backed by `client`.
+    pub fn new(client: Arc<C>) -> Self {
+        Accounts { client }
+    }
+}
+
+impl<C> AccountsRpc for Accounts<C>
+where
+    C: ProvideRuntimeApi + HeaderBackend<Block> + Send + Sync + 'static,
+    C::Api: AccountStateApi<Block, Address, AccountId, AccountIndex, AssetId, Balance, Index>,
+{
+    fn balances(
+        &self,
+        who: AccountAddress,
+        at: Option<Hash>,
+    ) -> Result<Option<Vec<AssetBalance<AssetId, Balance>>>> {
+        let at = block_id(&*self.client, at)?;
+        self.client
+            .runtime_api()
+            .account_balances(&at, who.into_address())
+            .map_err(runtime_error)
+    }
+
+    fn info(
+        &self,
+        who: AccountAddress,
+        at: Option<Hash>,
+    ) -> Result<Option<AccountInfo<AccountId, AccountIndex, AssetId, Balance, Index>>> {
+        let at = block_id(&*self.client, at)?;
+        self.client
+            .runtime_api()
+            .account_info(&at, who.into_address())
+            .map_err(runtime_error)
+    }
+}
+
+/// Manual sealing RPC methods.
+#[rpc]
+pub trait EngineRpc {
+    /// Seal a block on the best block, finalizing it if `finalize`. Unless `create_empty`, no
+    /// block is sealed while the pool has no ready transactions. Returns the sealed block hash.
+    #[rpc(name = "engine_createBlock")]
+    fn create_block(&self, create_empty: bool, finalize: bool) -> Result<Option<Hash>>;
+}
+
+/// Implements manual sealing RPC methods via a `Sealer`.
+pub struct Engine {
+    sealer: Arc<Sealer>,
+}
+
+impl Engine {
+    /// Create new `Engine` RPC methods sealing with `sealer`.
+    pub fn new(sealer: Arc<Sealer>) -> Self {
+        Engine { sealer }
+    }
+}
+
+impl EngineRpc for Engine {
+    fn create_block(&self, create_empty: bool, finalize: bool) -> Result<Option<Hash>> {
+        self.sealer.seal(create_empty, finalize).map_err(|e| Error {
+            code: ErrorCode::ServerError(2),
+            message: "Sealing failed".into(),
+            data: Some(e.into()),
+        })
+    }
+}
+
+/// Runtime event subscription RPC methods.
+#[rpc]
+pub trait EventsRpc {
+    /// RPC metadata
+    type Metadata;
+
+    /// Subscribe to the runtime events matching `filter`. Notifications carry the matching
+    /// events of one block, and blocks without matching events are skipped.
+    #[pubsub(subscription = "events_filtered", subscribe, name = "events_subscribe")]
+    fn subscribe(
+        &self,
+        metadata: Self::Metadata,
+        subscriber: Subscriber<Vec<FilteredEvent>>,
+        filter: EventFilter,
+    );
+
+    /// Cancel the event subscription `id`.
+    #[pubsub(subscription = "events_filtered", unsubscribe, name = "events_unsubscribe")]
+    fn unsubscribe(&self, metadata: Option<Self::Metadata>, id: SubscriptionId) -> Result<bool>;
+}
+
+/// The running event subscriptions: the session owning each and the sender cancelling it.
+type ActiveSubscriptions = Arc<Mutex<HashMap<SubscriptionId, (usize, oneshot::Sender<()>)>>>;
+
+/// Implements runtime event subscriptions, notifying on best or finalized blocks of `client`.
+/// Subscriptions are removed when they end, are cancelled by their own session, or their
+/// session closes.
+pub struct Events {
+    client: Arc<FullClient<Factory>>,
+    executor: TaskExecutor,
+    next_id: AtomicUsize,
+    active: ActiveSubscriptions,
+}
+
+impl Events {
+    /// Create new `Events` RPC methods, running subscriptions on `executor`.
+    pub fn new(client: Arc<FullClient<Factory>>, executor: TaskExecutor) -> Self {
+        Events {
+            client,
+            executor,
+            next_id: AtomicUsize::new(0),
+            active: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+/// Identifies the session `session` while it is open.
+fn session_key(session: &Arc<Session>) -> usize {
+    &**session as *const Session as usize
+}
+
+impl EventsRpc for Events {
+    type Metadata = Arc<Session>;
+
+    fn subscribe(
+        &self,
+        metadata: Self::Metadata,
+        subscriber: Subscriber<Vec<FilteredEvent>>,
+        filter: EventFilter,
+    ) {
+        let id = SubscriptionId::Number(self.next_id.fetch_add(1, Ordering::SeqCst) as u64);
+        let sink = match subscriber.assign_id(id.clone()) {
+            Ok(sink) => sink,
+            Err(()) => return,
+        };
+
+        let client = self.client.clone();
+        let headers: Box<dyn Stream<Item = Header, Error = ()> + Send> = if filter.finalized {
+            // A notification may finalize several blocks at once, so every block since the
+            // previously finalized one is notified.
+            let mut last = client.info().map(|info| info.chain.finalized_number).unwrap_or_default();
+            Box::new(
+                client
+                    .finality_notification_stream()
+                    .map(move |notification| {
+                        let number = *notification.header.number();
+                        let headers: Vec<Header> = (last + 1..=number)
+                            .filter_map(|n| client.header(&BlockId::number(n)).ok().and_then(|h| h))
+                            .collect();
+                        last = last.max(number);
+                        stream::iter_ok(headers)
+                    })
+                    .flatten(),
+            )
+        } else {
+            Box::new(
+                client
+                    .import_notification_stream()
+                    .filter(|notification| notification.is_new_best)
+                    .map(|notification| notification.header),
+            )
+        };
+
+        let client = self.client.clone();
+        let notifications = headers.filter_map(move |header| {
+            match events::block_events(&client, &header, &filter) {
+                Ok(events) => Some(events).filter(|events| !events.is_empty()).map(Ok),
+                Err(e) => {
+                    warn!("Failed to read events of block {}: {}", header.hash(), e);
+                    None
+                }
+            }
+        });
+
+        let (cancel, cancelled) = oneshot::channel();
+        self.active.lock().insert(id.clone(), (session_key(&metadata), cancel));
+        {
+            let active = self.active.clone();
+            let id = id.clone();
+            metadata.on_drop(move || {
+                if let Some((_, cancel)) = active.lock().remove(&id) {
+                    let _ = cancel.send(());
+                }
+            });
+        }
+
+        let active = self.active.clone();
+        let task = sink
+            .sink_map_err(|e| warn!("Failed to send event notification: {:?}", e))
+            .send_all(notifications)
+            .map(|_| ())
+            .select(cancelled.map_err(|_| ()))
+            .then(move |_| {
+                active.lock().remove(&id);
+                Ok(())
+            });
+        self.executor.spawn(task);
+    }
+
+    fn unsubscribe(&self, metadata: Option<Self::Metadata>, id: SubscriptionId) -> Result<bool> {
+        let session = match metadata {
+            Some(session) => session_key(&session),
+            None => return Ok(false),
+        };
+        let mut active = self.active.lock();
+        if active.get(&id).map_or(true, |(owner, _)| *owner != session) {
+            return Ok(false);
+        }
+        Ok(match active.remove(&id) {
+            Some((_, cancel)) => cancel.send(()).is_ok(),
+            None => false,
+        })
+    }
+}
+
+/// The number of rows returned by indexer queries unless a limit is given.
+const DEFAULT_INDEXER_LIMIT: u32 = 100;
+
+/// Indexed chain history RPC methods.
+#[rpc]
+pub trait IndexerRpc {
+    /// Generic asset transfers from or to `who`, most recent first.
+    #[rpc(name = "indexer_transfers")]
+    fn transfers(&self, who: AccountId, limit: Option<u32>) -> Result<Vec<Transfer>>;
+
+    /// Transaction fees paid by `who`, most recent first.
+    #[rpc(name = "indexer_fees")]
+    fn fees(&self, who: AccountId, limit: Option<u32>) -> Result<Vec<FeeCharged>>;
+
+    /// CENNZX-Spot trades by `who`, most recent first.
+    #[rpc(name = "indexer_trades")]
+    fn trades(&self, who: AccountId, limit: Option<u32>) -> Result<Vec<Trade>>;
+
+    /// Staking rewards, offline warnings and slashes of `who` (or of everyone), most recent first.
+    #[rpc(name = "indexer_stakingEvents")]
+    fn staking_events(&self, who: Option<AccountId>, limit: Option<u32>) -> Result<Vec<StakingEvent>>;
+}
+
+/// Implements indexed chain history RPC methods via an `Indexer`.
+pub struct IndexerQueries {
+    indexer: Arc<Indexer>,
+}
+
+impl IndexerQueries {
+    /// Create new `IndexerQueries` RPC methods reading from `indexer`.
+    pub fn new(indexer: Arc<Indexer>) -> Self {
+        IndexerQueries { indexer }
+    }
+}
+
+impl IndexerRpc for IndexerQueries {
+    fn transfers(&self, who: AccountId, limit: Option<u32>) -> Result<Vec<Transfer>> {
+        self.indexer
+            .transfers(&who, limit.unwrap_or(DEFAULT_INDEXER_LIMIT))
+            .map_err(indexer_error)
+    }
+
+    fn fees(&self, who: AccountId, limit: Option<u32>) -> Result<Vec<FeeCharged>> {
+        self.indexer
+            .fees(&who, limit.unwrap_or(DEFAULT_INDEXER_LIMIT))
+            .map_err(indexer_error)
+    }
+
+    fn trades(&self, who: AccountId, limit: Option<u32>) -> Result<Vec<Trade>> {
+        self.indexer
+            .trades(&who, limit.unwrap_or(DEFAULT_INDEXER_LIMIT))
+            .map_err(indexer_error)
+    }
+
+    fn staking_events(&self, who: Option<AccountId>, limit: Option<u32>) -> Result<Vec<StakingEvent>> {
+        self.indexer
+            .staking_events(who.as_ref(), limit.unwrap_or(DEFAULT_INDEXER_LIMIT))
+            .map_err(indexer_error)
+    }
+}
+
+/// Convert an indexer database error into an RPC error.
+fn indexer_error(e: rusqlite::Error) -> Error {
+    Error {
+        code: ErrorCode::ServerError(3),
+        message: "Indexer query failed".into(),
+        data: Some(format!("{:?}", e).into()),
+    }
+}
+
+/// Implements contract RPC methods via the runtime's `ContractsApi`.
+pub struct Contracts<C> {
+    client: Arc<C>,
+}
+
+impl<C> Contracts<C> {
+    /// Create new `Contracts` RPC methods backed by `client`.
+    pub fn new(client: Arc<C>) -> Self {
+        Contracts { client }
+    }
+}
+
+impl<C> ContractsRpc for Contracts<C>
+where
+    C: ProvideRuntimeApi + HeaderBackend<Block> + Send + Sync + 'static,
+    C::Api: ContractsApi<Block, AccountId, Balance, Hash, Event>,
+{
+    fn call(&self, request: CallRequest, at: Option<Hash>) -> Result<ExecResult> {
+        let at = block_id(&*self.client, at)?;
+        let CallRequest { origin, dest, value, gas_limit, input_data } = request;
+        self.client
+            .runtime_api()
+            .call(&at, origin, dest, value, gas_limit, input_data.to_vec())
+            .map(Into::into)
+            .map_err(runtime_error)
+    }
+
+    fn instantiate(&self, request: InstantiateRequest, at: Option<Hash>) -> Result<ExecResult> {
+        let at = block_id(&*self.client, at)?;
+        let InstantiateRequest { origin, endowment, gas_limit, code_hash, data } = request;
+        self.client
+            .runtime_api()
+            .instantiate(&at, origin, endowment, gas_limit, code_hash, data.to_vec())
+            .map(Into::into)
+            .map_err(runtime_error)
+    }
+}
+
+/// The block to query: `at` if given, otherwise the best block.
+pub(crate) fn block_id<C: HeaderBackend<Block>>(client: &C, at: Option<Hash>) -> Result<BlockId<Block>> {
+    match at {
+        Some(hash) => Ok(BlockId::hash(hash)),
+        None => client
+            .info()
+            .map(|info| BlockId::hash(info.best_hash))
+            .map_err(runtime_error),
+    }
+}
+
+/// Convert a client or runtime API error into an RPC error.
+pub(crate) fn runtime_error<E: std::fmt::Debug>(e: E) -> Error {
+    Error {
+        code: ErrorCode::ServerError(1),
+        message: "Runtime call failed".into(),
+        data: Some(format!("{:?}", e).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exec_result_exposes_output_and_debug_buffer() {
+        let result: ExecResult = ContractExecResult {
+            error: Some(b"contract trapped".to_vec()),
+            output: vec![1, 2],
+            debug_buffer: vec![0xab],
+            address: None,
+            gas_used: 7,
+            events: Vec::new(),
+        }
+        .into();
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["error"], "contract trapped");
+        assert_eq!(json["output"], "0x0102");
+        assert_eq!(json["debugBuffer"], "0xab");
+        assert_eq!(json["gasUsed"], 7);
+    }
+}
@@ -0,0 +1,195 @@
+//! Instant and manual block sealing for development, bypassing Aura slot timing.
+
+use crate::service::Factory;
+use basic_authorship::ProposerFactory;
+use cennznet_runtime_template_runtime::{opaque::Block, timestamp, Hash};
+use consensus_common::{
+    BlockImport, BlockOrigin, Environment, ForkChoiceStrategy, ImportBlock, Proposer,
+};
+use futures::{Future, Stream};
+use inherents::InherentData;
+use log::{info, warn};
+use parity_codec::Decode;
+use parking_lot::Mutex;
+use primitives::{storage::StorageKey, twox_128};
+use runtime_primitives::generic::BlockId;
+use runtime_primitives::traits::{Block as BlockT, Header as HeaderT};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use substrate_service::{FullClient, FullComponents, ServiceFactory, TaskExecutor};
+
+/// Maximum time spent proposing a block.
+const MAX_PROPOSAL_DURATION: Duration = Duration::from_secs(2);
+
+/// How blocks are sealed instead of by Aura.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sealing {
+    /// Seal a block as soon as transactions enter the pool.
+    Instant,
+    /// Seal a block on each `engine_createBlock` RPC call.
+    Manual,
+}
+
+impl FromStr for Sealing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "instant" => Ok(Sealing::Instant),
+            "manual" => Ok(Sealing::Manual),
+            _ => Err(format!("Unknown sealing mode: {}", s)),
+        }
+    }
+}
+
+type TransactionPoolApi = <Factory as ServiceFactory>::FullTransactionPoolApi;
+
+/// Authors and imports blocks on top of the best block on demand.
+pub struct Sealer {
+    client: Arc<FullClient<Factory>>,
+    proposer_factory: ProposerFactory<FullClient<Factory>, TransactionPoolApi>,
+    lock: Mutex<()>,
+}
+
+impl Sealer {
+    /// Create a sealer authoring blocks with the transactions in `service`'s pool.
+    pub fn new(service: &FullComponents<Factory>) -> Self {
+        Sealer {
+            client: service.client(),
+            proposer_factory: ProposerFactory {
+                client: service.client(),
+                transaction_pool: service.transaction_pool(),
+                inherents_pool: service.inherents_pool(),
+            },
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Author and import a block, finalizing it if `finalize`. Unless `create_empty`, no block
+    /// is sealed while the pool has no ready transactions. Returns the hash of the sealed block.
+    pub fn seal(&self, create_empty: bool, finalize: bool) -> Result<Option<Hash>, String> {
+        let _lock = self.lock.lock();
+        if !create_empty && self.proposer_factory.transaction_pool.status().ready == 0 {
+            return Ok(None);
+        }
+
+        let info = self.client.info().map_err(|e| format!("{:?}", e))?;
+        let parent_id = BlockId::hash(info.chain.best_hash);
+        let parent = self
+            .client
+            .header(&parent_id)
+            .map_err(|e| format!("{:?}", e))?
+            .ok_or("Best block header not found")?;
+
+        let mut inherent_data = InherentData::new();
+        inherent_data
+            .put_data(timestamp::INHERENT_IDENTIFIER, &self.next_timestamp(&parent_id))
+            .map_err(|e| format!("{:?}", e))?;
+
+        let proposer = self
+            .proposer_factory
+            .init(&parent, &[])
+            .map_err(|e| format!("{:?}", e))?;
+        let block: Block = proposer
+            .propose(inherent_data, MAX_PROPOSAL_DURATION)
+            .map_err(|e| format!("{:?}", e))?;
+
+        let (header, body) = block.deconstruct();
+        let hash = header.hash();
+        let import = ImportBlock {
+            origin: BlockOrigin::Own,
+            header,
+            justification: None,
+            post_digests: Vec::new(),
+            body: Some(body),
+            finalized: finalize,
+            auxiliary: Vec::new(),
+            fork_choice: ForkChoiceStrategy::LongestChain,
+        };
+        self.client
+            .import_block(import, HashMap::new())
+            .map_err(|e| format!("{:?}", e))?;
+
+        info!("Sealed block {}{}", hash, if finalize { " (finalized)" } else { "" });
+        Ok(Some(hash))
+    }
+
+    /// The timestamp of a block built on `parent_id`, in the slot following the parent's. See
+    /// `next_timestamp`.
+    fn next_timestamp(&self, parent_id: &BlockId<Block>) -> u64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let storage_value = |key: &[u8]| -> Option<u64> {
+            let data = self
+                .client
+                .storage(parent_id, &StorageKey(twox_128(key).to_vec()))
+                .ok()??;
+            Decode::decode(&mut &data.0[..])
+        };
+        match (
+            storage_value(b"Timestamp Now"),
+            storage_value(b"Timestamp MinimumPeriod"),
+        ) {
+            (Some(last), Some(period)) if last > 0 => next_timestamp(last, period),
+            _ => now,
+        }
+    }
+}
+
+/// The earliest timestamp in the Aura slot after the one of timestamp `last`, and at least the
+/// `minimum_period` after it. Aura slots last twice the minimum period; Aura allows one block per
+/// slot and slashes the authorities of skipped slots, so each sealed block takes the next slot,
+/// however far the wall clock has moved.
+fn next_timestamp(last: u64, minimum_period: u64) -> u64 {
+    let slot_duration = minimum_period * 2;
+    ((last / slot_duration + 1) * slot_duration).max(last + minimum_period)
+}
+
+/// Seal a block whenever transactions are imported into `service`'s pool.
+pub fn start_instant_sealing(
+    sealer: Arc<Sealer>,
+    service: &FullComponents<Factory>,
+    executor: &TaskExecutor,
+    finalize: bool,
+) {
+    let on_import = service
+        .transaction_pool()
+        .import_notification_stream()
+        .for_each(move |_| {
+            if let Err(e) = sealer.seal(false, finalize) {
+                warn!("Failed to seal block: {}", e);
+            }
+            Ok(())
+        })
+        .select(service.on_exit())
+        .then(|_| Ok(()));
+
+    executor.spawn(on_import);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn back_to_back_blocks_take_consecutive_slots() {
+        let period = 3;
+        let slot = |timestamp: u64| timestamp / (period * 2);
+
+        // The first block after genesis is sealed at the wall clock, early and late in a slot
+        for &first in &[1_560_000_001, 1_560_000_004] {
+            let mut last = first;
+            for _ in 0..5 {
+                let next = next_timestamp(last, period);
+                assert!(next >= last + period);
+                assert_eq!(slot(next), slot(last) + 1);
+                last = next;
+            }
+        }
+    }
+}
+
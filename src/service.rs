@@ -2,20 +2,29 @@
 
 #![warn(unused_extern_crates)]
 
+use crate::equivocation::EquivocationReporter;
+use crate::metrics::BlockImportTime;
+use crate::price_feed::{self, PriceFeedConfig};
+use crate::sealing::Sealing;
+use crate::signer::{AuthorityPair, KeystoreKeys, RemoteSigner, SealCheck};
 use basic_authorship::ProposerFactory;
 use cennznet_runtime_template_runtime::{self, opaque::Block, GenesisConfig, RuntimeApi};
 use consensus::{import_queue, start_aura, AuraImportQueue, NothingExtra, SlotDuration};
+use futures::{Future, Stream};
 use inherents::InherentDataProviders;
 use log::info;
 use network::construct_simple_protocol;
-use primitives::{ed25519::Pair, Pair as PairT};
+use primitives::{ed25519::Pair, sr25519, Pair as PairT};
+use runtime_primitives::traits::Header as HeaderT;
 use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 use substrate_client as client;
 use substrate_executor::native_executor_instance;
 use substrate_service::construct_service_factory;
 use substrate_service::{
-    FactoryFullConfiguration, FullBackend, FullClient, FullComponents, FullExecutor, LightBackend,
-    LightClient, LightComponents, LightExecutor, TaskExecutor,
+    ComponentClient, FactoryFullConfiguration, FullBackend, FullClient, FullComponents,
+    FullExecutor, LightBackend, LightClient, LightComponents, LightExecutor, TaskExecutor,
 };
 use transaction_pool::{self, txpool::Pool as TransactionPool};
 
@@ -31,6 +40,18 @@ native_executor_instance!(
 #[derive(Default)]
 pub struct NodeConfig {
     inherent_data_providers: InherentDataProviders,
+    /// The account signing reports of equivocations seen in imported blocks, if any.
+    pub equivocation_reporter: Option<Arc<sr25519::Pair>>,
+    /// Seal blocks instantly or manually instead of authoring with Aura, if set.
+    pub sealing: Option<Sealing>,
+    /// The password decrypting encrypted keystore keys, if any.
+    pub keystore_password: Option<String>,
+    /// The remote signer holding the authority key instead of the keystore, if any.
+    pub remote_signer: Option<RemoteSigner>,
+    /// The endpoint and feeder of the example price feed off-chain worker, if any.
+    pub price_feed: Option<PriceFeedConfig>,
+    /// The time taken to import blocks, served as a metric.
+    pub block_import_time: BlockImportTime,
 }
 
 construct_simple_protocol! {
@@ -51,12 +72,19 @@ construct_service_factory! {
         Genesis = GenesisConfig,
         Configuration = NodeConfig,
         FullService = FullComponents<Self>
-            { |config: FactoryFullConfiguration<Self>, executor: TaskExecutor|
-                FullComponents::<Factory>::new(config, executor)
-            },
+            { |config: FactoryFullConfiguration<Self>, executor: TaskExecutor| {
+                let service = FullComponents::<Factory>::new(config, executor.clone())?;
+                start_offchain_workers(&service, &executor);
+                if let Some(reporter) = service.config.custom.equivocation_reporter.clone() {
+                    start_equivocation_reporter(&service, &executor, reporter);
+                }
+                Ok(service)
+            }},
         AuthoritySetup = {
             |service: Self::FullService, executor: TaskExecutor, key: Option<Arc<Pair>>| {
-                if let Some(key) = key {
+                if let Some(sealing) = service.config.custom.sealing {
+                    info!("Aura authoring disabled, blocks are sealed {:?}", sealing);
+                } else if let Some(key) = authority_key(&service, key) {
                     info!("Using authority key {}", key.public());
                     let proposer = Arc::new(ProposerFactory {
                         client: service.client(),
@@ -64,11 +92,14 @@ construct_service_factory! {
                         inherents_pool: service.inherents_pool(),
                     });
                     let client = service.client();
+                    let slot_duration = SlotDuration::get_or_compute(&*client)?;
+                    start_key_refresh(key.clone(), Duration::from_secs(slot_duration.get()))?;
+                    let block_import = Arc::new(SealCheck::new(client.clone(), key.clone()));
                     executor.spawn(start_aura(
-                        SlotDuration::get_or_compute(&*client)?,
+                        slot_duration,
                         key.clone(),
-                        client.clone(),
                         client,
+                        Arc::new(service.config.custom.block_import_time.wrap(block_import)),
                         proposer,
                         service.network(),
                         service.on_exit(),
@@ -88,7 +119,7 @@ construct_service_factory! {
             { |config: &mut FactoryFullConfiguration<Self> , client: Arc<FullClient<Self>>| {
                     import_queue::<_, _, _, Pair>(
                         SlotDuration::get_or_compute(&*client)?,
-                        client.clone(),
+                        Arc::new(config.custom.block_import_time.wrap(client.clone())),
                         None,
                         client,
                         NothingExtra,
@@ -112,3 +143,82 @@ construct_service_factory! {
             },
     }
 }
+
+/// The key to author with: the remote signer's if configured, otherwise the keystore key in
+/// the authority set, falling back to the keystore key `key` the service was started with.
+fn authority_key(
+    service: &FullComponents<Factory>,
+    key: Option<Arc<Pair>>,
+) -> Option<Arc<AuthorityPair>> {
+    let custom = &service.config.custom;
+    if let Some(signer) = custom.remote_signer.clone() {
+        return Some(Arc::new(AuthorityPair::Remote(signer)));
+    }
+    key.map(|key| {
+        Arc::new(AuthorityPair::Keystore(KeystoreKeys::new(
+            service.client(),
+            service.config.keystore_path.clone().into(),
+            custom.keystore_password.clone(),
+            (*key).clone(),
+        )))
+    })
+}
+
+/// Refresh `key` once per slot of `slot_duration` on its own thread, as it may block on I/O.
+fn start_key_refresh(key: Arc<AuthorityPair>, slot_duration: Duration) -> Result<(), String> {
+    key.refresh();
+    thread::Builder::new()
+        .name("authority-key".into())
+        .spawn(move || loop {
+            thread::sleep(slot_duration);
+            key.refresh();
+        })
+        .map(|_| ())
+        .map_err(|e| format!("{:?}", e))
+}
+
+/// Run the runtime's off-chain workers on every block imported by `service`, and the node's
+/// price feed if configured.
+fn start_offchain_workers(service: &FullComponents<Factory>, executor: &TaskExecutor) {
+    if let Some(config) = service.config.custom.price_feed.clone() {
+        price_feed::start(service, executor, config);
+    }
+
+    let offchain = Arc::new(offchain::OffchainWorkers::new(service.client(), executor.clone()));
+    let transaction_pool = service.transaction_pool();
+    let client: Arc<ComponentClient<FullComponents<Factory>>> = service.client();
+
+    let on_import = client
+        .import_notification_stream()
+        .for_each(move |notification| {
+            offchain.on_block_imported(notification.header.number(), &transaction_pool);
+            Ok(())
+        })
+        .select(service.on_exit())
+        .then(|_| Ok(()));
+
+    executor.spawn(on_import);
+}
+
+/// Report equivocations in blocks imported by `service`, signed by `reporter`.
+fn start_equivocation_reporter(
+    service: &FullComponents<Factory>,
+    executor: &TaskExecutor,
+    reporter: Arc<sr25519::Pair>,
+) {
+    info!("Reporting equivocations as {}", reporter.public());
+    let client: Arc<ComponentClient<FullComponents<Factory>>> = service.client();
+    let mut equivocations =
+        EquivocationReporter::new(client.clone(), service.transaction_pool(), reporter);
+
+    let on_import = client
+        .import_notification_stream()
+        .for_each(move |notification| {
+            equivocations.on_block_imported(&notification.header);
+            Ok(())
+        })
+        .select(service.on_exit())
+        .then(|_| Ok(()));
+
+    executor.spawn(on_import);
+}
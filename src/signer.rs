@@ -0,0 +1,479 @@
+//! Authority keys signing locally, with the keystore's current authority key, or through a
+//! remote signing service.
+//!
+//! The remote signer is reached over a Unix socket (`unix:<path>`) or TCP (`<host>:<port>`),
+//! one connection per signature. A request is the line `<public> <payload>` and the response
+//! the line `<signature>`, all hex encoded. The signer is checked once per slot, and while it
+//! fails to sign the node claims no slots. Should it fail to seal a claimed slot, the block is
+//! rejected by `SealCheck` before import and the slot skipped. `key serve-signer` runs an
+//! unauthenticated stand-in service for testing.
+
+use crate::keystore;
+use crate::service::Factory;
+use cennznet_runtime_template_runtime::{opaque::Block, Hash};
+use consensus_common::well_known_cache_keys::Id as CacheKeyId;
+use consensus_common::{
+    BlockImport, BlockOrigin, Error as ConsensusError, ErrorKind as ConsensusErrorKind, ImportBlock,
+    ImportResult,
+};
+use log::{info, warn};
+use parking_lot::{Mutex, RwLock};
+use primitives::crypto::{DeriveJunction, SecretStringError};
+use primitives::{ed25519, hexdisplay::HexDisplay, Pair};
+use runtime_primitives::generic::{BlockId, DigestItem};
+use runtime_primitives::traits::Header as HeaderT;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use substrate_service::FullClient;
+
+/// Timeout of each request to the remote signer.
+const SIGN_TIMEOUT: Duration = Duration::from_secs(2);
+/// The message the remote signer signs to show it is available.
+const HEALTH_CHECK_MESSAGE: &[u8] = b"cennznet remote signer health check";
+
+/// Where a signing service listens.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Endpoint {
+    /// A Unix socket path
+    #[cfg(unix)]
+    Unix(PathBuf),
+    /// A TCP address
+    Tcp(String),
+}
+
+impl FromStr for Endpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with("unix:") {
+            #[cfg(unix)]
+            return Ok(Endpoint::Unix(PathBuf::from(&s["unix:".len()..])));
+            #[cfg(not(unix))]
+            return Err("Unix sockets are not supported on this platform".into());
+        } else if s.contains(':') {
+            Ok(Endpoint::Tcp(s.to_string()))
+        } else {
+            Err(format!("Expected unix:<path> or <host>:<port>, got {}", s))
+        }
+    }
+}
+
+trait Stream: Read + Write {}
+impl<T: Read + Write> Stream for T {}
+
+impl Endpoint {
+    fn connect(&self) -> std::io::Result<Box<dyn Stream>> {
+        Ok(match self {
+            #[cfg(unix)]
+            Endpoint::Unix(path) => {
+                let stream = UnixStream::connect(path)?;
+                stream.set_read_timeout(Some(SIGN_TIMEOUT))?;
+                stream.set_write_timeout(Some(SIGN_TIMEOUT))?;
+                Box::new(stream)
+            }
+            Endpoint::Tcp(addr) => {
+                let stream = TcpStream::connect(addr)?;
+                stream.set_read_timeout(Some(SIGN_TIMEOUT))?;
+                stream.set_write_timeout(Some(SIGN_TIMEOUT))?;
+                Box::new(stream)
+            }
+        })
+    }
+}
+
+/// An ed25519 key held by a remote signing service.
+#[derive(Debug, Clone)]
+pub struct RemoteSigner {
+    endpoint: Endpoint,
+    public: ed25519::Public,
+    /// Whether the last health check got a valid signature
+    available: Arc<AtomicBool>,
+}
+
+impl RemoteSigner {
+    /// Sign with the key `public` held by the service at `endpoint`.
+    pub fn new(endpoint: Endpoint, public: ed25519::Public) -> Self {
+        RemoteSigner { endpoint, public, available: Arc::new(AtomicBool::new(false)) }
+    }
+
+    /// Check the signer returns valid signatures, so slots are only claimed while it does.
+    pub fn check(&self) {
+        let available = match self.request(HEALTH_CHECK_MESSAGE) {
+            Ok(_) => true,
+            Err(e) => {
+                warn!("Remote signer at {:?} is unavailable, skipping slots: {}", self.endpoint, e);
+                false
+            }
+        };
+        if available && !self.available.swap(available, Ordering::SeqCst) {
+            info!("Remote signer at {:?} is available", self.endpoint);
+        } else if !available {
+            self.available.store(false, Ordering::SeqCst);
+        }
+    }
+
+    fn request(&self, message: &[u8]) -> Result<ed25519::Signature, String> {
+        let mut stream = self.endpoint.connect().map_err(|e| format!("{:?}", e))?;
+        writeln!(stream, "{} {}", HexDisplay::from(&self.public.0), HexDisplay::from(&message))
+            .map_err(|e| format!("{:?}", e))?;
+        let mut response = String::new();
+        BufReader::new(stream)
+            .read_line(&mut response)
+            .map_err(|e| format!("{:?}", e))?;
+
+        let bytes = from_hex(response.trim()).ok_or_else(|| format!("Invalid response: {}", response.trim()))?;
+        if bytes.len() != 64 {
+            return Err(format!("Invalid signature length {}", bytes.len()));
+        }
+        let mut signature = [0u8; 64];
+        signature.copy_from_slice(&bytes);
+        let signature = ed25519::Signature::from_raw(signature);
+        if !ed25519::Pair::verify(&signature, message, &self.public) {
+            return Err("Invalid signature".into());
+        }
+        Ok(signature)
+    }
+
+    /// The key to claim slots with: the signer's key while it is available, otherwise one in no
+    /// authority set, so no slot is claimed that could not be signed.
+    fn slot_key(&self) -> ed25519::Public {
+        if self.available.load(Ordering::SeqCst) {
+            self.public.clone()
+        } else {
+            ed25519::Public::default()
+        }
+    }
+
+    /// Sign `message` for a claimed slot. Slots are only claimed after a successful health
+    /// check, so this fails only if the signer went down within the slot. Signing cannot fail
+    /// here, so an invalid signature is returned, which `SealCheck` rejects before import.
+    fn sign(&self, message: &[u8]) -> ed25519::Signature {
+        self.request(message).unwrap_or_else(|e| {
+            self.available.store(false, Ordering::SeqCst);
+            warn!("Remote signer at {:?} failed to seal a claimed slot, skipping it: {}", self.endpoint, e);
+            ed25519::Signature::from_raw([0; 64])
+        })
+    }
+}
+
+/// The keys of the node's keystore, of which the one in the on-chain authority set is used.
+///
+/// The keystore and authority set are checked by the per-slot refresh, so a rotated key becomes
+/// active once the authority set includes it without a restart. Looking up the key is cheap.
+/// Only the Aura key is rotated this way, as the node runs no GRANDPA voter.
+pub struct KeystoreKeys {
+    /// Reads the authority set at the best block
+    authorities: Box<dyn Fn() -> Vec<ed25519::Public> + Send + Sync>,
+    path: PathBuf,
+    password: Option<String>,
+    /// The key the node was started with, used while no keystore key is an authority
+    fallback: ed25519::Pair,
+    loaded: Mutex<HashMap<PathBuf, Option<ed25519::Pair>>>,
+    active: RwLock<ed25519::Pair>,
+}
+
+impl KeystoreKeys {
+    /// Author with the keys in the keystore at `path`, decrypting encrypted keys with
+    /// `password` if given, or with `fallback` while none is an authority at the best block.
+    pub fn new(
+        client: Arc<FullClient<Factory>>,
+        path: PathBuf,
+        password: Option<String>,
+        fallback: ed25519::Pair,
+    ) -> Self {
+        let authorities = move || {
+            client
+                .info()
+                .and_then(|info| client.authorities_at(&BlockId::hash(info.chain.best_hash)))
+                .unwrap_or_else(|e| {
+                    warn!("Failed to read the authority set: {:?}", e);
+                    Vec::new()
+                })
+        };
+        Self::with_authorities(path, password, fallback, authorities)
+    }
+
+    /// Author with the keys in the keystore at `path`, selecting the one in the authority set
+    /// read by `authorities`.
+    fn with_authorities<F>(path: PathBuf, password: Option<String>, fallback: ed25519::Pair, authorities: F) -> Self
+    where
+        F: Fn() -> Vec<ed25519::Public> + Send + Sync + 'static,
+    {
+        KeystoreKeys {
+            authorities: Box::new(authorities),
+            path,
+            password,
+            active: RwLock::new(fallback.clone()),
+            fallback,
+            loaded: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Select the key in the authority set at the best block, reloading the keystore.
+    fn refresh(&self) {
+        let authorities = (self.authorities)();
+        let mut loaded = self.loaded.lock();
+        keystore::load(&self.path, self.password.as_ref().map(String::as_str), &mut loaded);
+        let selected = loaded
+            .values()
+            .filter_map(Option::as_ref)
+            .chain(Some(&self.fallback))
+            .find(|pair| authorities.contains(&pair.public()))
+            .unwrap_or(&self.fallback);
+
+        let public = selected.public();
+        let mut active = self.active.write();
+        if active.public() != public {
+            info!("Switching authority key to {}", public);
+            *active = selected.clone();
+        }
+    }
+}
+
+/// The error deriving an authority key.
+#[derive(Debug)]
+pub enum DeriveError {
+    /// Deriving the local key failed
+    Local(<ed25519::Pair as Pair>::DeriveError),
+    /// Only local keys can be derived
+    NotLocal,
+}
+
+/// The authority key used for authoring, signing locally or remotely.
+pub enum AuthorityPair {
+    /// A key held by the node
+    Local(ed25519::Pair),
+    /// A key held by a remote signing service
+    Remote(RemoteSigner),
+    /// The keystore key in the authority set, looked up each slot
+    Keystore(KeystoreKeys),
+}
+
+impl AuthorityPair {
+    /// Prepare the key for the next slot: check the remote signer is available, or select the
+    /// keystore key in the authority set.
+    pub fn refresh(&self) {
+        match self {
+            AuthorityPair::Local(_) => (),
+            AuthorityPair::Remote(signer) => signer.check(),
+            AuthorityPair::Keystore(keys) => keys.refresh(),
+        }
+    }
+}
+
+impl Pair for AuthorityPair {
+    type Public = ed25519::Public;
+    type Seed = <ed25519::Pair as Pair>::Seed;
+    type Signature = ed25519::Signature;
+    type DeriveError = DeriveError;
+
+    fn generate() -> Self {
+        AuthorityPair::Local(ed25519::Pair::generate())
+    }
+
+    fn generate_with_phrase(password: Option<&str>) -> (Self, String) {
+        let (pair, phrase) = ed25519::Pair::generate_with_phrase(password);
+        (AuthorityPair::Local(pair), phrase)
+    }
+
+    fn from_phrase(phrase: &str, password: Option<&str>) -> Result<Self, SecretStringError> {
+        ed25519::Pair::from_phrase(phrase, password).map(AuthorityPair::Local)
+    }
+
+    fn derive<Iter: Iterator<Item = DeriveJunction>>(&self, path: Iter) -> Result<Self, Self::DeriveError> {
+        match self {
+            AuthorityPair::Local(pair) => pair
+                .derive(path)
+                .map(AuthorityPair::Local)
+                .map_err(DeriveError::Local),
+            AuthorityPair::Remote(_) | AuthorityPair::Keystore(_) => Err(DeriveError::NotLocal),
+        }
+    }
+
+    fn from_seed(seed: Self::Seed) -> Self {
+        AuthorityPair::Local(ed25519::Pair::from_seed(seed))
+    }
+
+    fn from_seed_slice(seed: &[u8]) -> Result<Self, SecretStringError> {
+        ed25519::Pair::from_seed_slice(seed).map(AuthorityPair::Local)
+    }
+
+    fn from_standard_components<I: Iterator<Item = DeriveJunction>>(
+        seed: &str,
+        password: Option<&str>,
+        path: I,
+    ) -> Result<Self, SecretStringError> {
+        ed25519::Pair::from_standard_components(seed, password, path).map(AuthorityPair::Local)
+    }
+
+    fn sign(&self, message: &[u8]) -> Self::Signature {
+        match self {
+            AuthorityPair::Local(pair) => pair.sign(message),
+            AuthorityPair::Remote(signer) => signer.sign(message),
+            AuthorityPair::Keystore(keys) => keys.active.read().sign(message),
+        }
+    }
+
+    fn verify<P: AsRef<Self::Public>, M: AsRef<[u8]>>(sig: &Self::Signature, message: M, pubkey: P) -> bool {
+        ed25519::Pair::verify(sig, message, pubkey)
+    }
+
+    fn verify_weak<P: AsRef<[u8]>, M: AsRef<[u8]>>(sig: &[u8], message: M, pubkey: P) -> bool {
+        ed25519::Pair::verify_weak(sig, message, pubkey)
+    }
+
+    fn public(&self) -> Self::Public {
+        match self {
+            AuthorityPair::Local(pair) => pair.public(),
+            AuthorityPair::Remote(signer) => signer.slot_key(),
+            AuthorityPair::Keystore(keys) => keys.active.read().public(),
+        }
+    }
+}
+
+/// Imports blocks through `inner`, rejecting blocks authored by the node whose seal the
+/// remote signer failed to sign, so the slot is skipped instead of an invalid block authored.
+pub struct SealCheck<I> {
+    inner: Arc<I>,
+    key: Arc<AuthorityPair>,
+}
+
+impl<I> SealCheck<I> {
+    /// Check the seals of blocks authored with `key` before importing them into `inner`.
+    pub fn new(inner: Arc<I>, key: Arc<AuthorityPair>) -> Self {
+        SealCheck { inner, key }
+    }
+}
+
+impl<I: BlockImport<Block, Error = ConsensusError>> BlockImport<Block> for SealCheck<I> {
+    type Error = ConsensusError;
+
+    fn check_block(&self, hash: Hash, parent_hash: Hash) -> Result<ImportResult, Self::Error> {
+        self.inner.check_block(hash, parent_hash)
+    }
+
+    fn import_block(
+        &self,
+        block: ImportBlock<Block>,
+        new_cache: HashMap<CacheKeyId, Vec<u8>>,
+    ) -> Result<ImportResult, Self::Error> {
+        if let (AuthorityPair::Remote(signer), BlockOrigin::Own) = (&*self.key, block.origin) {
+            let pre_hash = block.header.hash();
+            let sealed = block.post_digests.iter().all(|item| match item {
+                DigestItem::Seal(_, signature) => ed25519::Pair::verify(signature, pre_hash.as_ref(), &signer.public),
+                _ => true,
+            });
+            if !sealed {
+                return Err(ConsensusErrorKind::ClientImport("Remote signer failed to seal the block".into()).into());
+            }
+        }
+        self.inner.import_block(block, new_cache)
+    }
+}
+
+/// Serve signatures by `pair` on `endpoint` until the process exits, as a stand-in for a
+/// remote signing service. For testing only: anyone able to connect can have any message
+/// signed, so TCP endpoints must be on the loopback interface.
+pub fn serve(endpoint: &Endpoint, pair: ed25519::Pair) -> Result<(), String> {
+    if let Endpoint::Tcp(addr) = endpoint {
+        let mut addrs = addr.to_socket_addrs().map_err(|e| format!("{:?}", e))?;
+        if !addrs.all(|addr| addr.ip().is_loopback()) {
+            return Err(format!("The stand-in signer only listens on localhost, not {}", addr));
+        }
+    }
+    println!("Signing with {} on {:?}", pair.public(), endpoint);
+    let handle = |stream: &mut dyn Stream| -> Result<(), String> {
+        let mut request = String::new();
+        BufReader::new(&mut *stream)
+            .read_line(&mut request)
+            .map_err(|e| format!("{:?}", e))?;
+        let mut parts = request.trim().split(' ');
+        let (public, payload) = match (parts.next().and_then(from_hex), parts.next().and_then(from_hex)) {
+            (Some(public), Some(payload)) => (public, payload),
+            _ => return Err(format!("Invalid request: {}", request.trim())),
+        };
+        if public[..] != pair.public().0[..] {
+            return Err(format!("Unknown key 0x{}", HexDisplay::from(&public)));
+        }
+        writeln!(stream, "{}", HexDisplay::from(&pair.sign(&payload).0)).map_err(|e| format!("{:?}", e))
+    };
+
+    let serve_connection = |stream: std::io::Result<Box<dyn Stream>>| {
+        if let Err(e) = stream.map_err(|e| format!("{:?}", e)).and_then(|mut s| handle(&mut *s)) {
+            warn!("Signing request failed: {}", e);
+        }
+    };
+    match endpoint {
+        #[cfg(unix)]
+        Endpoint::Unix(path) => {
+            let listener = UnixListener::bind(path).map_err(|e| format!("{:?}", e))?;
+            listener
+                .incoming()
+                .for_each(|s| serve_connection(s.map(|s| Box::new(s) as Box<dyn Stream>)));
+        }
+        Endpoint::Tcp(addr) => {
+            let listener = TcpListener::bind(addr).map_err(|e| format!("{:?}", e))?;
+            listener
+                .incoming()
+                .for_each(|s| serve_connection(s.map(|s| Box::new(s) as Box<dyn Stream>)));
+        }
+    }
+    Ok(())
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    let s = if s.starts_with("0x") { &s[2..] } else { s };
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn rotated_keystore_key_becomes_public_after_refresh() {
+        let path = std::env::temp_dir().join(format!("cennznet-keystore-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        let first = ed25519::Pair::from_seed_slice(&[1; 32]).unwrap();
+        let second = ed25519::Pair::from_seed_slice(&[2; 32]).unwrap();
+        let fallback = ed25519::Pair::from_seed_slice(&[3; 32]).unwrap();
+        keystore::insert(&path, &first, "password").unwrap();
+
+        let authorities = Arc::new(Mutex::new(vec![first.public()]));
+        let keys = {
+            let authorities = authorities.clone();
+            KeystoreKeys::with_authorities(path.clone(), Some("password".into()), fallback.clone(), move || {
+                authorities.lock().clone()
+            })
+        };
+        let pair = AuthorityPair::Keystore(keys);
+        assert_eq!(pair.public(), fallback.public());
+
+        pair.refresh();
+        assert_eq!(pair.public(), first.public());
+
+        // The session key is rotated: the new key is inserted and becomes the authority
+        keystore::insert(&path, &second, "password").unwrap();
+        *authorities.lock() = vec![second.public()];
+        assert_eq!(pair.public(), first.public());
+        pair.refresh();
+        assert_eq!(pair.public(), second.public());
+
+        fs::remove_dir_all(&path).unwrap();
+    }
+}